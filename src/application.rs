@@ -1,22 +1,48 @@
 use std::fmt::Display;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::bvh::Bvh;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::cli::Args;
+use crate::clock::Clock;
 use crate::cornell_box as cbox;
 use crate::egui_wgpu::EguiWgpu;
-use crate::functions::PredefinedFunction;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::export;
+use crate::functions::{
+    default_field_function, FieldMode, PredefinedFunction, Presets, CUSTOM_FUNCTION,
+    DEFAULT_FUNCTION,
+};
+use crate::integrator::{IntegrationScheme, IntegratorSettings};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::library::{Library, LibraryFunction};
 use crate::main_view::{MainView, Settings};
+use crate::parameters;
 use crate::reference_view::ReferenceView;
+use crate::session::{model_mesh, Model, Session, SESSION_VERSION};
 use crate::syntax_highlighting::code_view_ui;
+use crate::timeline::{Interpolation, Keyframe, Timeline};
+use crate::validation::{Diagnostic, FieldFunctionError, Snippet};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::vertices::Normalization;
 use crate::vertices::{get_center, normalize_vertices};
-use anyhow::{Context, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::volume::{Outside, VectorFieldOptions, Volume, VolumeSpace};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
 use winit::window::Window;
 
 pub const INITIAL_SIDEBAR_WIDTH: f32 = 500.0;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum OverlayMode {
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum OverlayMode {
+    #[serde(rename = "disabled")]
     Disabled = 0,
+    #[serde(rename = "lyapunov")]
     LyapunovExponents = 1,
 }
 
@@ -30,6 +56,86 @@ impl Display for OverlayMode {
     }
 }
 
+impl FromStr for OverlayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(Self::Disabled),
+            "lyapunov" => Ok(Self::LyapunovExponents),
+            _ => Err("possible values: disabled, lyapunov".to_string()),
+        }
+    }
+}
+
+/// Color of rays that leave the scene without hitting a surface.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Background {
+    #[default]
+    #[serde(rename = "black")]
+    Black = 0,
+    #[serde(rename = "checkerboard")]
+    Checkerboard = 1,
+    #[serde(rename = "stars")]
+    Stars = 2,
+}
+
+impl Background {
+    pub const ALL: [Background; 3] = [Self::Black, Self::Checkerboard, Self::Stars];
+}
+
+impl Display for Background {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::Black => "Black",
+            Self::Checkerboard => "Checkerboard",
+            Self::Stars => "Stars",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "black" => Ok(Self::Black),
+            "checkerboard" => Ok(Self::Checkerboard),
+            "stars" => Ok(Self::Stars),
+            _ => Err("possible values: black, checkerboard, stars".to_string()),
+        }
+    }
+}
+
+/// Actions of the timeline panel, applied after the UI has been laid out.
+enum TimelineAction {
+    Add,
+    GoTo(f32),
+    Remove(usize),
+    #[cfg(not(target_arch = "wasm32"))]
+    RenderSequence,
+}
+
+/// View saved by the image panel.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy)]
+enum ImageAction {
+    SaveMainView,
+    SaveReferenceView,
+    ExportRayData,
+}
+
+/// Actions of the function library panel, applied after the UI has been laid out.
+#[cfg(not(target_arch = "wasm32"))]
+enum LibraryAction {
+    Load(String),
+    Save,
+    Rename,
+    Duplicate,
+    Delete,
+}
+
 pub struct Application {
     _instance: wgpu::Instance,
     surface_config: wgpu::SurfaceConfiguration,
@@ -41,19 +147,90 @@ pub struct Application {
     reference_view: ReferenceView,
     vertices_buffer: wgpu::Buffer,
     faces_buffer: wgpu::Buffer,
+    bvh_buffer: wgpu::Buffer,
     indices: u32,
     ray_samples_buffer: wgpu::Buffer,
+    /// The loaded model before normalization, `None` for the Cornell box
+    model: Option<Model>,
     // egui
     egui_wgpu: EguiWgpu,
     // gui state
     shader_error: Option<String>,
+    /// Errors in the field function, shown in the editor
+    diagnostics: Vec<Diagnostic>,
     field_weight: f32,
     mouse_pos: [f32; 2],
     overlay_mode: OverlayMode,
+    background: Background,
     central_difference_delta: i32,
     lyapunov_scaling: f32,
-    predefined_function: PredefinedFunction,
+    presets: Presets,
+    /// Identifier of the selected predefined function, `None` for other functions
+    predefined_function: Option<String>,
+    /// Helper functions, constants and structs inserted before the field function
+    prelude: String,
     field_function: String,
+    /// What the field function computes
+    field_mode: FieldMode,
+    integrator: IntegratorSettings,
+    clock: Clock,
+    timeline: Timeline,
+    /// Directory the frames of the timeline are written to
+    #[cfg(not(target_arch = "wasm32"))]
+    sequence_dir: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    sequence_size: [u32; 2],
+    #[cfg(not(target_arch = "wasm32"))]
+    sequence_fps: u32,
+    #[cfg(not(target_arch = "wasm32"))]
+    sequence_status: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    image_path: String,
+    /// Whether saved images of the main view include the overlay
+    #[cfg(not(target_arch = "wasm32"))]
+    image_overlay: bool,
+    /// Size of saved images, `None` to use the size of the view
+    #[cfg(not(target_arch = "wasm32"))]
+    image_size: Option<[u32; 2]>,
+    #[cfg(not(target_arch = "wasm32"))]
+    image_status: Option<String>,
+    /// File the per-pixel results of the main view are exported to, `.exr` or `.npy`
+    #[cfg(not(target_arch = "wasm32"))]
+    ray_data_path: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    session_path: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    session_status: Option<String>,
+    /// File of the volumetric scalar field, `None` if no field is loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    volume: Option<PathBuf>,
+    #[cfg(not(target_arch = "wasm32"))]
+    volume_path: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    volume_status: Option<String>,
+    /// File of the vector field, `None` if no field is loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    vector_field: Option<PathBuf>,
+    #[cfg(not(target_arch = "wasm32"))]
+    vector_field_path: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    vector_field_status: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    vector_field_options: VectorFieldOptions,
+    /// Mapping of the loaded model into the scene, used for vector fields in model coordinates
+    #[cfg(not(target_arch = "wasm32"))]
+    normalization: Normalization,
+    #[cfg(not(target_arch = "wasm32"))]
+    library: Library,
+    /// Library function shown in the editor, `None` for predefined and unsaved functions
+    #[cfg(not(target_arch = "wasm32"))]
+    library_function: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    library_name: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    library_description: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    library_status: Option<String>,
 }
 
 impl Application {
@@ -102,24 +279,25 @@ impl Application {
         };
         surface.configure(&device, &surface_config);
 
-        let mut vertices = cbox::VERTICES;
-        for i in 0..(vertices.len() / 3) {
-            // Invert x and z axis
-            vertices[3 * i] = -vertices[3 * i];
-            vertices[3 * i + 2] = -vertices[3 * i + 2];
-        }
-        normalize_vertices(&mut vertices);
+        let mut vertices = cbox::inverted_vertices();
+        #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+        let normalization = normalize_vertices(&mut vertices);
         let vertices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("vertices_buffer"),
             contents: bytemuck::cast_slice(&vertices),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
         });
-        let indices = cbox::INDICES;
+        let bvh = Bvh::build(&vertices, &cbox::INDICES);
         let faces_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("faces_buffer"),
-            contents: bytemuck::cast_slice(&indices),
+            contents: bytemuck::cast_slice(&bvh.indices),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDEX,
         });
+        let bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("bvh_buffer"),
+            contents: bytemuck::cast_slice(&bvh.nodes),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
         let center = get_center(&vertices);
 
         let ray_samples_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -131,16 +309,27 @@ impl Application {
 
         let mut egui_wgpu = EguiWgpu::new(window.as_ref(), &device, surface_format);
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let presets = Presets::with_user_presets().unwrap_or_else(|e| {
+            println!("Loading presets failed: {:#}", e);
+            Presets::builtin()
+        });
+        #[cfg(target_arch = "wasm32")]
+        let presets = Presets::builtin();
+        // The function the main view is created with
+        let field_function = default_field_function();
+
         let main_view = MainView::new(
-            &mut egui_wgpu.renderer,
             &device,
             vertices_buffer.as_entire_binding(),
             faces_buffer.as_entire_binding(),
+            bvh_buffer.as_entire_binding(),
             center,
             ray_samples_buffer.as_entire_binding(),
             size.width - INITIAL_SIDEBAR_WIDTH as u32,
             size.height,
-            discrete_gpu,
+            if discrete_gpu { 1 } else { 2 },
+            adapter.get_info().backend,
         );
         let reference_view = ReferenceView::new(
             &mut egui_wgpu.renderer,
@@ -161,19 +350,77 @@ impl Application {
             reference_view,
             vertices_buffer,
             faces_buffer,
-            indices: indices.len() as u32,
+            bvh_buffer,
+            indices: bvh.indices.len() as u32,
             ray_samples_buffer,
+            model: None,
             // egui
             egui_wgpu,
             // gui state
             shader_error: None,
+            diagnostics: Vec::new(),
             field_weight: 1.0,
             mouse_pos: [0.5, 0.5],
             overlay_mode: OverlayMode::Disabled,
+            background: Background::Black,
             central_difference_delta: 1,
             lyapunov_scaling: 50.0,
-            predefined_function: PredefinedFunction::MirageSphericalSigmoid,
-            field_function: PredefinedFunction::MirageSphericalSigmoid.to_code(),
+            presets,
+            predefined_function: Some(DEFAULT_FUNCTION.to_string()),
+            prelude: String::new(),
+            field_function,
+            field_mode: FieldMode::Velocity,
+            integrator: IntegratorSettings::default(),
+            clock: Clock::default(),
+            timeline: Timeline::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_dir: "frames".to_string(),
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_size: [1280, 720],
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_fps: 30,
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_status: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_path: "linon.png".to_string(),
+            #[cfg(not(target_arch = "wasm32"))]
+            image_overlay: true,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_size: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_status: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            ray_data_path: "rays.exr".to_string(),
+            #[cfg(not(target_arch = "wasm32"))]
+            session_path: "session.json".to_string(),
+            #[cfg(not(target_arch = "wasm32"))]
+            session_status: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            volume: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            volume_path: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            volume_status: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_path: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_status: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_options: VectorFieldOptions::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            normalization,
+            #[cfg(not(target_arch = "wasm32"))]
+            library: Library::open(Library::default_dir()),
+            #[cfg(not(target_arch = "wasm32"))]
+            library_function: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            library_name: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            library_description: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            library_status: None,
         })
     }
 
@@ -191,13 +438,28 @@ impl Application {
         self.surface.configure(&self.device, &self.surface_config);
     }
 
+    /// Loads the built-in Cornell box, which is not stored in sessions.
     pub fn load_default_model(&mut self) {
-        let mut vertices = cbox::VERTICES;
-        self.load_model(&mut vertices, &cbox::INDICES);
+        let (mut vertices, indices) = model_mesh(None);
+        self.upload_model(&mut vertices, &indices);
+        self.model = None;
     }
 
-    pub fn load_model(&mut self, vertices: &mut [f32], indices: &[u32]) {
-        normalize_vertices(vertices);
+    pub fn load_model(&mut self, model: Model) {
+        let mut vertices = model.vertices.clone();
+        self.upload_model(&mut vertices, &model.indices);
+        self.model = Some(model);
+    }
+
+    fn upload_model(&mut self, vertices: &mut [f32], indices: &[u32]) {
+        #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+        let normalization = normalize_vertices(vertices);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.normalization = normalization;
+            self.update_vector_field_mapping();
+        }
+        let bvh = Bvh::build(vertices, indices);
 
         self.vertices_buffer = self
             .device
@@ -210,13 +472,28 @@ impl Application {
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("faces_buffer"),
-                contents: bytemuck::cast_slice(indices),
+                contents: bytemuck::cast_slice(&bvh.indices),
                 usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDEX,
             });
-        self.indices = indices.len() as u32;
+        self.bvh_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("bvh_buffer"),
+                contents: bytemuck::cast_slice(&bvh.nodes),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        self.indices = bvh.indices.len() as u32;
         let center = get_center(vertices);
 
         self.main_view.update_model(
+            &self.device,
+            &self.queue,
+            self.vertices_buffer.as_entire_binding(),
+            self.faces_buffer.as_entire_binding(),
+            self.bvh_buffer.as_entire_binding(),
+            center,
+        );
+        self.reference_view.update_model(
             &self.device,
             &self.queue,
             self.vertices_buffer.as_entire_binding(),
@@ -225,10 +502,496 @@ impl Application {
         );
     }
 
+    /// Captures everything needed to reproduce the current exploration.
+    pub fn session(&self) -> Session {
+        Session {
+            version: SESSION_VERSION,
+            predefined_function: self
+                .predefined_function
+                .clone()
+                .unwrap_or_else(|| CUSTOM_FUNCTION.to_string()),
+            prelude: self.prelude.clone(),
+            field_function: self.field_function.clone(),
+            field_mode: self.field_mode,
+            field_weight: self.field_weight,
+            overlay_mode: self.overlay_mode,
+            background: self.background,
+            central_difference_delta: self.central_difference_delta,
+            lyapunov_scaling: self.lyapunov_scaling,
+            mouse_pos: self.mouse_pos,
+            integrator: self.integrator,
+            clock: self.clock,
+            timeline: self.timeline.clone(),
+            parameters: parameters::values(self.main_view.parameters()),
+            #[cfg(not(target_arch = "wasm32"))]
+            volume: self.volume.clone(),
+            #[cfg(target_arch = "wasm32")]
+            volume: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field: self.vector_field.clone(),
+            #[cfg(target_arch = "wasm32")]
+            vector_field: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_options: self.vector_field_options,
+            #[cfg(target_arch = "wasm32")]
+            vector_field_options: Default::default(),
+            model: self.model.clone(),
+            main_camera: self.main_view.camera_pose(),
+            reference_camera: self.reference_view.camera_pose(),
+        }
+    }
+
+    /// Restores a session previously captured by [`Application::session`].
+    /// Fails with all parts of the session that could not be restored.
+    pub fn open_session(&mut self, session: Session) -> Result<()> {
+        #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+        let mut errors = Vec::new();
+        let (mut vertices, indices) = model_mesh(session.model.as_ref());
+        self.upload_model(&mut vertices, &indices);
+        self.model = session.model;
+        self.predefined_function =
+            Some(session.predefined_function).filter(|id| self.presets.get(id).is_some());
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.library_function = None;
+            match &session.volume {
+                Some(path) => {
+                    if let Err(e) = self.load_volume(path) {
+                        self.volume_status = Some(format!("Loading volume failed: {:#}", e));
+                        errors.push(e);
+                    }
+                }
+                None => self.clear_volume(),
+            }
+            self.vector_field_options = session.vector_field_options;
+            match &session.vector_field {
+                Some(path) => {
+                    if let Err(e) = self.load_vector_field(path) {
+                        self.vector_field_status =
+                            Some(format!("Loading vector field failed: {:#}", e));
+                        errors.push(e);
+                    }
+                }
+                None => self.clear_vector_field(),
+            }
+        }
+        self.prelude = session.prelude;
+        self.field_function = session.field_function;
+        self.field_mode = session.field_mode;
+        let result = self.main_view.reload_shader(
+            &self.device,
+            &self.queue,
+            None,
+            self.prelude.clone(),
+            self.field_function.clone(),
+            self.field_mode,
+        );
+        self.show_shader_result(&result);
+        self.main_view
+            .set_parameter_values(&self.queue, &session.parameters);
+        self.field_weight = session.field_weight;
+        self.overlay_mode = session.overlay_mode;
+        self.background = session.background;
+        self.central_difference_delta = session.central_difference_delta;
+        self.lyapunov_scaling = session.lyapunov_scaling;
+        self.mouse_pos = session.mouse_pos;
+        self.integrator = session.integrator;
+        self.clock = session.clock;
+        self.timeline = session.timeline;
+        self.update_settings();
+        self.main_view
+            .set_camera_pose(&self.queue, session.main_camera);
+        self.reference_view
+            .set_camera_pose(&self.queue, session.reference_camera);
+        if let Err(e) = result {
+            errors.insert(0, e.context("compiling field function failed"));
+        }
+        combine_errors(errors)
+    }
+
+    /// Applies the scene, function, settings and camera given on the command line.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn apply_args(&mut self, args: &Args) -> Result<()> {
+        // Options that fail are reported together, the others are still applied
+        let mut errors = Vec::new();
+        if args.library.is_some() {
+            self.library = args.library();
+        }
+        match args.presets() {
+            Ok(presets) => self.presets = presets,
+            Err(e) => errors.push(e),
+        }
+        let resolved = args.resolve(self.session(), &self.presets, &self.library);
+        errors.extend(resolved.errors);
+        if let Err(e) = self.open_session(resolved.session) {
+            errors.push(e);
+        }
+        if let Some(function) = resolved.library_function {
+            self.library_function = Some(function.name.clone());
+            self.library_name = function.name;
+            self.library_description = function.description;
+        }
+        combine_errors(errors)
+    }
+
+    /// Loads the volumetric scalar field sampled by `sample_field`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_volume(&mut self, path: &Path) -> Result<()> {
+        let volume = Volume::load(path)?;
+        self.main_view
+            .set_scalar_field(&self.device, &self.queue, Some(&volume))?;
+        self.volume = Some(path.to_path_buf());
+        self.volume_path = path.display().to_string();
+        let [x, y, z] = volume.dimensions;
+        let (min, max) = volume.range();
+        self.volume_status = Some(format!(
+            "Loaded {}x{}x{} grid from {:?} to {:?} with values from {} to {}",
+            x,
+            y,
+            z,
+            volume.origin,
+            volume.end(),
+            min,
+            max
+        ));
+        Ok(())
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn reload_compute_shader(&mut self, new_src: &str) -> Result<(), wgpu::Error> {
+    fn clear_volume(&mut self) {
         self.main_view
-            .reload_shader(&self.device, Some(new_src), self.field_function.clone())
+            .set_scalar_field(&self.device, &self.queue, None)
+            .expect("removing the scalar field failed");
+        self.volume = None;
+        self.volume_status = None;
+    }
+
+    /// Loads the vector field sampled by `sample_vector_field`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_vector_field(&mut self, path: &Path) -> Result<()> {
+        let volume = Volume::load(path)?;
+        self.main_view.set_vector_field(
+            &self.device,
+            &self.queue,
+            Some(&volume),
+            self.vector_field_options.mapping(self.normalization),
+            self.vector_field_options.outside,
+        )?;
+        self.vector_field = Some(path.to_path_buf());
+        self.vector_field_path = path.display().to_string();
+        let [x, y, z] = volume.dimensions;
+        self.vector_field_status = Some(format!(
+            "Loaded {}x{}x{} grid from {:?} to {:?}",
+            x,
+            y,
+            z,
+            volume.origin,
+            volume.end()
+        ));
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn clear_vector_field(&mut self) {
+        self.main_view
+            .set_vector_field(
+                &self.device,
+                &self.queue,
+                None,
+                Normalization::IDENTITY,
+                self.vector_field_options.outside,
+            )
+            .expect("removing the vector field failed");
+        self.vector_field = None;
+        self.vector_field_status = None;
+    }
+
+    /// Applies the placement of the vector field after its options or the model changed.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn update_vector_field_mapping(&mut self) {
+        self.main_view.set_vector_field_mapping(
+            &self.queue,
+            self.vector_field_options.mapping(self.normalization),
+            self.vector_field_options.outside,
+        );
+    }
+
+    fn update_settings(&mut self) {
+        let mut settings = Settings {
+            field_weight: self.field_weight,
+            mouse_pos: self.mouse_pos,
+            overlay_mode: self.overlay_mode as i32,
+            central_difference_delta: self.central_difference_delta,
+            lyapunov_scaling: self.lyapunov_scaling,
+            background: self.background as i32,
+            time: self.clock.time,
+            ..Default::default()
+        };
+        settings.set_integrator(&self.integrator);
+        self.main_view.update_settings(&self.queue, settings);
+    }
+
+    /// The camera, field weight and parameters at the current time.
+    fn current_keyframe(&self) -> Keyframe {
+        Keyframe {
+            time: self.clock.time,
+            camera: self.main_view.camera_pose(),
+            field_weight: self.field_weight,
+            parameters: parameters::values(self.main_view.parameters()),
+        }
+    }
+
+    /// Moves the camera and sets the field weight and parameters of a keyframe,
+    /// the settings have to be updated afterwards.
+    fn apply_keyframe(&mut self, keyframe: &Keyframe) {
+        self.field_weight = keyframe.field_weight;
+        self.main_view
+            .set_parameter_values(&self.queue, &keyframe.parameters);
+        self.main_view.set_camera_pose(&self.queue, keyframe.camera);
+    }
+
+    /// Renders the timeline from its first to its last keyframe with the high accuracy settings
+    /// and writes the frames as numbered PNG files into `dir`. Returns the number of frames.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn render_sequence(&mut self, dir: &Path) -> Result<usize> {
+        let (start, end) = self
+            .timeline
+            .range()
+            .context("the timeline has no keyframes")?;
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("creating {} failed", dir.display()))?;
+        let fps = self.sequence_fps as f32;
+        let frames = ((end - start) * fps).round() as usize + 1;
+        let previous = self.current_keyframe();
+        let result = (0..frames).try_for_each(|frame| {
+            self.clock.time = start + frame as f32 / fps;
+            if let Some(keyframe) = self.timeline.sample(self.clock.time) {
+                self.apply_keyframe(&keyframe);
+            }
+            self.update_settings();
+            let [width, height] = self.sequence_size;
+            let image = self
+                .main_view
+                .render_high_accuracy_image(&self.device, &self.queue, (width, height), true)
+                .context("reading back main view texture failed")?;
+            let path = dir.join(format!("frame_{:05}.png", frame));
+            let mut metadata = self.main_view.image_metadata();
+            metadata.push((
+                "Camera",
+                serde_json::to_string(&self.main_view.camera_pose())?,
+            ));
+            export::save_png(&path, &image, &metadata)
+        });
+        self.clock.time = previous.time;
+        self.apply_keyframe(&previous);
+        self.update_settings();
+        result.map(|()| frames)
+    }
+
+    /// Renders the main or the reference view and writes it as PNG file together with the field
+    /// function, the settings and the camera of the view as text chunks, or exports the ray data
+    /// of the main view. Returns the status shown below the buttons.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_image(&mut self, action: ImageAction) -> Result<String> {
+        let mut metadata = self.main_view.image_metadata();
+        let image = match action {
+            ImageAction::SaveMainView => {
+                let size = self
+                    .image_size
+                    .map_or_else(|| self.main_view.full_size(), |[w, h]| (w, h));
+                let image = self
+                    .main_view
+                    .render_high_accuracy_image(&self.device, &self.queue, size, self.image_overlay)
+                    .context("reading back main view texture failed")?;
+                // Restores the overlay and the size of the view
+                self.main_view.needs_redraw = true;
+                metadata.push((
+                    "Camera",
+                    serde_json::to_string(&self.main_view.camera_pose())?,
+                ));
+                image
+            }
+            ImageAction::SaveReferenceView => {
+                let size = self
+                    .image_size
+                    .map_or_else(|| self.reference_view.dimensions(), |[w, h]| (w, h));
+                let image = self
+                    .reference_view
+                    .render_image(
+                        &self.device,
+                        &self.queue,
+                        size,
+                        self.indices,
+                        self.ray_samples_buffer.slice(..),
+                    )
+                    .context("reading back reference view texture failed")?;
+                metadata.push((
+                    "Camera",
+                    serde_json::to_string(&self.reference_view.camera_pose())?,
+                ));
+                image
+            }
+            ImageAction::ExportRayData => {
+                let size = self
+                    .image_size
+                    .map_or_else(|| self.main_view.full_size(), |[w, h]| (w, h));
+                let data = self
+                    .main_view
+                    .render_ray_data(&self.device, &self.queue, size)?;
+                self.main_view.needs_redraw = true;
+                metadata.push((
+                    "Camera",
+                    serde_json::to_string(&self.main_view.camera_pose())?,
+                ));
+                let path = PathBuf::from(&self.ray_data_path);
+                let paths = export::save_ray_data(&path, &data, &metadata)?;
+                let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+                return Ok(format!("Exported ray data to {}", paths.join(", ")));
+            }
+        };
+        let path = PathBuf::from(&self.image_path);
+        export::save_png(&path, &image, &metadata)?;
+        Ok(format!("Saved image to {}", path.display()))
+    }
+
+    /// Shows the diagnostics of an invalid field function in the editor, other errors below it.
+    fn show_shader_result(&mut self, result: &Result<()>) {
+        self.shader_error = None;
+        self.diagnostics.clear();
+        if let Err(e) = result {
+            match e.downcast_ref::<FieldFunctionError>() {
+                Some(e) => self.diagnostics = e.diagnostics.clone(),
+                None => self.shader_error = Some(e.to_string()),
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_compute_shader(&mut self, new_src: &str) -> Result<()> {
+        self.main_view.reload_shader(
+            &self.device,
+            &self.queue,
+            Some(new_src),
+            self.prelude.clone(),
+            self.field_function.clone(),
+            self.field_mode,
+        )
+    }
+
+    /// Shows a predefined function in the editor and applies its recommended settings.
+    fn select_predefined_function(&mut self, function: PredefinedFunction) -> Result<()> {
+        self.predefined_function = Some(function.id);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.library_function = None;
+        }
+        if !function.prelude.is_empty() {
+            self.prelude = function.prelude;
+        }
+        self.field_function = function.body;
+        self.field_mode = function.mode;
+        let settings = function.settings;
+        if let Some(field_weight) = settings.field_weight {
+            self.field_weight = field_weight;
+        }
+        if let Some(overlay_mode) = settings.overlay_mode {
+            self.overlay_mode = overlay_mode;
+        }
+        if let Some(background) = settings.background {
+            self.background = background;
+        }
+        if let Some(scheme) = settings.integrator {
+            self.integrator.scheme = scheme;
+        }
+        if let Some(t_max) = settings.t_max {
+            self.integrator.t_max = t_max;
+        }
+        self.update_settings();
+        if let Some(pose) = settings.camera {
+            self.main_view.set_camera_pose(&self.queue, pose);
+        }
+        let result = self.main_view.reload_shader(
+            &self.device,
+            &self.queue,
+            None,
+            self.prelude.clone(),
+            self.field_function.clone(),
+            self.field_mode,
+        );
+        self.show_shader_result(&result);
+        result
+    }
+
+    /// Performs an action of the library panel and describes its outcome.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply_library_action(&mut self, action: LibraryAction) -> Result<String> {
+        let selected = || {
+            self.library_function
+                .clone()
+                .context("no library function is selected")
+        };
+        match action {
+            LibraryAction::Load(name) => {
+                let function = self
+                    .library
+                    .get(&name)
+                    .cloned()
+                    .with_context(|| format!("no library function named {:?}", name))?;
+                self.predefined_function = None;
+                self.library_function = Some(function.name.clone());
+                self.library_name = function.name.clone();
+                self.library_description = function.description;
+                self.prelude = function.prelude;
+                self.field_function = function.body;
+                self.field_mode = function.mode;
+                let result = self.main_view.reload_shader(
+                    &self.device,
+                    &self.queue,
+                    None,
+                    self.prelude.clone(),
+                    self.field_function.clone(),
+                    self.field_mode,
+                );
+                self.show_shader_result(&result);
+                self.main_view
+                    .set_parameter_values(&self.queue, &function.parameters);
+                Ok(format!("Loaded {}", function.name))
+            }
+            LibraryAction::Save => {
+                let name = self.library_name.trim().to_string();
+                self.library.save(LibraryFunction {
+                    name: name.clone(),
+                    description: self.library_description.clone(),
+                    prelude: self.prelude.clone(),
+                    body: self.field_function.clone(),
+                    mode: self.field_mode,
+                    parameters: parameters::values(self.main_view.parameters()),
+                })?;
+                self.predefined_function = None;
+                self.library_function = Some(name.clone());
+                Ok(format!("Saved {}", name))
+            }
+            LibraryAction::Rename => {
+                let name = selected()?;
+                let new_name = self.library_name.trim().to_string();
+                self.library.rename(&name, &new_name)?;
+                self.library_function = Some(new_name.clone());
+                Ok(format!("Renamed {} to {}", name, new_name))
+            }
+            LibraryAction::Duplicate => {
+                let name = selected()?;
+                let new_name = self.library.duplicate(&name)?;
+                self.library_function = Some(new_name.clone());
+                self.library_name = new_name.clone();
+                Ok(format!("Duplicated {} as {}", name, new_name))
+            }
+            LibraryAction::Delete => {
+                let name = selected()?;
+                self.library.delete(&name)?;
+                self.library_function = None;
+                Ok(format!("Deleted {}", name))
+            }
+        }
     }
 
     fn show(&mut self) {
@@ -238,19 +1001,203 @@ impl Application {
             main_view,
             reference_view,
             shader_error,
+            diagnostics,
             field_weight,
             mouse_pos,
             overlay_mode,
+            background,
             central_difference_delta,
             lyapunov_scaling,
+            prelude,
             field_function,
+            field_mode,
+            presets,
             predefined_function,
+            integrator,
+            clock,
+            timeline,
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_dir,
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_size,
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_fps,
+            #[cfg(not(target_arch = "wasm32"))]
+            sequence_status,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_path,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_overlay,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_size,
+            #[cfg(not(target_arch = "wasm32"))]
+            image_status,
+            #[cfg(not(target_arch = "wasm32"))]
+            ray_data_path,
+            #[cfg(not(target_arch = "wasm32"))]
+            session_path,
+            #[cfg(not(target_arch = "wasm32"))]
+            session_status,
+            #[cfg(not(target_arch = "wasm32"))]
+            volume_path,
+            #[cfg(not(target_arch = "wasm32"))]
+            volume_status,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_path,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_status,
+            #[cfg(not(target_arch = "wasm32"))]
+            vector_field_options,
+            #[cfg(not(target_arch = "wasm32"))]
+            library,
+            #[cfg(not(target_arch = "wasm32"))]
+            library_function,
+            #[cfg(not(target_arch = "wasm32"))]
+            library_name,
+            #[cfg(not(target_arch = "wasm32"))]
+            library_description,
+            #[cfg(not(target_arch = "wasm32"))]
+            library_status,
             ..
         } = self;
         let mut field_function_changed = false;
+        let mut settings_changed = false;
+        let mut time_changed = clock.advance(ctx.input(|i| i.unstable_dt));
+        let mut timeline_action = None;
+        let mut selected_predefined_function = None;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut save_session = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut open_session = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut image_action = None;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut library_action = None;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut load_volume = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut clear_volume = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut load_vector_field = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut clear_vector_field = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut vector_field_options_changed = false;
         let device = &self.device;
         let queue = &self.queue;
         egui::SidePanel::left("Settings").show(ctx, |ui| {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                ui.horizontal(|ui| {
+                    ui.label("Session:");
+                    ui.text_edit_singleline(session_path);
+                    save_session = ui.button("Save").clicked();
+                    open_session = ui.button("Open").clicked();
+                });
+                if let Some(session_status) = session_status {
+                    ui.label(session_status.as_str());
+                }
+                egui::CollapsingHeader::new("Save image").show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Image:");
+                        ui.text_edit_singleline(image_path);
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Save main view").clicked() {
+                            image_action = Some(ImageAction::SaveMainView);
+                        }
+                        ui.checkbox(image_overlay, "Overlay")
+                            .on_hover_text("Include the overlay, e.g. the Lyapunov exponents");
+                    });
+                    if ui.button("Save reference view").clicked() {
+                        image_action = Some(ImageAction::SaveReferenceView);
+                    }
+                    ui.horizontal(|ui| {
+                        let mut custom_size = image_size.is_some();
+                        ui.checkbox(&mut custom_size, "Custom size")
+                            .on_hover_text("Render in this size instead of the size of the view");
+                        match (custom_size, image_size.as_mut()) {
+                            (true, Some(size)) => {
+                                ui.add(egui::DragValue::new(&mut size[0]));
+                                ui.label("x");
+                                ui.add(egui::DragValue::new(&mut size[1]));
+                                size[0] = size[0].clamp(1, 8192);
+                                size[1] = size[1].clamp(1, 8192);
+                            }
+                            (true, None) => *image_size = Some([1920, 1080]),
+                            (false, _) => *image_size = None,
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Ray data:");
+                        ui.text_edit_singleline(ray_data_path);
+                        if ui
+                            .button("Export")
+                            .on_hover_text(
+                                "Write the end points, hit distances, hit colors and Lyapunov \
+                                 exponents of the main view as OpenEXR file (.exr) or as NumPy \
+                                 arrays (.npy)",
+                            )
+                            .clicked()
+                        {
+                            image_action = Some(ImageAction::ExportRayData);
+                        }
+                    });
+                    if let Some(image_status) = image_status {
+                        ui.label(image_status.as_str());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Volume:")
+                        .on_hover_text("Scalar field sampled by sample_field, a .vtk or raw file");
+                    ui.text_edit_singleline(volume_path);
+                    load_volume = ui.button("Load").clicked();
+                    clear_volume = ui.button("Clear").clicked();
+                });
+                if let Some(volume_status) = volume_status {
+                    ui.label(volume_status.as_str());
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Vector field:").on_hover_text(
+                        "Vector field sampled by sample_vector_field, a .vtk file with VECTORS \
+                         or a raw file with 3 components",
+                    );
+                    ui.text_edit_singleline(vector_field_path);
+                    load_vector_field = ui.button("Load").clicked();
+                    clear_vector_field = ui.button("Clear").clicked();
+                });
+                if let Some(vector_field_status) = vector_field_status {
+                    ui.label(vector_field_status.as_str());
+                }
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Coordinates")
+                        .selected_text(vector_field_options.space.to_string())
+                        .show_ui(ui, |ui| {
+                            for space in VolumeSpace::ALL {
+                                vector_field_options_changed |= ui
+                                    .selectable_value(
+                                        &mut vector_field_options.space,
+                                        space,
+                                        space.to_string(),
+                                    )
+                                    .clicked();
+                            }
+                        });
+                    egui::ComboBox::from_label("Outside of grid")
+                        .selected_text(vector_field_options.outside.to_string())
+                        .show_ui(ui, |ui| {
+                            for outside in Outside::ALL {
+                                vector_field_options_changed |= ui
+                                    .selectable_value(
+                                        &mut vector_field_options.outside,
+                                        outside,
+                                        outside.to_string(),
+                                    )
+                                    .clicked();
+                            }
+                        });
+                });
+            }
             ui.horizontal(|ui| {
                 if egui::ComboBox::from_label("Overlay")
                     .selected_text(overlay_mode.to_string())
@@ -272,24 +1219,97 @@ impl Application {
                     .inner
                     .unwrap_or(false)
                 {
-                    main_view.update_settings(
-                        queue,
-                        Settings {
-                            field_weight: *field_weight,
-                            mouse_pos: *mouse_pos,
-                            overlay_mode: *overlay_mode as i32,
-                            central_difference_delta: *central_difference_delta,
-                            lyapunov_scaling: *lyapunov_scaling,
-                        },
-                    );
+                    settings_changed = true;
                 }
                 if ui.button("Enhance").clicked() {
-                    main_view.render_high_accuracy(device, queue, field_function.clone());
+                    main_view.render_high_accuracy(device, queue);
                 }
                 if ui.button("Outline").clicked() {
-                    main_view.render_outline_rays(device, queue, field_function.clone());
+                    if let Err(e) = main_view.render_outline_rays(device, queue) {
+                        *shader_error = Some(e.to_string());
+                    }
                 }
             });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Background")
+                    .selected_text(background.to_string())
+                    .show_ui(ui, |ui| {
+                        for option in Background::ALL {
+                            settings_changed |= ui
+                                .selectable_value(background, option, option.to_string())
+                                .clicked();
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Integrator")
+                    .selected_text(integrator.scheme.to_string())
+                    .show_ui(ui, |ui| {
+                        for scheme in IntegrationScheme::ALL {
+                            if ui
+                                .selectable_value(
+                                    &mut integrator.scheme,
+                                    scheme,
+                                    scheme.to_string(),
+                                )
+                                .clicked()
+                            {
+                                settings_changed = true;
+                            }
+                        }
+                    });
+            });
+            egui::Grid::new("integrator_settings").show(ui, |ui| {
+                ui.label("Integration time:");
+                settings_changed |= ui
+                    .add(egui::Slider::new(&mut integrator.t_max, 0.5..=50.0).logarithmic(true))
+                    .changed();
+                ui.end_row();
+                ui.label("Initial step size:");
+                settings_changed |= ui
+                    .add(egui::Slider::new(&mut integrator.h_initial, 1e-4..=1.0).logarithmic(true))
+                    .changed();
+                ui.end_row();
+                ui.label("Minimum step size:");
+                settings_changed |= ui
+                    .add(egui::Slider::new(&mut integrator.h_min, 1e-5..=1e-1).logarithmic(true))
+                    .changed();
+                ui.end_row();
+                ui.label("Maximum steps:");
+                settings_changed |= ui
+                    .add(
+                        egui::Slider::new(&mut integrator.max_steps, 100..=100000)
+                            .logarithmic(true),
+                    )
+                    .changed();
+                ui.end_row();
+                if integrator.scheme.is_adaptive() {
+                    ui.label("Maximum step size:");
+                    settings_changed |= ui
+                        .add(egui::Slider::new(&mut integrator.h_max, 1e-3..=1.0).logarithmic(true))
+                        .changed();
+                    ui.end_row();
+                    ui.label("Absolute tolerance:");
+                    settings_changed |= ui
+                        .add(
+                            egui::Slider::new(&mut integrator.abs_tol, 1e-7..=1e-1)
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    ui.end_row();
+                    ui.label("Relative tolerance:");
+                    settings_changed |= ui
+                        .add(
+                            egui::Slider::new(&mut integrator.rel_tol, 1e-7..=1e-1)
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    ui.end_row();
+                }
+            });
+            if integrator.h_min > integrator.h_max {
+                integrator.h_max = integrator.h_min;
+            }
             if *overlay_mode != OverlayMode::Disabled {
                 ui.horizontal(|ui| {
                     ui.label("Central difference delta:");
@@ -297,16 +1317,7 @@ impl Application {
                         .add(egui::Slider::new(central_difference_delta, 1..=10))
                         .changed()
                     {
-                        main_view.update_settings(
-                            queue,
-                            Settings {
-                                field_weight: *field_weight,
-                                mouse_pos: *mouse_pos,
-                                overlay_mode: *overlay_mode as i32,
-                                central_difference_delta: *central_difference_delta,
-                                lyapunov_scaling: *lyapunov_scaling,
-                            },
-                        );
+                        settings_changed = true;
                     }
                 });
             }
@@ -317,147 +1328,258 @@ impl Application {
                         .add(egui::Slider::new(lyapunov_scaling, 1.0..=100.0))
                         .changed()
                     {
-                        main_view.update_settings(
-                            queue,
-                            Settings {
-                                field_weight: *field_weight,
-                                mouse_pos: *mouse_pos,
-                                overlay_mode: *overlay_mode as i32,
-                                central_difference_delta: *central_difference_delta,
-                                lyapunov_scaling: *lyapunov_scaling,
-                            },
-                        );
+                        settings_changed = true;
                     }
                 });
             }
             ui.horizontal(|ui| {
                 ui.label("Field weight:");
                 if ui.add(egui::Slider::new(field_weight, 0.0..=1.0)).changed() {
-                    main_view.update_settings(
-                        queue,
-                        Settings {
-                            field_weight: *field_weight,
-                            mouse_pos: *mouse_pos,
-                            overlay_mode: *overlay_mode as i32,
-                            central_difference_delta: *central_difference_delta,
-                            lyapunov_scaling: *lyapunov_scaling,
-                        },
-                    );
+                    settings_changed = true;
                 }
             });
-            egui::ComboBox::from_label("Predefined function")
-                .selected_text(predefined_function.to_string())
-                .show_ui(ui, |ui| {
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::MirageSpherical,
-                            PredefinedFunction::MirageSpherical.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::MirageSpherical.to_code();
-                        field_function_changed = true;
-                    }
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::MiragePlane,
-                            PredefinedFunction::MiragePlane.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::MiragePlane.to_code();
-                        field_function_changed = true;
-                    }
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::MirageSphericalSigmoid,
-                            PredefinedFunction::MirageSphericalSigmoid.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::MirageSphericalSigmoid.to_code();
-                        field_function_changed = true;
-                    }
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::MiragePlaneSigmoid,
-                            PredefinedFunction::MiragePlaneSigmoid.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::MiragePlaneSigmoid.to_code();
-                        field_function_changed = true;
-                    }
+            ui.horizontal(|ui| {
+                ui.label("Time:").on_hover_text(
+                    "Animation time returned by animation_time(), \
+                     independent of the integration time t of the rays",
+                );
+                if ui
+                    .button(if clock.playing { "Pause" } else { "Play" })
+                    .clicked()
+                {
+                    clock.playing = !clock.playing;
+                }
+                let range = clock.loop_start..=clock.loop_end.max(clock.loop_start);
+                time_changed |= ui
+                    .add(egui::Slider::new(&mut clock.time, range).suffix(" s"))
+                    .changed();
+            });
+            ui.horizontal(|ui| {
+                ui.label("Loop:");
+                ui.add(
+                    egui::DragValue::new(&mut clock.loop_start)
+                        .speed(0.1)
+                        .suffix(" s"),
+                );
+                ui.label("to");
+                ui.add(
+                    egui::DragValue::new(&mut clock.loop_end)
+                        .speed(0.1)
+                        .suffix(" s"),
+                );
+                ui.label("Speed:");
+                ui.add(
+                    egui::DragValue::new(&mut clock.speed)
+                        .speed(0.01)
+                        .suffix("x"),
+                );
+                clock.speed = clock.speed.max(0.0);
+            });
+            egui::CollapsingHeader::new("Timeline").show(ui, |ui| {
+                ui.horizontal(|ui| {
                     if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::TranslationX,
-                            PredefinedFunction::TranslationX.to_string(),
+                        .button("Add keyframe")
+                        .on_hover_text(
+                            "Store the camera, the field weight and the parameters at the current time",
                         )
                         .clicked()
                     {
-                        *field_function = PredefinedFunction::TranslationX.to_code();
-                        field_function_changed = true;
+                        timeline_action = Some(TimelineAction::Add);
                     }
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::TranslationZ,
-                            PredefinedFunction::TranslationZ.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::TranslationZ.to_code();
-                        field_function_changed = true;
+                    egui::ComboBox::from_label("Interpolation")
+                        .selected_text(timeline.interpolation.to_string())
+                        .show_ui(ui, |ui| {
+                            for interpolation in Interpolation::ALL {
+                                ui.selectable_value(
+                                    &mut timeline.interpolation,
+                                    interpolation,
+                                    interpolation.to_string(),
+                                );
+                            }
+                        });
+                });
+                for (index, keyframe) in timeline.keyframes().iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{:.2} s", keyframe.time));
+                        if ui.button("Go to").clicked() {
+                            timeline_action = Some(TimelineAction::GoTo(keyframe.time));
+                        }
+                        if ui.button("Remove").clicked() {
+                            timeline_action = Some(TimelineAction::Remove(index));
+                        }
+                    });
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    ui.horizontal(|ui| {
+                        ui.label("Frames:");
+                        ui.text_edit_singleline(sequence_dir);
+                        ui.add_enabled_ui(!timeline.keyframes().is_empty(), |ui| {
+                            if ui
+                                .button("Render sequence")
+                                .on_hover_text(
+                                    "Write numbered PNG frames from the first to the last keyframe \
+                                     into this directory",
+                                )
+                                .clicked()
+                            {
+                                timeline_action = Some(TimelineAction::RenderSequence);
+                            }
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Size:");
+                        ui.add(egui::DragValue::new(&mut sequence_size[0]));
+                        ui.label("x");
+                        ui.add(egui::DragValue::new(&mut sequence_size[1]));
+                        ui.label("Frames per second:");
+                        ui.add(egui::DragValue::new(sequence_fps));
+                        sequence_size[0] = sequence_size[0].max(1);
+                        sequence_size[1] = sequence_size[1].max(1);
+                        *sequence_fps = (*sequence_fps).max(1);
+                    });
+                    if let Some(sequence_status) = sequence_status {
+                        ui.label(sequence_status.as_str());
                     }
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::Rotation,
-                            PredefinedFunction::Rotation.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::Rotation.to_code();
-                        field_function_changed = true;
+                }
+            });
+            let predefined_name = predefined_function
+                .as_ref()
+                .and_then(|id| presets.get(id))
+                .map_or_else(|| "Custom".to_string(), |f| f.name.clone());
+            #[cfg(not(target_arch = "wasm32"))]
+            let selected_text = library_function.clone().unwrap_or(predefined_name);
+            #[cfg(target_arch = "wasm32")]
+            let selected_text = predefined_name;
+            egui::ComboBox::from_label("Predefined function")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    for category in presets.categories() {
+                        if !category.is_empty() {
+                            ui.label(egui::RichText::new(category).weak());
+                        }
+                        for function in presets.iter().filter(|f| f.category == category) {
+                            let selected = predefined_function.as_ref() == Some(&function.id);
+                            let mut response = ui.selectable_label(selected, &function.name);
+                            if !function.description.is_empty() {
+                                response = response.on_hover_text(&function.description);
+                            }
+                            if response.clicked() {
+                                selected_predefined_function = Some(function.clone());
+                            }
+                        }
                     }
-                    if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::LorenzAttractor,
-                            PredefinedFunction::LorenzAttractor.to_string(),
-                        )
-                        .clicked()
-                    {
-                        *field_function = PredefinedFunction::LorenzAttractor.to_code();
-                        field_function_changed = true;
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if library.functions().next().is_some() {
+                        ui.separator();
+                        for function in library.functions() {
+                            let selected = library_function.as_ref() == Some(&function.name);
+                            let mut response = ui.selectable_label(selected, &function.name);
+                            if !function.description.is_empty() {
+                                response = response.on_hover_text(&function.description);
+                            }
+                            if response.clicked() {
+                                library_action = Some(LibraryAction::Load(function.name.clone()));
+                            }
+                        }
                     }
+                });
+            #[cfg(not(target_arch = "wasm32"))]
+            egui::CollapsingHeader::new("Function library").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Name:");
+                    ui.text_edit_singleline(library_name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Description:");
+                    ui.text_edit_singleline(library_description);
+                });
+                ui.horizontal(|ui| {
                     if ui
-                        .selectable_value(
-                            predefined_function,
-                            PredefinedFunction::RoesslerAttractor,
-                            PredefinedFunction::RoesslerAttractor.to_string(),
-                        )
+                        .button("Save")
+                        .on_hover_text("Save the current function under this name")
                         .clicked()
                     {
-                        *field_function = PredefinedFunction::RoesslerAttractor.to_code();
-                        field_function_changed = true;
+                        library_action = Some(LibraryAction::Save);
                     }
+                    ui.add_enabled_ui(library_function.is_some(), |ui| {
+                        if ui
+                            .button("Rename")
+                            .on_hover_text("Give the selected library function this name")
+                            .clicked()
+                        {
+                            library_action = Some(LibraryAction::Rename);
+                        }
+                        if ui.button("Duplicate").clicked() {
+                            library_action = Some(LibraryAction::Duplicate);
+                        }
+                        if ui.button("Delete").clicked() {
+                            library_action = Some(LibraryAction::Delete);
+                        }
+                    });
                 });
+                ui.label(format!("Stored in {}", library.dir().display()));
+                if let Some(library_status) = library_status {
+                    ui.label(library_status.as_str());
+                }
+            });
             ui.vertical(|ui| {
-                ui.label("Custom function:");
-                if code_view_ui(ui, field_function).lost_focus() {
-                    *predefined_function = PredefinedFunction::Custom;
+                let (prelude_diagnostics, body_diagnostics): (Vec<_>, Vec<_>) = diagnostics
+                    .iter()
+                    .cloned()
+                    .partition(|d| d.snippet == Snippet::Prelude);
+                egui::CollapsingHeader::new("Prelude (helper functions, constants and structs)")
+                    .default_open(!prelude.is_empty())
+                    .show(ui, |ui| {
+                        let response = ui.push_id("prelude", |ui| {
+                            code_view_ui(ui, prelude, &prelude_diagnostics, main_view.helpers())
+                        });
+                        if response.inner.lost_focus() {
+                            field_function_changed = true;
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("Custom function:");
+                    egui::ComboBox::from_label("Mode")
+                        .selected_text(field_mode.to_string())
+                        .show_ui(ui, |ui| {
+                            for mode in FieldMode::ALL {
+                                if ui
+                                    .selectable_value(field_mode, mode, mode.to_string())
+                                    .clicked()
+                                {
+                                    *predefined_function = None;
+                                    field_function_changed = true;
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "What the function returns: the velocity of the ray as vec3<f32>, \
+                             the refractive index at p as f32, for which the ray equation \
+                             of gradient-index optics is integrated, or the acceleration of the \
+                             ray as vec3<f32>",
+                        );
+                });
+                if code_view_ui(ui, field_function, &body_diagnostics, main_view.helpers())
+                    .lost_focus()
+                {
+                    *predefined_function = None;
                     field_function_changed = true;
                 }
+                for diagnostic in diagnostics.iter() {
+                    ui.colored_label(ui.visuals().error_fg_color, diagnostic.to_string());
+                }
                 if let Some(shader_error) = shader_error {
                     ui.label(format!("Shader error: {}", shader_error));
                 }
             });
+            if !main_view.parameters().is_empty() {
+                ui.label("Parameters:");
+                if parameters::parameters_ui(ui, main_view.parameters_mut()) {
+                    main_view.update_parameters(queue);
+                }
+            }
             reference_view.show(ui, device, queue);
         });
         let device = &self.device;
@@ -465,28 +1587,110 @@ impl Application {
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(new_pos) = main_view.show(ui, rpass, device, queue) {
                 *mouse_pos = new_pos;
-                main_view.update_settings(
-                    queue,
-                    Settings {
-                        field_weight: *field_weight,
-                        mouse_pos: *mouse_pos,
-                        overlay_mode: *overlay_mode as i32,
-                        central_difference_delta: *central_difference_delta,
-                        lyapunov_scaling: *lyapunov_scaling,
-                    },
-                );
+                settings_changed = true;
             }
         });
+        match timeline_action {
+            Some(TimelineAction::Add) => self.timeline.insert(self.current_keyframe()),
+            Some(TimelineAction::GoTo(time)) => {
+                self.clock.time = time;
+                time_changed = true;
+            }
+            Some(TimelineAction::Remove(index)) => self.timeline.remove(index),
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(TimelineAction::RenderSequence) => {
+                let dir = PathBuf::from(&self.sequence_dir);
+                self.sequence_status = Some(match self.render_sequence(&dir) {
+                    Ok(frames) => format!("Wrote {} frames to {}", frames, dir.display()),
+                    Err(e) => format!("Rendering sequence failed: {:#}", e),
+                });
+            }
+            None => {}
+        }
+        if time_changed {
+            if let Some(keyframe) = self.timeline.sample(self.clock.time) {
+                self.apply_keyframe(&keyframe);
+                settings_changed = true;
+            }
+        }
+        if settings_changed {
+            self.update_settings();
+        } else if time_changed {
+            self.main_view.set_time(&self.queue, self.clock.time);
+        }
+        if let Some(function) = selected_predefined_function {
+            // Errors are shown in the editor
+            let _ = self.select_predefined_function(function);
+        }
         if field_function_changed {
-            if let Err(e) = self
-                .main_view
-                .reload_shader(device, None, field_function.clone())
-            {
-                self.shader_error = Some(e.to_string());
-            } else {
-                self.shader_error = None;
+            // An invalid function keeps the last working pipeline active
+            let result = self.main_view.reload_shader(
+                &self.device,
+                &self.queue,
+                None,
+                self.prelude.clone(),
+                self.field_function.clone(),
+                self.field_mode,
+            );
+            self.show_shader_result(&result);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(action) = image_action {
+            self.image_status = Some(match self.save_image(action) {
+                Ok(status) => status,
+                Err(e) => format!("Saving failed: {:#}", e),
+            });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if save_session {
+            let path = std::path::PathBuf::from(&self.session_path);
+            self.session_status = Some(match self.session().save(&path) {
+                Ok(()) => format!("Saved session to {}", path.display()),
+                Err(e) => format!("Saving session failed: {:#}", e),
+            });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if load_volume {
+            let path = PathBuf::from(&self.volume_path);
+            if let Err(e) = self.load_volume(&path) {
+                self.volume_status = Some(format!("Loading volume failed: {:#}", e));
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if clear_volume {
+            self.clear_volume();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if load_vector_field {
+            let path = PathBuf::from(&self.vector_field_path);
+            if let Err(e) = self.load_vector_field(&path) {
+                self.vector_field_status = Some(format!("Loading vector field failed: {:#}", e));
             }
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        if clear_vector_field {
+            self.clear_vector_field();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if vector_field_options_changed {
+            self.update_vector_field_mapping();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(action) = library_action {
+            self.library_status = Some(match self.apply_library_action(action) {
+                Ok(status) => status,
+                Err(e) => format!("Function library: {:#}", e),
+            });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if open_session {
+            let path = std::path::PathBuf::from(&self.session_path);
+            let result = Session::load(&path).and_then(|session| self.open_session(session));
+            self.session_status = Some(match result {
+                Ok(()) => format!("Opened session {}", path.display()),
+                Err(e) => format!("Opening session failed: {:#}", e),
+            });
+        }
     }
 
     pub fn render(&mut self, window: &winit::window::Window) -> Result<(), wgpu::SurfaceError> {
@@ -527,3 +1731,12 @@ impl Application {
         Ok(())
     }
 }
+
+/// Fails with all `errors` if there are any, one per line.
+pub fn combine_errors(errors: Vec<anyhow::Error>) -> Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let errors: Vec<_> = errors.iter().map(|e| format!("{:#}", e)).collect();
+    bail!("{}", errors.join("\n"))
+}
@@ -7,6 +7,7 @@
 use cgmath::num_traits::clamp;
 use cgmath::prelude::*;
 use cgmath::{BaseFloat, Matrix4, Quaternion, Vector2, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum CameraOperation {
@@ -15,6 +16,28 @@ pub enum CameraOperation {
     Pan,
 }
 
+/// A camera pose that can be stored and restored independently of the screen size.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraPose<F> {
+    /// The point the camera orbits around
+    pub center: [F; 3],
+    /// The camera rotation as quaternion `[w, x, y, z]`
+    pub rotation: [F; 4],
+    /// The distance between the camera and `center`
+    pub distance: F,
+}
+
+impl<F: BaseFloat> CameraPose<F> {
+    /// The pose the views start in, looking at `center` from the front.
+    pub fn initial(center: Vector3<F>) -> Self {
+        CameraPose {
+            center: center.into(),
+            rotation: [F::one(), F::zero(), F::zero(), F::zero()],
+            distance: F::one() + F::one(),
+        }
+    }
+}
+
 /// The Shoemake Arcball camera.
 pub struct ArcballCamera<F> {
     pub center: Vector3<F>,
@@ -108,6 +131,31 @@ impl<F: BaseFloat> ArcballCamera<F> {
                 * self.center_translation;
         self.update_camera();
     }
+    /// Get the current pose of the camera.
+    pub fn pose(&self) -> CameraPose<F> {
+        let center = -self.center_translation[3];
+        CameraPose {
+            center: [center.x, center.y, center.z],
+            rotation: [
+                self.rotation.s,
+                self.rotation.v.x,
+                self.rotation.v.y,
+                self.rotation.v.z,
+            ],
+            distance: -self.translation[3].z,
+        }
+    }
+    /// Move the camera to the given pose.
+    pub fn set_pose(&mut self, pose: CameraPose<F>) {
+        let [x, y, z] = pose.center;
+        let [w, i, j, k] = pose.rotation;
+        self.center = Vector3::new(x, y, z);
+        self.center_translation = Matrix4::from_translation(-self.center);
+        self.translation =
+            Matrix4::from_translation(Vector3::new(F::zero(), F::zero(), -pose.distance));
+        self.rotation = Quaternion::new(w, i, j, k).normalize();
+        self.update_camera();
+    }
     /// Update the screen dimensions, e.g. if the window has resized.
     pub fn update_screen(&mut self, width: F, height: F) {
         self.inv_screen[0] = F::one() / width;
@@ -0,0 +1,386 @@
+//! Bounding volume hierarchy over the faces of a mesh, built with binned SAH
+//! and traversed in `ray_color` in `main_view.wgsl`.
+
+use cgmath::{ElementWise, Vector3};
+
+/// Maximum number of faces in a leaf.
+const MAX_LEAF_SIZE: usize = 4;
+/// Maximum number of faces in a leaf that the surface area heuristic may prefer over a split.
+const MAX_SAH_LEAF_SIZE: usize = 16;
+/// Maximum depth of the tree, limited by the traversal stack of 32 entries in the shader.
+const MAX_DEPTH: usize = 30;
+/// Number of candidate split planes per axis is one less than this.
+const BINS: usize = 16;
+/// Padding added to every box, so that flat boxes (e.g. of axis-aligned walls)
+/// are not missed because of rounding errors in the slab test.
+const PADDING: f32 = 0.00001;
+
+/// A node in the same layout as `BvhNode` in `main_view.wgsl`.
+/// The root is the first node, an empty tree consists of a root with `left_first == 0` and `count == 0`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    /// Index of the left child (the right child follows it), or of the first face for leaves
+    pub left_first: u32,
+    pub max: [f32; 3],
+    /// Number of faces for leaves, 0 for inner nodes
+    pub count: u32,
+}
+
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// The triangle indices reordered so that every leaf references a contiguous range of faces
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Self {
+            min: Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    fn grow(&mut self, p: Vector3<f32>) {
+        self.min = Vector3::new(
+            self.min.x.min(p.x),
+            self.min.y.min(p.y),
+            self.min.z.min(p.z),
+        );
+        self.max = Vector3::new(
+            self.max.x.max(p.x),
+            self.max.y.max(p.y),
+            self.max.z.max(p.z),
+        );
+    }
+
+    fn union(&mut self, other: &Bounds) {
+        self.grow(other.min);
+        self.grow(other.max);
+    }
+
+    fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    fn area(&self) -> f32 {
+        if self.min.x > self.max.x {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+}
+
+impl Bvh {
+    pub fn build(vertices: &[f32], indices: &[u32]) -> Self {
+        let vertex = |i: u32| {
+            let i = i as usize;
+            Vector3::new(vertices[3 * i], vertices[3 * i + 1], vertices[3 * i + 2])
+        };
+        let faces: Vec<Bounds> = indices
+            .chunks_exact(3)
+            .map(|face| {
+                let mut bounds = Bounds::empty();
+                for &i in face {
+                    bounds.grow(vertex(i));
+                }
+                bounds
+            })
+            .collect();
+
+        let mut order: Vec<usize> = (0..faces.len()).collect();
+        let mut nodes = vec![BvhNode::zeroed()];
+        // (node, first face, face count, depth) of the nodes that still have to be built
+        let mut pending = vec![(0, 0, faces.len(), 0)];
+        while let Some((node, first, count, depth)) = pending.pop() {
+            let range = &mut order[first..first + count];
+            let mut bounds = Bounds::empty();
+            for &face in range.iter() {
+                bounds.union(&faces[face]);
+            }
+            nodes[node] = BvhNode {
+                min: (bounds.min.sub_element_wise(PADDING)).into(),
+                left_first: first as u32,
+                max: (bounds.max.add_element_wise(PADDING)).into(),
+                count: count as u32,
+            };
+            if count <= MAX_LEAF_SIZE || depth >= MAX_DEPTH {
+                continue;
+            }
+            let split = match partition(&faces, range, &bounds) {
+                Some(split) => split,
+                None => continue,
+            };
+            let left = nodes.len();
+            nodes.push(BvhNode::zeroed());
+            nodes.push(BvhNode::zeroed());
+            nodes[node].left_first = left as u32;
+            nodes[node].count = 0;
+            pending.push((left, first, split, depth + 1));
+            pending.push((left + 1, first + split, count - split, depth + 1));
+        }
+        if faces.is_empty() {
+            nodes[0] = BvhNode::zeroed();
+        }
+
+        let indices = order
+            .iter()
+            .flat_map(|&face| indices[3 * face..3 * face + 3].iter().copied())
+            .collect();
+        Self { nodes, indices }
+    }
+}
+
+impl BvhNode {
+    fn zeroed() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+}
+
+/// Partitions `range` along the plane with the lowest surface area heuristic cost
+/// and returns the number of faces in front of the plane, or `None` if a leaf is cheaper.
+/// Nodes with more than `MAX_SAH_LEAF_SIZE` faces are always split to keep the traversal cheap.
+fn partition(faces: &[Bounds], range: &mut [usize], bounds: &Bounds) -> Option<usize> {
+    let mut centroid_bounds = Bounds::empty();
+    for &face in range.iter() {
+        centroid_bounds.grow(faces[face].center());
+    }
+
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        let lower = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - lower;
+        if extent <= 0.0 {
+            continue;
+        }
+        let bin_of = |face: usize| {
+            let bin = ((faces[face].center()[axis] - lower) / extent * BINS as f32) as usize;
+            bin.min(BINS - 1)
+        };
+
+        let mut bin_bounds = [Bounds::empty(); BINS];
+        let mut bin_counts = [0; BINS];
+        for &face in range.iter() {
+            let bin = bin_of(face);
+            bin_bounds[bin].union(&faces[face]);
+            bin_counts[bin] += 1;
+        }
+
+        // Sweep from the right to get the cost of the faces behind every plane
+        let mut right_costs = [0.0; BINS];
+        let mut right_bounds = Bounds::empty();
+        let mut right_count = 0;
+        for bin in (1..BINS).rev() {
+            right_bounds.union(&bin_bounds[bin]);
+            right_count += bin_counts[bin];
+            right_costs[bin] = right_bounds.area() * right_count as f32;
+        }
+        let mut left_bounds = Bounds::empty();
+        let mut left_count = 0;
+        for plane in 1..BINS {
+            left_bounds.union(&bin_bounds[plane - 1]);
+            left_count += bin_counts[plane - 1];
+            let cost = left_bounds.area() * left_count as f32 + right_costs[plane];
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, plane));
+            }
+        }
+    }
+
+    let (cost, axis, plane) = best?;
+    if range.len() <= MAX_SAH_LEAF_SIZE && cost >= bounds.area() * range.len() as f32 {
+        return None;
+    }
+    let lower = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - lower;
+    let is_left = |face: usize| {
+        let bin = ((faces[face].center()[axis] - lower) / extent * BINS as f32) as usize;
+        bin.min(BINS - 1) < plane
+    };
+
+    // Partition in place, faces in front of the plane first
+    let mut split = 0;
+    for i in 0..range.len() {
+        if is_left(range[i]) {
+            range.swap(i, split);
+            split += 1;
+        }
+    }
+    if split == 0 || split == range.len() {
+        return None;
+    }
+    Some(split)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_integrator::{hit_triangle, Vec3};
+    use cgmath::InnerSpace;
+
+    /// Triangles of random size and orientation scattered in the cube [-1, 1]^3.
+    fn triangle_soup(count: usize) -> (Vec<f32>, Vec<u32>) {
+        // Linear congruential generator, so that the mesh is the same in every run
+        let mut state = 12345u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let mut vertices = Vec::new();
+        for _ in 0..count {
+            let center = [random(), random(), random()];
+            for _ in 0..3 {
+                for c in center {
+                    vertices.push(c + 0.1 * random());
+                }
+            }
+        }
+        (vertices, (0..3 * count as u32).collect())
+    }
+
+    fn triangle(vertices: &[f32], face: &[u32]) -> [Vec3; 3] {
+        let vertex = |i: u32| {
+            let i = i as usize;
+            Vector3::new(vertices[3 * i], vertices[3 * i + 1], vertices[3 * i + 2])
+                .cast()
+                .unwrap()
+        };
+        [vertex(face[0]), vertex(face[1]), vertex(face[2])]
+    }
+
+    /// Calls `f` with every node and its parent and depth.
+    fn visit(bvh: &Bvh, mut f: impl FnMut(&BvhNode, Option<&BvhNode>, usize)) {
+        let mut stack = vec![(0, None, 0)];
+        while let Some((node, parent, depth)) = stack.pop() {
+            let current = &bvh.nodes[node];
+            f(
+                current,
+                parent.map(|parent: usize| &bvh.nodes[parent]),
+                depth,
+            );
+            if current.count == 0 {
+                let left = current.left_first as usize;
+                stack.push((left, Some(node), depth + 1));
+                stack.push((left + 1, Some(node), depth + 1));
+            }
+        }
+    }
+
+    fn hit_box(node: &BvhNode, origin: Vec3, direction: Vec3) -> bool {
+        let mut t_min = 0.0f64;
+        let mut t_max = f64::INFINITY;
+        for axis in 0..3 {
+            let t1 = (node.min[axis] as f64 - origin[axis]) / direction[axis];
+            let t2 = (node.max[axis] as f64 - origin[axis]) / direction[axis];
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+        }
+        t_min <= t_max
+    }
+
+    /// Distance to the closest face along the ray, found by traversing the tree.
+    fn closest_hit(bvh: &Bvh, vertices: &[f32], origin: Vec3, direction: Vec3) -> Option<f64> {
+        let mut closest = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &bvh.nodes[node];
+            if !hit_box(node, origin, direction) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.left_first as usize);
+                stack.push(node.left_first as usize + 1);
+                continue;
+            }
+            let first = node.left_first as usize;
+            for face in bvh.indices[3 * first..3 * (first + node.count as usize)].chunks(3) {
+                let t = hit_triangle(&triangle(vertices, face), origin, direction, false);
+                if t > 0.0 && closest.is_none_or(|closest| t < closest) {
+                    closest = Some(t);
+                }
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn every_face_once() {
+        let (vertices, indices) = triangle_soup(300);
+        let bvh = Bvh::build(&vertices, &indices);
+        let mut faces: Vec<&[u32]> = bvh.indices.chunks(3).collect();
+        faces.sort();
+        let expected: Vec<&[u32]> = indices.chunks(3).collect();
+        assert_eq!(faces, expected);
+
+        // The leaves cover every face exactly once
+        let mut covered = vec![0; indices.len() / 3];
+        visit(&bvh, |node, _, _| {
+            let first = node.left_first as usize;
+            for count in &mut covered[first..first + node.count as usize] {
+                *count += 1;
+            }
+        });
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn nested_boxes_within_depth() {
+        let (vertices, indices) = triangle_soup(300);
+        let bvh = Bvh::build(&vertices, &indices);
+        assert!(bvh.nodes.len() > 1);
+        visit(&bvh, |node, parent, depth| {
+            assert!(depth <= MAX_DEPTH);
+            if let Some(parent) = parent {
+                for axis in 0..3 {
+                    assert!(node.min[axis] >= parent.min[axis]);
+                    assert!(node.max[axis] <= parent.max[axis]);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn traversal_matches_brute_force() {
+        let (vertices, indices) = triangle_soup(300);
+        let bvh = Bvh::build(&vertices, &indices);
+        let origin = Vec3::new(0.1, 0.2, 3.0);
+        let mut hits = 0;
+        for y in 0..20 {
+            for x in 0..20 {
+                let direction = (Vec3::new(x as f64 / 10.0 - 1.0, y as f64 / 10.0 - 1.0, 0.0)
+                    - origin)
+                    .normalize();
+                let brute_force = indices
+                    .chunks(3)
+                    .map(|face| hit_triangle(&triangle(&vertices, face), origin, direction, false))
+                    .filter(|&t| t > 0.0)
+                    .min_by(|a, b| a.total_cmp(b));
+                assert_eq!(
+                    closest_hit(&bvh, &vertices, origin, direction),
+                    brute_force,
+                    "direction {:?}",
+                    direction
+                );
+                hits += brute_force.is_some() as usize;
+            }
+        }
+        assert!(hits > 50, "only {} rays hit the mesh", hits);
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let bvh = Bvh::build(&[], &[]);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert_eq!(bvh.nodes[0].count, 0);
+        assert_eq!(bvh.nodes[0].left_first, 0);
+        assert!(bvh.indices.is_empty());
+    }
+}
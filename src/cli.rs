@@ -0,0 +1,389 @@
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::application::{Background, OverlayMode};
+use crate::arcball::CameraPose;
+use crate::functions::{FieldMode, Presets, CUSTOM_FUNCTION};
+use crate::integrator::IntegrationScheme;
+use crate::library::{Library, LibraryFunction};
+use crate::session::{model_center, Model, Session};
+use crate::vertices::load_obj;
+use crate::volume::{Outside, VectorFieldOptions, VolumeSpace};
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+/// Interactive exploration of nonlinear ray casting
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Args {
+    /// Session file to open; the other options override its values
+    #[arg(long, value_name = "JSON")]
+    pub session: Option<PathBuf>,
+
+    /// OBJ model to load instead of the Cornell box
+    #[arg(long, value_name = "OBJ")]
+    pub model: Option<PathBuf>,
+
+    /// Volumetric scalar field sampled by `sample_field`, a legacy VTK or raw volume file
+    #[arg(long, value_name = "FILE")]
+    pub volume: Option<PathBuf>,
+
+    /// Vector field sampled by `sample_vector_field`, a legacy VTK file with `VECTORS`
+    /// or a raw volume file with 3 components
+    #[arg(long, value_name = "FILE")]
+    pub vector_field: Option<PathBuf>,
+
+    /// Coordinates of the vector field grid, `scene` or `model` (before normalization)
+    #[arg(long, value_name = "SPACE")]
+    pub vector_field_space: Option<VolumeSpace>,
+
+    /// Vector field value outside of its grid: `zero`, `clamp` or `fallback`
+    #[arg(long, value_name = "MODE")]
+    pub vector_field_outside: Option<Outside>,
+
+    /// Predefined field function, e.g. `mirage-plane` or `lorenz`, or a function of the library
+    #[arg(long, value_name = "NAME", conflicts_with = "function_file")]
+    pub function: Option<String>,
+
+    /// Additional preset manifest, presets with an existing identifier replace it
+    #[arg(long, value_name = "JSON")]
+    pub presets: Vec<PathBuf>,
+
+    /// File containing the body of a custom field function
+    #[arg(long, value_name = "WGSL")]
+    pub function_file: Option<PathBuf>,
+
+    /// What the body of the function file computes: `velocity`, `refractive-index` or `acceleration`
+    #[arg(long, value_name = "MODE", requires = "function_file")]
+    pub field_mode: Option<FieldMode>,
+
+    /// Directory of the user function library, defaults to `linon/functions` in the config directory
+    #[arg(long, value_name = "DIR")]
+    pub library: Option<PathBuf>,
+
+    /// Weight of the field function, between 0 (linear) and 1 (nonlinear)
+    #[arg(long, value_name = "WEIGHT")]
+    pub field_weight: Option<f32>,
+
+    /// Overlay mode, either `disabled` or `lyapunov`
+    #[arg(long, value_name = "MODE")]
+    pub overlay: Option<OverlayMode>,
+
+    /// Color of rays that leave the scene: `black`, `checkerboard` or `stars`
+    #[arg(long, value_name = "BACKGROUND")]
+    pub background: Option<Background>,
+
+    /// Integration scheme: `euler`, `midpoint`, `rk4`, `rk45` or `leapfrog`
+    #[arg(long, value_name = "SCHEME")]
+    pub integrator: Option<IntegrationScheme>,
+
+    /// Animation time in seconds returned by `animation_time()`
+    #[arg(long, value_name = "SECONDS", allow_hyphen_values = true)]
+    pub time: Option<f32>,
+
+    /// Point the main view camera orbits around, as `x,y,z`
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_vector::<f32, 3>, allow_hyphen_values = true)]
+    pub camera_center: Option<[f32; 3]>,
+
+    /// Rotation of the main view camera as quaternion `w,x,y,z`
+    #[arg(long, value_name = "W,X,Y,Z", value_parser = parse_vector::<f32, 4>, allow_hyphen_values = true)]
+    pub camera_rotation: Option<[f32; 4]>,
+
+    /// Distance between the main view camera and its center
+    #[arg(long, value_name = "DISTANCE")]
+    pub camera_distance: Option<f32>,
+
+    /// Window width, or image width in headless mode
+    #[arg(long, default_value_t = 1400)]
+    pub width: u32,
+
+    /// Window height, or image height in headless mode
+    #[arg(long, default_value_t = 900)]
+    pub height: u32,
+
+    /// Compute shader to watch for changes
+    #[arg(long, value_name = "WGSL", default_value = "src/main_view.wgsl")]
+    pub watch: PathBuf,
+
+    /// Render the main view once without a window and save it to this PNG file
+    #[arg(long, value_name = "PNG")]
+    pub headless: Option<PathBuf>,
+
+    /// Also export the end points, hit distances, hit colors and Lyapunov exponents of the
+    /// headless render as OpenEXR file (.exr) or as NumPy arrays (.npy)
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    pub export_rays: Option<PathBuf>,
+
+    /// Compare every STRIDE-th pixel of the headless render with the CPU reference integrator
+    #[arg(long, value_name = "STRIDE", requires = "headless")]
+    pub validate: Option<u32>,
+
+    /// Print the CPU reference integration of a single pixel of the headless render
+    #[arg(long, value_name = "X,Y", value_parser = parse_vector::<u32, 2>, requires = "headless")]
+    pub trace_pixel: Option<[u32; 2]>,
+}
+
+impl Args {
+    /// The built-in presets extended by the user presets and the manifests given by `--presets`.
+    pub fn presets(&self) -> Result<Presets> {
+        let mut presets = Presets::with_user_presets()?;
+        for path in &self.presets {
+            presets.load(path)?;
+        }
+        Ok(presets)
+    }
+
+    /// The library given by `--library` or the default library.
+    pub fn library(&self) -> Library {
+        Library::open(self.library.clone().unwrap_or_else(Library::default_dir))
+    }
+
+    /// Applies the options on top of `current`, the state before they are applied, in the order
+    /// in which they override each other: the session replaces `current`, then the model, the
+    /// fields and the function are replaced, where a predefined function brings its recommended
+    /// settings, then the keyframe of the timeline at `--time` and finally the individual
+    /// settings and the camera options are applied.
+    ///
+    /// The application and the headless renderer both start from the result, so that a command
+    /// line shows the same view in either.
+    pub fn resolve(&self, current: Session, presets: &Presets, library: &Library) -> Resolved {
+        // Options that fail are reported together, the others are still applied
+        let mut errors = Vec::new();
+        let mut session = match self.session.as_deref().map(Session::load) {
+            Some(Ok(session)) => session,
+            Some(Err(e)) => {
+                errors.push(e);
+                current
+            }
+            None => current,
+        };
+        if let Some(path) = &self.model {
+            match load_obj(path) {
+                Ok((vertices, indices)) => {
+                    let model = Model { vertices, indices };
+                    // Loading a model resets the cameras to its center
+                    let camera = CameraPose::initial(model_center(Some(&model)));
+                    session.model = Some(model);
+                    session.main_camera = camera;
+                    session.reference_camera = camera;
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(path) = &self.volume {
+            session.volume = Some(path.clone());
+        }
+        if let Some(path) = &self.vector_field {
+            session.vector_field = Some(path.clone());
+        }
+        session.vector_field_options = self.vector_field_options(session.vector_field_options);
+
+        let mut library_function = None;
+        if let Some(id) = &self.function {
+            if id == CUSTOM_FUNCTION {
+                errors.push(anyhow!("use --function-file for custom field functions"));
+            } else if let Some(function) = presets.get(id) {
+                session.predefined_function = function.id.clone();
+                if !function.prelude.is_empty() {
+                    session.prelude = function.prelude.clone();
+                }
+                session.field_function = function.body.clone();
+                session.field_mode = function.mode;
+                session.parameters.clear();
+                let settings = &function.settings;
+                if let Some(field_weight) = settings.field_weight {
+                    session.field_weight = field_weight;
+                }
+                if let Some(overlay_mode) = settings.overlay_mode {
+                    session.overlay_mode = overlay_mode;
+                }
+                if let Some(background) = settings.background {
+                    session.background = background;
+                }
+                if let Some(scheme) = settings.integrator {
+                    session.integrator.scheme = scheme;
+                }
+                if let Some(t_max) = settings.t_max {
+                    session.integrator.t_max = t_max;
+                }
+                if let Some(pose) = settings.camera {
+                    session.main_camera = pose;
+                }
+            } else if let Some(function) = library.get(id) {
+                session.predefined_function = CUSTOM_FUNCTION.to_string();
+                session.prelude = function.prelude.clone();
+                session.field_function = function.body.clone();
+                session.field_mode = function.mode;
+                session.parameters = function.parameters.clone();
+                library_function = Some(function.clone());
+            } else {
+                let mut names = vec![presets.ids()];
+                names.extend(library.functions().map(|f| f.name.clone()));
+                errors.push(anyhow!(
+                    "unknown function {:?}, possible values: {}",
+                    id,
+                    names.join(", ")
+                ));
+            }
+        }
+        match self.function_file() {
+            Ok(Some(field_function)) => {
+                session.predefined_function = CUSTOM_FUNCTION.to_string();
+                session.field_function = field_function;
+                session.field_mode = self.field_mode.unwrap_or_default();
+                session.parameters.clear();
+            }
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+
+        if let Some(time) = self.time {
+            session.clock.time = time;
+            if let Some(keyframe) = session.timeline.sample(time) {
+                session.field_weight = keyframe.field_weight;
+                session.parameters.extend(keyframe.parameters);
+                session.main_camera = keyframe.camera;
+            }
+        }
+        if let Some(field_weight) = self.field_weight {
+            session.field_weight = field_weight;
+        }
+        if let Some(overlay_mode) = self.overlay {
+            session.overlay_mode = overlay_mode;
+        }
+        if let Some(background) = self.background {
+            session.background = background;
+        }
+        if let Some(scheme) = self.integrator {
+            session.integrator.scheme = scheme;
+        }
+        session.main_camera = self.camera_pose(session.main_camera);
+        Resolved {
+            session,
+            library_function,
+            errors,
+        }
+    }
+
+    /// Returns the body of the field function in the file given by `--function-file`, if any.
+    pub fn function_file(&self) -> Result<Option<String>> {
+        match &self.function_file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("reading {} failed", path.display()))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Applies the camera options on top of `pose`.
+    pub fn camera_pose(&self, pose: CameraPose<f32>) -> CameraPose<f32> {
+        CameraPose {
+            center: self.camera_center.unwrap_or(pose.center),
+            rotation: self.camera_rotation.unwrap_or(pose.rotation),
+            distance: self.camera_distance.unwrap_or(pose.distance),
+        }
+    }
+
+    /// Applies the vector field options on top of `options`.
+    pub fn vector_field_options(&self, options: VectorFieldOptions) -> VectorFieldOptions {
+        VectorFieldOptions {
+            space: self.vector_field_space.unwrap_or(options.space),
+            outside: self.vector_field_outside.unwrap_or(options.outside),
+        }
+    }
+}
+
+/// The state the command line asks for, see [`Args::resolve`].
+pub struct Resolved {
+    pub session: Session,
+    /// Function of the library selected by `--function`
+    pub library_function: Option<LibraryFunction>,
+    /// Options that could not be applied, the others are included in `session`
+    pub errors: Vec<anyhow::Error>,
+}
+
+fn parse_vector<T, const N: usize>(s: &str) -> Result<[T; N], String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<T>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    values
+        .try_into()
+        .map_err(|_| format!("expected {} comma-separated values", N))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::Keyframe;
+    use std::collections::BTreeMap;
+
+    fn resolve(args: &[&str], current: Session) -> Resolved {
+        let args = Args::parse_from(std::iter::once("linon").chain(args.iter().copied()));
+        let presets = args.presets().unwrap();
+        let library = Library::open(std::env::temp_dir().join("linon-cli-no-library"));
+        args.resolve(current, &presets, &library)
+    }
+
+    fn with_keyframes() -> Session {
+        let mut session = Session::initial();
+        for (time, field_weight) in [(0.0, 0.0), (2.0, 1.0)] {
+            session.timeline.insert(Keyframe {
+                time,
+                camera: CameraPose {
+                    center: [0.0; 3],
+                    rotation: [1.0, 0.0, 0.0, 0.0],
+                    distance: 1.0 + field_weight,
+                },
+                field_weight,
+                parameters: BTreeMap::from([("k".to_string(), [field_weight; 4])]),
+            });
+        }
+        session
+    }
+
+    #[test]
+    fn time_applies_keyframe() {
+        let resolved = resolve(&["--time", "2.0"], with_keyframes());
+        assert!(resolved.errors.is_empty());
+        let session = resolved.session;
+        assert_eq!(session.clock.time, 2.0);
+        assert_eq!(session.field_weight, 1.0);
+        assert_eq!(session.parameters["k"], [1.0; 4]);
+        assert_eq!(session.main_camera.distance, 2.0);
+    }
+
+    #[test]
+    fn options_override_keyframe() {
+        let resolved = resolve(
+            &[
+                "--time",
+                "2.0",
+                "--field-weight",
+                "0.5",
+                "--camera-distance",
+                "3",
+            ],
+            with_keyframes(),
+        );
+        let session = resolved.session;
+        assert_eq!(session.field_weight, 0.5);
+        assert_eq!(session.parameters["k"], [1.0; 4]);
+        assert_eq!(session.main_camera.distance, 3.0);
+    }
+
+    #[test]
+    fn time_without_keyframes() {
+        let initial = Session::initial();
+        let session = resolve(&["--time", "2.0"], initial.clone()).session;
+        assert_eq!(session.clock.time, 2.0);
+        assert_eq!(session.field_weight, initial.field_weight);
+        assert_eq!(session.main_camera.distance, initial.main_camera.distance);
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// Global animation time passed to the shader as `settings.time`, independent of the
+/// integration time `t` of the rays, so that fields can change while they are explored.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Clock {
+    /// Current time in seconds
+    pub time: f32,
+    /// Seconds of animation time per second of wall-clock time
+    pub speed: f32,
+    /// Range the time is wrapped into while playing
+    pub loop_start: f32,
+    pub loop_end: f32,
+    /// Not stored in sessions, which always open paused
+    #[serde(skip)]
+    pub playing: bool,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            speed: 1.0,
+            loop_start: 0.0,
+            loop_end: 10.0,
+            playing: false,
+        }
+    }
+}
+
+impl Clock {
+    /// Advances the time by `dt` seconds of wall-clock time if playing, wrapping it into the
+    /// loop range. Returns whether the time has changed.
+    pub fn advance(&mut self, dt: f32) -> bool {
+        if !self.playing || dt <= 0.0 {
+            return false;
+        }
+        self.time += dt * self.speed;
+        let length = self.loop_end - self.loop_start;
+        if length > 0.0 && (self.time < self.loop_start || self.time >= self.loop_end) {
+            self.time = self.loop_start + (self.time - self.loop_start).rem_euclid(length);
+        }
+        true
+    }
+}
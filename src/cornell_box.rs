@@ -161,3 +161,14 @@ pub const INDICES: [u32; 108] = [
     68, 69, 70,
     70, 71, 68,
 ];
+
+/// Returns the Cornell box vertices with x and z axis inverted,
+/// so that the box opening faces the initial camera.
+pub fn inverted_vertices() -> [f32; 216] {
+    let mut vertices = VERTICES;
+    for i in 0..(vertices.len() / 3) {
+        vertices[3 * i] = -vertices[3 * i];
+        vertices[3 * i + 2] = -vertices[3 * i + 2];
+    }
+    vertices
+}
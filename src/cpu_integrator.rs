@@ -0,0 +1,645 @@
+//! CPU reference implementation of the ray integration in `main_view.wgsl`.
+//!
+//! Every function mirrors its WGSL counterpart step by step, but computes in `f64`.
+//! This allows validating the GPU output and debugging single pixels without a GPU.
+
+use crate::arcball::{ArcballCamera, CameraPose};
+use crate::integrator::{IntegrationScheme, IntegratorSettings};
+use cgmath::{ElementWise, InnerSpace, Matrix3, Vector3, Vector4};
+
+pub type Vec3 = Vector3<f64>;
+
+/// A field function with the same parameters as in WGSL: `(p_prev, p, v0, v, t)`.
+pub type FieldFunction = dyn Fn(Vec3, Vec3, Vec3, Vec3, f64) -> Vec3;
+
+const EPS: f64 = 0.0000001;
+const LIGHT_COLOR: Vector3<f64> = Vector3::new(1.0, 1.0, 1.0);
+const AMBIENT_STRENGTH: f64 = 0.01;
+const SHININESS: f64 = 64.0;
+
+/// Shader switches and the settings that affect the integration.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub field_weight: f64,
+    pub adaptive_sampling: bool,
+    pub backface_culling: bool,
+    pub use_lighting: bool,
+    pub integrator: IntegratorSettings,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            field_weight: 1.0,
+            adaptive_sampling: true,
+            backface_culling: false,
+            use_lighting: true,
+            integrator: IntegratorSettings::default(),
+        }
+    }
+}
+
+/// A normalized triangle mesh, i.e. the contents of the vertices and faces buffers.
+pub struct Mesh {
+    vertices: Vec<Vec3>,
+    faces: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    pub fn new(vertices: &[f32], indices: &[u32]) -> Self {
+        Self {
+            vertices: vertices
+                .chunks_exact(3)
+                .map(|v| Vector3::new(v[0], v[1], v[2]).cast().unwrap())
+                .collect(),
+            faces: indices
+                .chunks_exact(3)
+                .map(|f| [f[0] as usize, f[1] as usize, f[2] as usize])
+                .collect(),
+        }
+    }
+}
+
+/// The camera as it is passed to the compute shader.
+pub struct Camera {
+    pub origin: Vec3,
+    pub view_direction: Vec3,
+    pub up: Vec3,
+}
+
+impl Camera {
+    pub fn from_pose(pose: CameraPose<f32>) -> Self {
+        let mut camera = ArcballCamera::new(Vector3::new(0.0, 0.0, 0.0), 1.0, [1.0, 1.0]);
+        camera.set_pose(pose);
+        Self {
+            origin: camera.eye_pos().cast().unwrap(),
+            view_direction: camera.eye_dir().cast().unwrap(),
+            up: camera.up_dir().cast().unwrap(),
+        }
+    }
+
+    /// Direction of the primary ray through pixel `(x, y)`, with `y = 0` being the top row.
+    pub fn ray_direction(&self, x: u32, y: u32, width: u32, height: u32) -> Vec3 {
+        let width = width as f64;
+        let height = height as f64;
+        let aspect_ratio = width / height;
+        let focal_length = 0.035;
+        let viewport_height = 0.025;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let w = -self.view_direction;
+        let horizontal = self.up.cross(w);
+        let vertical = w.cross(horizontal);
+
+        // The shader flips the image vertically when storing the color
+        let gid_y = height - 1.0 - y as f64;
+        let u = x as f64 / (width - 1.0) * viewport_width - 0.5 * viewport_width;
+        let v = gid_y / (height - 1.0) * viewport_height - 0.5 * viewport_height;
+        let s = u * horizontal.normalize()
+            + v * vertical.normalize()
+            + focal_length * self.view_direction;
+        s.normalize()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NonlinearRayColorResult {
+    pub color: Vector4<f64>,
+    /// End point of the ray and the length of the path to the first hit, 0 if nothing is hit
+    pub mapping_point: Vector4<f64>,
+}
+
+/// State of a ray after an accepted integration step.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub t: f64,
+    pub h: f64,
+    pub point: Vec3,
+    pub dir: Vec3,
+}
+
+pub struct Integrator<'a> {
+    pub mesh: &'a Mesh,
+    pub field_function: &'a FieldFunction,
+    pub options: Options,
+}
+
+impl<'a> Integrator<'a> {
+    /// Closest hit of the straight ray segment, with the hit distance in the alpha channel.
+    pub fn ray_color(&self, origin: Vec3, direction: Vec3, max_dist: f64) -> Vector4<f64> {
+        let mut t = -1.0;
+        let mut d1 = Vector3::new(0.0, 0.0, 0.0);
+        let mut d2 = Vector3::new(0.0, 0.0, 0.0);
+        for face in &self.mesh.faces {
+            let triangle = [
+                self.mesh.vertices[face[0]],
+                self.mesh.vertices[face[1]],
+                self.mesh.vertices[face[2]],
+            ];
+            let t_new = hit_triangle(&triangle, origin, direction, self.options.backface_culling);
+            if t_new > 0.0 && t_new < max_dist && (t < 0.0 || t_new < t) {
+                t = t_new;
+                d1 = triangle[1] - triangle[0];
+                d2 = triangle[2] - triangle[0];
+            }
+        }
+        if t > 0.0 && t < max_dist {
+            let normal = d1.cross(d2).normalize();
+            let abs_normal = Vector3::new(normal.x.abs(), normal.y.abs(), normal.z.abs());
+            if !self.options.use_lighting {
+                return abs_normal.extend(t);
+            }
+            let ambient = AMBIENT_STRENGTH * LIGHT_COLOR;
+            // The camera is the light source here, which allows for
+            // some simplifications
+            let mut intensity = normal.dot(-direction).max(0.0);
+            if intensity == 0.0 {
+                intensity = (-normal).dot(-direction).max(0.0);
+            }
+            let diffuse = intensity * LIGHT_COLOR;
+            let specular = intensity.powf(SHININESS) * LIGHT_COLOR;
+            let result = (ambient + diffuse + specular).mul_element_wise(abs_normal);
+            return result.extend(t);
+        }
+        Vector4::new(0.0, 0.0, 0.0, 0.0)
+    }
+
+    pub fn nonlinear_ray_color(
+        &self,
+        start_point: Vec3,
+        start_dir: Vec3,
+    ) -> NonlinearRayColorResult {
+        self.integrate(start_point, start_dir, |_| {})
+    }
+
+    /// Like [`Integrator::nonlinear_ray_color`], but also returns every accepted step.
+    pub fn trace(
+        &self,
+        start_point: Vec3,
+        start_dir: Vec3,
+    ) -> (NonlinearRayColorResult, Vec<Step>) {
+        let mut steps = Vec::new();
+        let result = self.integrate(start_point, start_dir, |step| steps.push(step));
+        (result, steps)
+    }
+
+    fn integrate(
+        &self,
+        start_point: Vec3,
+        start_dir: Vec3,
+        mut on_step: impl FnMut(Step),
+    ) -> NonlinearRayColorResult {
+        let field_weight = self.options.field_weight;
+        let mut color = Vector4::new(0.0, 0.0, 0.0, 0.0);
+        let mut has_color = false;
+        let mut cur_point = start_point;
+        let mut cur_dir = start_dir;
+        // Accumulated in single precision like in the shader,
+        // so that both terminate after the same number of steps
+        let mut t: f32 = 0.0;
+        let mut last_v = Vector3::new(0.0, 0.0, 0.0);
+        let mut last_diff = -1.0;
+        let mut path_length = 0.0;
+        let mut hit_distance = 0.0;
+        let integrator = self.options.integrator;
+        let h_initial = integrator.h_initial as f64;
+        let h_min = integrator.h_min as f64;
+        let mut h = h_initial;
+        if integrator.scheme == IntegrationScheme::Rk45 {
+            h = clamp(h, h_min, integrator.h_max as f64);
+        }
+
+        let mut steps = 0;
+        while t <= integrator.t_max && steps < integrator.max_steps {
+            steps += 1;
+            let v;
+            let mut h_next = h;
+            if integrator.scheme == IntegrationScheme::Rk45 {
+                let (v5, error) = self.rk45(cur_point, start_dir, cur_dir, t as f64, h);
+                h_next = self.next_step_size(h, error);
+                if error > 1.0 && h > h_min {
+                    // Reject the step and retry with the smaller step size
+                    h = h_next;
+                    continue;
+                }
+                v = v5;
+            } else {
+                v = self.fixed_step(cur_point, start_dir, cur_dir, t as f64, h);
+                // Heuristic step size control for the fixed step schemes
+                let diff = (v - last_v).magnitude();
+                if self.options.adaptive_sampling
+                    && last_diff >= 0.0
+                    && diff > 10.0 * last_diff
+                    && h > 2.0 * h_min
+                {
+                    h = h_min;
+                    continue;
+                }
+                if 2.0 * diff < last_diff && h < h_initial {
+                    h_next = h_initial;
+                }
+                last_diff = diff;
+            }
+            let next_dir = (1.0 - field_weight) * cur_dir + field_weight * v;
+
+            let step_dir = h * next_dir;
+            if !has_color {
+                color = self.ray_color(cur_point, step_dir.normalize(), step_dir.magnitude());
+                has_color = color.w > 0.0;
+                if has_color {
+                    hit_distance = path_length + color.w;
+                }
+                path_length += step_dir.magnitude();
+            }
+            cur_dir = next_dir;
+
+            cur_point += step_dir;
+            t += h as f32;
+            on_step(Step {
+                t: t as f64,
+                h,
+                point: cur_point,
+                dir: cur_dir,
+            });
+            last_v = v;
+            h = h_next;
+        }
+
+        color.w = 1.0;
+        if !has_color {
+            color = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        }
+        NonlinearRayColorResult {
+            color,
+            mapping_point: cur_point.extend(hit_distance),
+        }
+    }
+
+    /// Step of one of the schemes without error control.
+    fn fixed_step(&self, p: Vec3, v0: Vec3, v: Vec3, t: f64, h: f64) -> Vec3 {
+        let f = self.field_function;
+        match self.options.integrator.scheme {
+            IntegrationScheme::Euler => f(p, p, v0, v, t),
+            IntegrationScheme::Midpoint => {
+                let k1 = f(p, p, v0, v, t);
+                f(p, p + 0.5 * h * k1, v0, k1, t + 0.5 * h)
+            }
+            // Velocity at the midpoint predicted with the velocity of the previous half step
+            IntegrationScheme::Leapfrog => f(p, p + 0.5 * h * v, v0, v, t + 0.5 * h),
+            IntegrationScheme::Rk4 | IntegrationScheme::Rk45 => {
+                let k1 = f(p, p, v0, v, t);
+                let k2 = f(p, p + 0.5 * h * k1, v0, k1, t + 0.5 * h);
+                let k3 = f(p, p + 0.5 * h * k2, v0, k2, t + 0.5 * h);
+                let k4 = f(p, p + h * k3, v0, k3, t + h);
+                (k1 + 2.0 * k2 + 2.0 * k3 + k4) / 6.0
+            }
+        }
+    }
+
+    /// Dormand-Prince 5(4) step, returns the 5th order solution and the local
+    /// position error relative to the tolerances.
+    fn rk45(&self, p: Vec3, v0: Vec3, v: Vec3, t: f64, h: f64) -> (Vec3, f64) {
+        let f = self.field_function;
+        let k1 = f(p, p, v0, v, t);
+        let k2 = f(p, p + h * (1.0 / 5.0 * k1), v0, k1, t + 1.0 / 5.0 * h);
+        let k3 = f(
+            p,
+            p + h * (3.0 / 40.0 * k1 + 9.0 / 40.0 * k2),
+            v0,
+            k2,
+            t + 3.0 / 10.0 * h,
+        );
+        let k4 = f(
+            p,
+            p + h * (44.0 / 45.0 * k1 - 56.0 / 15.0 * k2 + 32.0 / 9.0 * k3),
+            v0,
+            k3,
+            t + 4.0 / 5.0 * h,
+        );
+        let k5 = f(
+            p,
+            p + h
+                * (19372.0 / 6561.0 * k1 - 25360.0 / 2187.0 * k2 + 64448.0 / 6561.0 * k3
+                    - 212.0 / 729.0 * k4),
+            v0,
+            k4,
+            t + 8.0 / 9.0 * h,
+        );
+        let k6 = f(
+            p,
+            p + h
+                * (9017.0 / 3168.0 * k1 - 355.0 / 33.0 * k2
+                    + 46732.0 / 5247.0 * k3
+                    + 49.0 / 176.0 * k4
+                    - 5103.0 / 18656.0 * k5),
+            v0,
+            k5,
+            t + h,
+        );
+        let v5 = 35.0 / 384.0 * k1 + 500.0 / 1113.0 * k3 + 125.0 / 192.0 * k4
+            - 2187.0 / 6784.0 * k5
+            + 11.0 / 84.0 * k6;
+        let k7 = f(p, p + h * v5, v0, k6, t + h);
+        let v4 = 5179.0 / 57600.0 * k1 + 7571.0 / 16695.0 * k3 + 393.0 / 640.0 * k4
+            - 92097.0 / 339200.0 * k5
+            + 187.0 / 2100.0 * k6
+            + 1.0 / 40.0 * k7;
+
+        // Only the weighted part of the field contributes to the position
+        let integrator = self.options.integrator;
+        let delta = h * self.options.field_weight * (v5 - v4);
+        let p_new = p + h * v5;
+        let mut error: f64 = 0.0;
+        for i in 0..3 {
+            let scale = integrator.abs_tol as f64
+                + integrator.rel_tol as f64 * p[i].abs().max(p_new[i].abs());
+            error = error.max(delta[i].abs() / scale);
+        }
+        (v5, error)
+    }
+
+    /// Step size for the next step of an adaptive scheme of order 5.
+    fn next_step_size(&self, h: f64, error: f64) -> f64 {
+        let integrator = self.options.integrator;
+        let factor = clamp(0.9 * error.max(0.0001).powf(-0.2), 0.2, 5.0);
+        clamp(h * factor, integrator.h_min as f64, integrator.h_max as f64)
+    }
+}
+
+/// Moeller-Trumbore intersection algorithm, returns -1 if the triangle is missed.
+pub fn hit_triangle(
+    v_in: &[Vec3; 3],
+    origin: Vec3,
+    direction: Vec3,
+    backface_culling: bool,
+) -> f64 {
+    let edge1 = v_in[1] - v_in[0];
+    let edge2 = v_in[2] - v_in[0];
+    let h = direction.cross(edge2);
+    let a = edge1.dot(h);
+    if (backface_culling || a > -EPS) && a < EPS {
+        return -1.0;
+    }
+    let f = 1.0 / a;
+    let s = origin - v_in[0];
+    let u = f * s.dot(h);
+    if !(0.0..=1.0).contains(&u) {
+        return -1.0;
+    }
+    let q = s.cross(edge1);
+    let v = f * direction.dot(q);
+    if v < 0.0 || u + v > 1.0 {
+        return -1.0;
+    }
+    let t = f * edge2.dot(q);
+    if t > EPS {
+        return t;
+    }
+    -1.0
+}
+
+// Helper functions from `main_view.wgsl` used by the predefined field functions
+
+/// WGSL `clamp(e, low, high)`, which is `min(max(e, low), high)` even if `low > high`.
+pub fn clamp(e: f64, low: f64, high: f64) -> f64 {
+    e.max(low).min(high)
+}
+
+pub fn rotate_z(v: Vec3, phi: f64) -> Vec3 {
+    Matrix3::from_cols(
+        Vector3::new(phi.cos(), phi.sin(), 0.0),
+        Vector3::new(-phi.sin(), phi.cos(), 0.0),
+        Vector3::new(0.0, 0.0, 0.0),
+    ) * v
+}
+
+pub fn translate(v: Vec3, dx: f64, dy: f64, dz: f64) -> Vec3 {
+    Vector3::new(v.x + dx, v.y + dy, v.z + dz)
+}
+
+/// `t`: temperature in Celsius
+pub fn refraction_index(t: f64) -> f64 {
+    // Calculation term by Y. Zhao et al
+    let air_pressure = 101325.0;
+    let c1 = 0.0000104;
+    let c2 = 0.00366;
+    c1 * air_pressure * (1.0 + air_pressure * (60.1 - 0.972 * t) * 10f64.powf(-10.0))
+        / (1.0 + c2 * t)
+}
+
+pub fn refraction(t_in: f64, t_out: f64, v_in: Vec3, n: Vec3) -> Vec3 {
+    let eta_in = refraction_index(t_in);
+    let eta_out = refraction_index(t_out);
+    let mut cosi = clamp(-1.0, 1.0, v_in.dot(n));
+    let mut n_ref = n;
+    if cosi < 0.0 {
+        cosi = -cosi;
+    } else {
+        n_ref = -n;
+    }
+    let eta = eta_in / eta_out;
+    let k = 1.0 - eta * eta * (1.0 - cosi * cosi);
+    if k < 0.0 {
+        // total reflection
+        return reflect(v_in, n_ref);
+    }
+    eta * v_in + (eta * cosi - k.sqrt()) * n_ref
+}
+
+pub fn reflect(e1: Vec3, e2: Vec3) -> Vec3 {
+    e1 - 2.0 * e2.dot(e1) * e2
+}
+
+pub fn point_plane_distance(p: Vec3, n: Vec3, p0: Vec3) -> f64 {
+    let d = p0.dot(n);
+    // assuming n is a unit vector
+    (p.dot(n) - d).abs()
+}
+
+pub fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: [Vec3; 3] = [
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+    ];
+    const DOWN: Vec3 = Vector3::new(0.0, 0.0, -1.0);
+    const UP: Vec3 = Vector3::new(0.0, 0.0, 1.0);
+
+    #[test]
+    fn hit_triangle_front() {
+        for culling in [false, true] {
+            let t = hit_triangle(&TRIANGLE, Vector3::new(0.25, 0.25, 1.0), DOWN, culling);
+            assert!((t - 1.0).abs() < 1e-12, "t = {}", t);
+        }
+    }
+
+    #[test]
+    fn hit_triangle_back() {
+        let origin = Vector3::new(0.25, 0.25, -2.0);
+        assert!((hit_triangle(&TRIANGLE, origin, UP, false) - 2.0).abs() < 1e-12);
+        assert_eq!(hit_triangle(&TRIANGLE, origin, UP, true), -1.0);
+    }
+
+    #[test]
+    fn hit_triangle_misses() {
+        // Outside of the triangle
+        assert_eq!(
+            hit_triangle(&TRIANGLE, Vector3::new(0.75, 0.75, 1.0), DOWN, false),
+            -1.0
+        );
+        assert_eq!(
+            hit_triangle(&TRIANGLE, Vector3::new(-0.1, 0.5, 1.0), DOWN, false),
+            -1.0
+        );
+        // Parallel to the plane of the triangle
+        let parallel = Vector3::new(1.0, 0.0, 0.0);
+        assert_eq!(
+            hit_triangle(&TRIANGLE, Vector3::new(-1.0, 0.25, 0.0), parallel, false),
+            -1.0
+        );
+        // Triangle behind the origin
+        assert_eq!(
+            hit_triangle(&TRIANGLE, Vector3::new(0.25, 0.25, -1.0), DOWN, false),
+            -1.0
+        );
+    }
+
+    #[test]
+    fn hit_triangle_edges() {
+        for (x, y) in [(0.5, 0.0), (0.0, 0.5), (0.5, 0.5), (0.0, 0.0)] {
+            let t = hit_triangle(&TRIANGLE, Vector3::new(x, y, 1.0), DOWN, false);
+            assert!((t - 1.0).abs() < 1e-12, "({}, {}): t = {}", x, y, t);
+        }
+    }
+
+    fn settings(scheme: IntegrationScheme, h: f32) -> IntegratorSettings {
+        IntegratorSettings {
+            scheme,
+            t_max: 1.0,
+            h_initial: h,
+            h_min: h / 10.0,
+            h_max: 0.1,
+            max_steps: 100_000,
+            ..IntegratorSettings::default()
+        }
+    }
+
+    /// Integrates from `start` without a mesh and returns the end point and the integration time.
+    fn integrate(
+        field_function: &FieldFunction,
+        integrator: IntegratorSettings,
+        start: Vec3,
+        dir: Vec3,
+    ) -> (Vec3, f64) {
+        let mesh = Mesh::new(&[], &[]);
+        let integrator = Integrator {
+            mesh: &mesh,
+            field_function,
+            options: Options {
+                adaptive_sampling: false,
+                integrator,
+                ..Options::default()
+            },
+        };
+        let (result, steps) = integrator.trace(start, dir);
+        assert!(!steps.is_empty());
+        assert_eq!(result.mapping_point.w, 0.0);
+        let t = steps.iter().map(|step| step.h).sum();
+        (result.mapping_point.truncate(), t)
+    }
+
+    #[test]
+    fn translation_is_exact() {
+        let velocity = Vector3::new(0.3, -0.2, 0.1);
+        let field = move |_, _, _, _, _| velocity;
+        let start = Vector3::new(0.1, 0.2, 0.3);
+        for scheme in IntegrationScheme::ALL {
+            let (end, t) = integrate(&field, settings(scheme, 0.01), start, velocity);
+            let expected = start + t * velocity;
+            assert!(
+                (end - expected).magnitude() < 1e-9,
+                "{}: {:?} != {:?}",
+                scheme,
+                end,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_follows_circle() {
+        // Rotation around the z axis with unit angular velocity, starting on the unit circle
+        let field = |_, p: Vec3, _, _, _| Vector3::new(-p.y, p.x, 0.0);
+        let start = Vector3::new(1.0, 0.0, 0.0);
+        let dir = Vector3::new(0.0, 1.0, 0.0);
+        let cases = [
+            (IntegrationScheme::Euler, 1e-3),
+            (IntegrationScheme::Midpoint, 1e-6),
+            (IntegrationScheme::Leapfrog, 1e-6),
+            (IntegrationScheme::Rk4, 1e-10),
+            (IntegrationScheme::Rk45, 1e-3),
+        ];
+        for (scheme, tolerance) in cases {
+            let (end, t) = integrate(&field, settings(scheme, 0.001), start, dir);
+            let expected = Vector3::new(t.cos(), t.sin(), 0.0);
+            let error = (end - expected).magnitude();
+            assert!(
+                error < tolerance,
+                "{}: error {} at t = {}",
+                scheme,
+                error,
+                t
+            );
+        }
+    }
+
+    #[test]
+    fn second_order_convergence() {
+        let field = |_, p: Vec3, _, _, _| Vector3::new(-p.y, p.x, 0.0);
+        let start = Vector3::new(1.0, 0.0, 0.0);
+        let dir = Vector3::new(0.0, 1.0, 0.0);
+        let error = |scheme, h| {
+            let (end, t) = integrate(&field, settings(scheme, h), start, dir);
+            (end - Vector3::new(t.cos(), t.sin(), 0.0)).magnitude()
+        };
+        for scheme in [IntegrationScheme::Midpoint, IntegrationScheme::Leapfrog] {
+            // A tenth of the step size gives a hundredth of the error
+            let ratio = error(scheme, 0.01) / error(scheme, 0.001);
+            assert!(ratio > 70.0 && ratio < 130.0, "{}: ratio {}", scheme, ratio);
+        }
+    }
+
+    #[test]
+    fn next_step_size_is_clamped() {
+        let mesh = Mesh::new(&[], &[]);
+        let field = |_, _, _, v, _| v;
+        let integrator = Integrator {
+            mesh: &mesh,
+            field_function: &field,
+            options: Options {
+                integrator: IntegratorSettings {
+                    h_min: 0.005,
+                    h_max: 0.02,
+                    ..IntegratorSettings::default()
+                },
+                ..Options::default()
+            },
+        };
+        let h_min = 0.005f32 as f64;
+        let h_max = 0.02f32 as f64;
+        // Tiny errors grow the step at most by a factor of 5, here limited by h_max
+        assert_eq!(integrator.next_step_size(0.01, 1e-12), h_max);
+        assert_eq!(integrator.next_step_size(0.01, 0.0), h_max);
+        // Huge errors shrink it at most by a factor of 5, here limited by h_min
+        assert_eq!(integrator.next_step_size(0.01, 1e12), h_min);
+        // In between the step is scaled by the safety factor
+        assert!((integrator.next_step_size(0.01, 1.0) - 0.009).abs() < 1e-12);
+    }
+}
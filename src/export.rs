@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+/// Writes `image` as PNG file with `text` as international text chunks, so that the field
+/// function and the settings an image was rendered with are kept next to the pixels.
+pub fn save_png(path: &Path, image: &image::RgbaImage, text: &[(&str, String)]) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("creating {} failed", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, value) in text {
+        encoder
+            .add_itxt_chunk(keyword.to_string(), value.clone())
+            .with_context(|| format!("adding {} to {} failed", keyword, path.display()))?;
+    }
+    let mut writer = encoder
+        .write_header()
+        .with_context(|| format!("writing {} failed", path.display()))?;
+    writer
+        .write_image_data(image.as_raw())
+        .with_context(|| format!("writing {} failed", path.display()))?;
+    writer
+        .finish()
+        .with_context(|| format!("writing {} failed", path.display()))
+}
+
+/// Per-pixel results of the main view for offline analysis, in rows from top to bottom.
+pub struct RayData {
+    pub width: u32,
+    pub height: u32,
+    /// Color of the first hit or the background
+    pub colors: Vec<[u8; 4]>,
+    /// End point of the ray and the length of the path to the first hit, 0 if nothing is hit
+    pub mapping: Vec<[f32; 4]>,
+    /// Largest singular value of the gradient of the end points with respect to the pixel
+    /// position as shown by the Lyapunov overlay, NaN at the border where it is not computed
+    pub exponents: Vec<f32>,
+}
+
+/// Writes `data` as OpenEXR file or as NumPy arrays depending on the extension of `path`, with
+/// `metadata` as header attributes or as JSON file next to the arrays. Returns the written files.
+///
+/// An `.exr` file has the channels `R`, `G`, `B` (hit color), `mapping.X`, `mapping.Y`,
+/// `mapping.Z` (end point), `distance` and `exponent`. For `path` = `rays.npy`, the arrays are
+/// written to `rays_colors.npy` (`u8`, height × width × 4), `rays_mapping.npy` (`f32`, height ×
+/// width × 4 with the distance last) and `rays_exponents.npy` (`f32`, height × width), and the
+/// metadata to `rays.json`.
+pub fn save_ray_data(
+    path: &Path,
+    data: &RayData,
+    metadata: &[(&str, String)],
+) -> Result<Vec<PathBuf>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("exr") => {
+            save_exr(path, data, metadata)?;
+            Ok(vec![path.to_path_buf()])
+        }
+        Some("npy") => save_npy_arrays(path, data, metadata),
+        _ => bail!("unknown format of {}, use .exr or .npy", path.display()),
+    }
+}
+
+fn save_exr(path: &Path, data: &RayData, metadata: &[(&str, String)]) -> Result<()> {
+    use exr::prelude::*;
+
+    let color = |i: usize| data.colors.iter().map(|c| c[i] as f32 / 255.0).collect();
+    let mapping = |i: usize| data.mapping.iter().map(|m| m[i]).collect();
+    let channel = |name: &str, samples: Vec<f32>| AnyChannel::new(name, FlatSamples::F32(samples));
+    let channels = AnyChannels::sort(SmallVec::from_vec(vec![
+        channel("R", color(0)),
+        channel("G", color(1)),
+        channel("B", color(2)),
+        channel("mapping.X", mapping(0)),
+        channel("mapping.Y", mapping(1)),
+        channel("mapping.Z", mapping(2)),
+        channel("distance", mapping(3)),
+        channel("exponent", data.exponents.clone()),
+    ]));
+    let mut layer = Layer::new(
+        (data.width as usize, data.height as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        channels,
+    );
+    for (key, value) in metadata {
+        // Text attributes are stored as bytes, which readers usually decode as UTF-8
+        layer.attributes.other.insert(
+            Text::from_slice_unchecked(key.as_bytes()),
+            AttributeValue::Text(Text::from_slice_unchecked(value.as_bytes())),
+        );
+    }
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .with_context(|| format!("writing {} failed", path.display()))
+}
+
+fn save_npy_arrays(
+    path: &Path,
+    data: &RayData,
+    metadata: &[(&str, String)],
+) -> Result<Vec<PathBuf>> {
+    let stem = path.with_extension("");
+    let sibling = |suffix: &str| {
+        let mut name = stem.clone().into_os_string();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    let (width, height) = (data.width, data.height);
+    let colors: Vec<u8> = data.colors.iter().flatten().copied().collect();
+    let mapping: Vec<u8> = data
+        .mapping
+        .iter()
+        .flatten()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let exponents: Vec<u8> = data
+        .exponents
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let arrays = vec![
+        ("_colors.npy", "|u1", vec![height, width, 4], colors),
+        ("_mapping.npy", "<f4", vec![height, width, 4], mapping),
+        ("_exponents.npy", "<f4", vec![height, width], exponents),
+    ];
+    let mut paths = Vec::new();
+    for (suffix, descr, shape, bytes) in arrays {
+        let path = sibling(suffix);
+        write_npy(&path, descr, &shape, &bytes)
+            .with_context(|| format!("writing {} failed", path.display()))?;
+        paths.push(path);
+    }
+
+    let path = stem.with_extension("json");
+    let metadata: BTreeMap<_, _> = metadata.iter().cloned().collect();
+    let json = serde_json::to_string_pretty(&metadata)?;
+    std::fs::write(&path, json).with_context(|| format!("writing {} failed", path.display()))?;
+    paths.push(path);
+    Ok(paths)
+}
+
+/// Writes an array in version 1.0 of the NumPy format, see
+/// <https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>.
+fn write_npy(path: &Path, descr: &str, shape: &[u32], bytes: &[u8]) -> Result<()> {
+    let dimensions: Vec<_> = shape.iter().map(u32::to_string).collect();
+    let mut shape = dimensions.join(", ");
+    // A tuple with one element needs a trailing comma
+    if dimensions.len() == 1 {
+        shape.push(',');
+    }
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({}), }}",
+        descr, shape
+    );
+    // The data starts at a multiple of 64 bytes after the magic string, version and header length
+    let unpadded_len = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded_len % 64) % 64));
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(bytes)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npy_header() {
+        let dir = std::env::temp_dir().join(format!("linon-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("array.npy");
+        let data: Vec<u8> = (0..2 * 3 * 4).map(|i| i as u8).collect();
+        write_npy(&path, "|u1", &[2, 3, 4], &data).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(
+            header.starts_with("{'descr': '|u1', 'fortran_order': False, 'shape': (2, 3, 4), }")
+        );
+        assert!(header.ends_with('\n'));
+        assert_eq!(bytes.len(), 10 + header_len + data.len());
+        assert_eq!(&bytes[10 + header_len..], &data[..]);
+    }
+
+    #[test]
+    fn npy_one_dimensional_shape() {
+        let path = std::env::temp_dir().join(format!("linon-export-{}.npy", std::process::id()));
+        let data: Vec<u8> = [1.0f32, 2.0, 3.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        write_npy(&path, "<f4", &[3], &data).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '<f4'"));
+        assert!(header.contains("'shape': (3,)"));
+        assert_eq!(bytes.len(), 10 + header_len + 12);
+    }
+}
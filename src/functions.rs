@@ -1,129 +1,453 @@
-#[derive(Debug, PartialEq)]
-pub enum PredefinedFunction {
-    Custom,
-    MirageSpherical,
-    MiragePlane,
-    MirageSphericalSigmoid,
-    MiragePlaneSigmoid,
-    TranslationX,
-    TranslationZ,
-    Rotation,
-    LorenzAttractor,
-    RoesslerAttractor,
+//! Predefined field functions, described by the preset manifest `presets.json` that is embedded
+//! into the binary and can be extended with manifests on disk.
+
+use crate::application::{Background, OverlayMode};
+use crate::arcball::CameraPose;
+use crate::cpu_integrator::{
+    clamp, point_plane_distance, refraction, rotate_z, sigmoid, translate, FieldFunction, Vec3,
+};
+use crate::integrator::IntegrationScheme;
+use crate::parameters;
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::{Context, Result};
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fmt::Display;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::str::FromStr;
+
+/// The manifest of the built-in presets.
+const MANIFEST: &str = include_str!("presets.json");
+
+/// Identifier of the function shown on startup.
+pub const DEFAULT_FUNCTION: &str = "mirage-spherical-sigmoid";
+
+/// Identifier stored in sessions for functions that are not predefined.
+pub const CUSTOM_FUNCTION: &str = "custom";
+
+/// Body of the function the main view is created with.
+pub fn default_field_function() -> String {
+    Presets::builtin()
+        .get(DEFAULT_FUNCTION)
+        .expect("the default function is not a built-in preset")
+        .body
+        .clone()
 }
 
-impl ToString for PredefinedFunction {
-    fn to_string(&self) -> String {
+/// What the body of a field function computes, which decides the equation the rays follow.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum FieldMode {
+    /// The velocity of the ray as `vec3<f32>` from `p_prev`, `p`, `v0`, `v` and `t`
+    #[default]
+    #[serde(rename = "velocity")]
+    Velocity,
+    /// The refractive index as `f32` from `p`, rays follow the ray equation of gradient-index optics
+    #[serde(rename = "refractive-index")]
+    RefractiveIndex,
+    /// The acceleration of the ray as `vec3<f32>` from `p`, `v` and `t`, for geodesics and forces
+    #[serde(rename = "acceleration")]
+    Acceleration,
+}
+
+impl FieldMode {
+    pub const ALL: [FieldMode; 3] = [Self::Velocity, Self::RefractiveIndex, Self::Acceleration];
+
+    /// Name of the function in `main_view.wgsl` that the body is inserted into.
+    pub fn function_name(&self) -> &'static str {
         match self {
-            Self::Custom => "Custom".to_string(),
-            Self::MirageSpherical => "Mirage (spherical)".to_string(),
-            Self::MiragePlane => "Mirage (plane)".to_string(),
-            Self::MirageSphericalSigmoid => "Mirage (spherical sigmoid)".to_string(),
-            Self::MiragePlaneSigmoid => "Mirage (plane sigmoid)".to_string(),
-            Self::TranslationX => "Translation (x-axis)".to_string(),
-            Self::TranslationZ => "Translation (z-axis)".to_string(),
-            Self::Rotation => "Rotation".to_string(),
-            Self::LorenzAttractor => "Lorenz attractor".to_string(),
-            Self::RoesslerAttractor => "Roessler attractor".to_string(),
+            Self::Velocity => "field_function",
+            Self::RefractiveIndex => "refractive_index",
+            Self::Acceleration => "acceleration",
         }
     }
 }
 
+impl Display for FieldMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::Velocity => "Velocity field",
+            Self::RefractiveIndex => "Refractive index (GRIN)",
+            Self::Acceleration => "Acceleration",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for FieldMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "velocity" => Ok(Self::Velocity),
+            "refractive-index" => Ok(Self::RefractiveIndex),
+            "acceleration" => Ok(Self::Acceleration),
+            _ => Err("possible values: velocity, refractive-index, acceleration".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    presets: Vec<PredefinedFunction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PredefinedFunction {
+    /// Short identifier used on the command line and in sessions
+    pub id: String,
+    /// Name shown in the user interface
+    pub name: String,
+    /// Heading the function is listed under in the user interface
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub description: String,
+    /// Helper code inserted before the field function, replaces the current prelude if not empty
+    #[serde(default, deserialize_with = "code")]
+    pub prelude: String,
+    #[serde(deserialize_with = "code")]
+    pub body: String,
+    /// What the body computes, a velocity unless given
+    #[serde(default)]
+    pub mode: FieldMode,
+    #[serde(default)]
+    pub settings: RecommendedSettings,
+    /// Whether the function comes from the embedded manifest, see [`PredefinedFunction::to_closure`]
+    #[serde(skip)]
+    builtin: bool,
+}
+
+/// Settings applied together with a predefined function, unset values are left unchanged.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecommendedSettings {
+    pub field_weight: Option<f32>,
+    pub overlay_mode: Option<OverlayMode>,
+    /// Color of rays that leave the scene
+    pub background: Option<Background>,
+    pub integrator: Option<IntegrationScheme>,
+    /// Integration time after which a ray is terminated
+    pub t_max: Option<f32>,
+    /// Pose of the main view camera
+    pub camera: Option<CameraPose<f32>>,
+}
+
+/// Code is written either as a single string or as an array of lines in the manifest.
+fn code<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Code {
+        Text(String),
+        Lines(Vec<String>),
+    }
+    Ok(match Code::deserialize(deserializer)? {
+        Code::Text(text) => text,
+        Code::Lines(lines) => lines.join("\n"),
+    })
+}
+
+/// The list of predefined functions in manifest order.
+#[derive(Debug, Clone)]
+pub struct Presets {
+    functions: Vec<PredefinedFunction>,
+}
+
+impl Presets {
+    /// The presets of the embedded manifest.
+    pub fn builtin() -> Self {
+        let manifest: Manifest =
+            serde_json::from_str(MANIFEST).expect("parsing embedded presets.json failed");
+        let functions = manifest
+            .presets
+            .into_iter()
+            .map(|function| PredefinedFunction {
+                builtin: true,
+                ..function
+            })
+            .collect();
+        Self { functions }
+    }
+
+    /// Adds the presets of a manifest on disk, replacing presets with the same identifier.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("reading {} failed", path.display()))?;
+        let manifest: Manifest = serde_json::from_str(&data)
+            .with_context(|| format!("parsing {} failed", path.display()))?;
+        for function in manifest.presets {
+            if function.id == CUSTOM_FUNCTION {
+                anyhow::bail!(
+                    "{}: the identifier {:?} is reserved",
+                    path.display(),
+                    CUSTOM_FUNCTION
+                );
+            }
+            match self.functions.iter_mut().find(|f| f.id == function.id) {
+                Some(existing) => *existing = function,
+                None => self.functions.push(function),
+            }
+        }
+        Ok(())
+    }
+
+    /// The built-in presets extended by `presets.json` in the config directory, if it exists.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_user_presets() -> Result<Self> {
+        let mut presets = Self::builtin();
+        let path = crate::library::config_dir().join("presets.json");
+        if path.exists() {
+            presets.load(&path)?;
+        }
+        Ok(presets)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&PredefinedFunction> {
+        self.functions.iter().find(|f| f.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PredefinedFunction> {
+        self.functions.iter()
+    }
+
+    /// Categories in the order of their first preset.
+    pub fn categories(&self) -> Vec<&str> {
+        let mut categories: Vec<&str> = Vec::new();
+        for function in &self.functions {
+            if !categories.contains(&function.category.as_str()) {
+                categories.push(&function.category);
+            }
+        }
+        categories
+    }
+
+    /// Comma separated identifiers, for error messages.
+    pub fn ids(&self) -> String {
+        self.functions
+            .iter()
+            .map(|f| f.id.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
 impl PredefinedFunction {
-    pub fn to_code(&self) -> String {
-        match self {
-            Self::Custom => "".to_string(),
-            Self::MirageSpherical => "let t_env = 15.0; // °C
-let t_src = 200.0; // °C
-let max_dist = 0.25;
-let center = vec3<f32>(-0.5, 0.5, -0.5);
-
-let center_dest = p - center;
-let normal = normalize(center_dest);
-let dist_in = length(p_prev - center);
-let dist_out = length(center_dest);
-let part_in = clamp(0.0, 1.0, dist_in / max_dist);
-let part_out = clamp(0.0, 1.0, dist_out / max_dist);
-let t_in = part_in * t_env + (1.0 - part_in) * t_src;
-let t_out = part_out * t_env + (1.0 - part_out) * t_src;
-
-return refraction(t_in, t_out, v, normal);"
-                .to_string(),
-            Self::MiragePlane => "let t_env = 15.0; // °C
-let t_src = 30.0; // °C
-let max_dist = 0.01;
-let plane_p0 = vec3<f32>(0.0, 0.1, 0.0);
-let plane_n = vec3<f32>(0.0, 1.0, 0.0);
-
-let dist_in = point_plane_distance(p_prev, plane_n, plane_p0);
-let dist_out = point_plane_distance(p, plane_n, plane_p0);
-let part_in = clamp(0.0, 1.0, dist_in / max_dist);
-let part_out = clamp(0.0, 1.0, dist_out / max_dist);
-let t_in = part_in * t_env + (1.0 - part_in) * t_src;
-let t_out = part_out * t_env + (1.0 - part_out) * t_src;
-
-return refraction(t_in, t_out, v, plane_n);"
-                .to_string(),
-            Self::MirageSphericalSigmoid => "let t_env = 15.0; // °C
-let t_src = 200.0; // °C
-let max_dist = 0.25;
-let center = vec3<f32>(-0.5, 0.5, -0.5);
-
-let center_dest = p - center;
-let normal = normalize(center_dest);
-let dist_in = length(p_prev - center);
-let dist_out = length(center_dest);
-let part_in = clamp(0.0, 1.0, sigmoid(dist_in / max_dist * 12.0 - 6.0));
-let part_out = clamp(0.0, 1.0, sigmoid(dist_out / max_dist * 12.0 - 6.0));
-let t_in = part_in * t_env + (1.0 - part_in) * t_src;
-let t_out = part_out * t_env + (1.0 - part_out) * t_src;
-
-return refraction(t_in, t_out, v, normal);"
-                .to_string(),
-            Self::MiragePlaneSigmoid => "let t_env = 15.0; // °C
-let t_src = 30.0; // °C
-let max_dist = 0.01;
-let plane_p0 = vec3<f32>(0.0, 0.1, 0.0);
-let plane_n = vec3<f32>(0.0, 1.0, 0.0);
-
-let dist_in = point_plane_distance(p_prev, plane_n, plane_p0);
-let dist_out = point_plane_distance(p, plane_n, plane_p0);
-let part_in = clamp(0.0, 1.0, sigmoid(dist_in / max_dist * 12.0 - 6.0));
-let part_out = clamp(0.0, 1.0, sigmoid(dist_out / max_dist * 12.0 - 6.0));
-let t_in = part_in * t_env + (1.0 - part_in) * t_src;
-let t_out = part_out * t_env + (1.0 - part_out) * t_src;
-
-return refraction(t_in, t_out, v, plane_n);"
-                .to_string(),
-            Self::TranslationX => "let dx = 0.5 * t;
-let dy = 0.0;
-let dz = 0.0;
-return translate(v0, dx, dy, dz);"
-                .to_string(),
-            Self::TranslationZ => "let dx = 0.0;
-let dy = 0.0;
-let dz = 0.5 * t;
-return translate(v0, dx, dy, dz);"
-                .to_string(),
-            Self::Rotation => "return normalize(v0 + rotateZ(v0, PI * t));".to_string(),
-            Self::LorenzAttractor => "let rho = 28.0;
-let sigma = 10.0;
-let beta = 8.0 / 3.0;
-return vec3<f32>(
-    sigma * (p.y - p.x),
-    p.x * (rho - p.z) - p.y,
-    p.x * p.y - beta * p.z,
-);"
-            .to_string(),
-            Self::RoesslerAttractor => "let a = 0.1;
-let b = 0.1;
-let c = 14.0;
-return vec3<f32>(
-    -p.y - p.z,
-    p.x + a * p.y,
-    b + p.z * (p.x - c),
-);"
-            .to_string(),
+    /// The function as Rust closure for the CPU reference integrator, with the parameters set to
+    /// `values` or to their defaults where no value is given.
+    /// Returns `None` for functions without a Rust counterpart, including built-in presets
+    /// replaced by a manifest on disk.
+    pub fn to_closure(&self, values: &BTreeMap<String, [f32; 4]>) -> Option<Box<FieldFunction>> {
+        if !self.builtin {
+            return None;
         }
+        let mut parameters = parameters::parse(&self.body).ok()?;
+        parameters::set_values(&mut parameters, values);
+        let value = |name: &str| {
+            let value = parameters.iter().find(|p| p.name == name)?.value;
+            Some(Vector3::new(
+                value[0] as f64,
+                value[1] as f64,
+                value[2] as f64,
+            ))
+        };
+        let scalar = |name: &str| value(name).map(|v| v.x);
+        let mirage = || {
+            Some(Mirage {
+                t_env: scalar("t_env")?,
+                t_src: scalar("t_src")?,
+                max_dist: scalar("max_dist")?,
+            })
+        };
+        let function: Box<FieldFunction> = match self.id.as_str() {
+            "mirage-spherical" => {
+                let (mirage, center) = (mirage()?, value("center")?);
+                Box::new(move |p_prev, p, _v0, v, _t| {
+                    mirage.spherical(p_prev, p, v, center, |dist, max_dist| dist / max_dist)
+                })
+            }
+            "mirage-plane" => {
+                let (mirage, plane_p0, plane_n) =
+                    (mirage()?, value("plane_p0")?, value("plane_n")?);
+                Box::new(move |p_prev, p, _v0, v, _t| {
+                    mirage.plane(p_prev, p, v, plane_p0, plane_n, |dist, max_dist| {
+                        dist / max_dist
+                    })
+                })
+            }
+            "mirage-spherical-sigmoid" => {
+                let (mirage, center) = (mirage()?, value("center")?);
+                Box::new(move |p_prev, p, _v0, v, _t| {
+                    mirage.spherical(p_prev, p, v, center, |dist, max_dist| {
+                        sigmoid(dist / max_dist * 12.0 - 6.0)
+                    })
+                })
+            }
+            "mirage-plane-sigmoid" => {
+                let (mirage, plane_p0, plane_n) =
+                    (mirage()?, value("plane_p0")?, value("plane_n")?);
+                Box::new(move |p_prev, p, _v0, v, _t| {
+                    mirage.plane(p_prev, p, v, plane_p0, plane_n, |dist, max_dist| {
+                        sigmoid(dist / max_dist * 12.0 - 6.0)
+                    })
+                })
+            }
+            "translation-x" => {
+                let speed = scalar("speed")?;
+                Box::new(move |_p_prev, _p, v0, _v, t| translate(v0, speed * t, 0.0, 0.0))
+            }
+            "translation-z" => {
+                let speed = scalar("speed")?;
+                Box::new(move |_p_prev, _p, v0, _v, t| translate(v0, 0.0, 0.0, speed * t))
+            }
+            "rotation" => {
+                Box::new(|_p_prev, _p, v0, _v, t| (v0 + rotate_z(v0, PI * t)).normalize())
+            }
+            "lorenz" => {
+                let (rho, sigma, beta) = (scalar("rho")?, scalar("sigma")?, scalar("beta")?);
+                Box::new(move |_p_prev, p: Vec3, _v0, _v, _t| {
+                    Vector3::new(
+                        sigma * (p.y - p.x),
+                        p.x * (rho - p.z) - p.y,
+                        p.x * p.y - beta * p.z,
+                    )
+                })
+            }
+            "roessler" => {
+                let (a, b, c) = (scalar("a")?, scalar("b")?, scalar("c")?);
+                Box::new(move |_p_prev, p: Vec3, _v0, _v, _t| {
+                    Vector3::new(-p.y - p.z, p.x + a * p.y, b + p.z * (p.x - c))
+                })
+            }
+            _ => return None,
+        };
+        Some(function)
+    }
+}
+
+/// Parameters shared by the mirage functions.
+struct Mirage {
+    /// Temperature of the environment
+    t_env: f64,
+    /// Temperature at the heat source
+    t_src: f64,
+    /// Distance from the source at which the environment temperature is reached
+    max_dist: f64,
+}
+
+impl Mirage {
+    /// Shared body of the spherical mirage functions, `part` maps a distance to the blend factor.
+    fn spherical(
+        &self,
+        p_prev: Vec3,
+        p: Vec3,
+        v: Vec3,
+        center: Vec3,
+        part: impl Fn(f64, f64) -> f64,
+    ) -> Vec3 {
+        let center_dest = p - center;
+        let normal = center_dest.normalize();
+        let dist_in = (p_prev - center).magnitude();
+        let dist_out = center_dest.magnitude();
+        self.refraction(
+            v,
+            normal,
+            part(dist_in, self.max_dist),
+            part(dist_out, self.max_dist),
+        )
+    }
+
+    /// Shared body of the plane mirage functions, `part` maps a distance to the blend factor.
+    fn plane(
+        &self,
+        p_prev: Vec3,
+        p: Vec3,
+        v: Vec3,
+        plane_p0: Vec3,
+        plane_n: Vec3,
+        part: impl Fn(f64, f64) -> f64,
+    ) -> Vec3 {
+        let dist_in = point_plane_distance(p_prev, plane_n, plane_p0);
+        let dist_out = point_plane_distance(p, plane_n, plane_p0);
+        self.refraction(
+            v,
+            plane_n,
+            part(dist_in, self.max_dist),
+            part(dist_out, self.max_dist),
+        )
+    }
+
+    /// Refracts `v` between the temperatures blended by `part_in` and `part_out`.
+    fn refraction(&self, v: Vec3, normal: Vec3, part_in: f64, part_out: f64) -> Vec3 {
+        let part_in = clamp(0.0, 1.0, part_in);
+        let part_out = clamp(0.0, 1.0, part_out);
+        let t_in = part_in * self.t_env + (1.0 - part_in) * self.t_src;
+        let t_out = part_out * self.t_env + (1.0 - part_out) * self.t_src;
+
+        refraction(t_in, t_out, v, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The identifiers matched in [`PredefinedFunction::to_closure`].
+    const CLOSURE_IDS: [&str; 9] = [
+        "mirage-spherical",
+        "mirage-plane",
+        "mirage-spherical-sigmoid",
+        "mirage-plane-sigmoid",
+        "translation-x",
+        "translation-z",
+        "rotation",
+        "lorenz",
+        "roessler",
+    ];
+
+    #[test]
+    fn builtin_presets_contain_default() {
+        let presets = Presets::builtin();
+        let default = presets.get(DEFAULT_FUNCTION).unwrap();
+        assert_eq!(default.mode, FieldMode::Velocity);
+        assert!(presets.iter().all(|f| f.builtin));
+    }
+
+    #[test]
+    fn closures_match_builtin_presets() {
+        let presets = Presets::builtin();
+        for id in CLOSURE_IDS.iter() {
+            let function = presets
+                .get(id)
+                .unwrap_or_else(|| panic!("{} is not in the manifest", id));
+            assert!(function.to_closure(&BTreeMap::new()).is_some(), "{}", id);
+        }
+        for function in presets.iter() {
+            let has_closure = function.to_closure(&BTreeMap::new()).is_some();
+            assert_eq!(has_closure, CLOSURE_IDS.contains(&function.id.as_str()));
+        }
+    }
+
+    #[test]
+    fn closures_use_parameter_values() {
+        let presets = Presets::builtin();
+        let function = presets.get("translation-x").unwrap();
+        let v0 = Vector3::new(0.0, 0.0, 1.0);
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let default = function.to_closure(&BTreeMap::new()).unwrap();
+        assert_eq!(
+            default(zero, zero, v0, v0, 2.0),
+            Vector3::new(1.0, 0.0, 1.0)
+        );
+        let values = vec![("speed".to_string(), [-0.25, 0.0, 0.0, 0.0])]
+            .into_iter()
+            .collect();
+        let changed = function.to_closure(&values).unwrap();
+        assert_eq!(
+            changed(zero, zero, v0, v0, 2.0),
+            Vector3::new(-0.5, 0.0, 1.0)
+        );
     }
 }
@@ -0,0 +1,307 @@
+use std::path::Path;
+
+use crate::application::combine_errors;
+use crate::bvh::Bvh;
+use crate::cli::Args;
+use crate::cpu_integrator::{Camera, Integrator, Mesh, Options, Vec3};
+use crate::export;
+use crate::main_view::MainView;
+use crate::parameters;
+use crate::session::{model_mesh, Session};
+use crate::vertices::{get_center, normalize_vertices};
+use crate::volume::Volume;
+use anyhow::{bail, Context, Result};
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+/// Requests an adapter that does not need to present to a surface.
+/// Falls back to a software adapter if no hardware adapter is available.
+async fn request_adapter(instance: &wgpu::Instance) -> Result<wgpu::Adapter> {
+    if let Some(adapter) = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })
+        .await
+    {
+        return Ok(adapter);
+    }
+    println!("No hardware adapter found, trying fallback adapter");
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: true,
+        })
+        .await
+        .context("no compatible adapter found")
+}
+
+/// Renders the main view once without a window and writes the result to `path`.
+pub async fn render_to_png(args: &Args, path: &Path) -> Result<()> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+    let adapter = request_adapter(&instance).await?;
+    let info = adapter.get_info();
+    println!("Using adapter {} ({:?})", info.name, info.backend);
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::default(),
+                required_limits: wgpu::Limits::default(),
+                memory_hints: wgpu::MemoryHints::default(),
+            },
+            None,
+        )
+        .await?;
+
+    let presets = args.presets()?;
+    let resolved = args.resolve(Session::initial(), &presets, &args.library());
+    combine_errors(resolved.errors)?;
+    let session = resolved.session;
+    let (mut vertices, indices) = model_mesh(session.model.as_ref());
+    let normalization = normalize_vertices(&mut vertices);
+    let bvh = Bvh::build(&vertices, &indices);
+    let vertices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("vertices_buffer"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let faces_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("faces_buffer"),
+        contents: bytemuck::cast_slice(&bvh.indices),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("bvh_buffer"),
+        contents: bytemuck::cast_slice(&bvh.nodes),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let ray_samples_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("ray_samples_buffer"),
+        size: std::mem::size_of::<[[[[f32; 4]; 2]; 100]; 8]>() as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let mut main_view = MainView::new(
+        &device,
+        vertices_buffer.as_entire_binding(),
+        faces_buffer.as_entire_binding(),
+        bvh_buffer.as_entire_binding(),
+        get_center(&vertices),
+        ray_samples_buffer.as_entire_binding(),
+        args.width,
+        args.height,
+        1,
+        info.backend,
+    );
+    main_view
+        .reload_shader(
+            &device,
+            &queue,
+            None,
+            session.prelude.clone(),
+            session.field_function.clone(),
+            session.field_mode,
+        )
+        .context("compiling field function failed")?;
+    main_view.set_parameter_values(&queue, &session.parameters);
+    if let Some(path) = &session.volume {
+        main_view.set_scalar_field(&device, &queue, Some(&Volume::load(path)?))?;
+    }
+    if let Some(path) = &session.vector_field {
+        let options = session.vector_field_options;
+        main_view.set_vector_field(
+            &device,
+            &queue,
+            Some(&Volume::load(path)?),
+            options.mapping(normalization),
+            options.outside,
+        )?;
+    }
+    main_view.update_settings(&queue, session.settings());
+    let pose = session.main_camera;
+    main_view.set_camera_pose(&queue, pose);
+    let image = main_view
+        .render_to_image(&device, &queue)
+        .context("reading back main view texture failed")?;
+    let mut metadata = main_view.image_metadata();
+    metadata.push(("Camera", serde_json::to_string(&pose)?));
+    export::save_png(path, &image, &metadata)?;
+    println!("Saved main view to {}", path.display());
+    if let Some(path) = &args.export_rays {
+        let data = main_view.render_ray_data(&device, &queue, main_view.dimensions())?;
+        for path in export::save_ray_data(path, &data, &metadata)? {
+            println!("Saved ray data to {}", path.display());
+        }
+    }
+
+    if args.validate.is_none() && args.trace_pixel.is_none() {
+        return Ok(());
+    }
+    let values = parameters::values(main_view.parameters());
+    let field_function = presets
+        .get(&session.predefined_function)
+        .and_then(|function| function.to_closure(&values))
+        .context(
+            "the CPU reference integrator only supports the built-in analytic velocity fields",
+        )?;
+    let mesh = Mesh::new(&vertices, &indices);
+    let switches = main_view.switches();
+    let integrator = Integrator {
+        mesh: &mesh,
+        field_function: field_function.as_ref(),
+        options: Options {
+            field_weight: session.field_weight as f64,
+            adaptive_sampling: switches.adaptive_sampling,
+            backface_culling: switches.backface_culling,
+            use_lighting: switches.use_lighting,
+            integrator: session.integrator,
+        },
+    };
+    let camera = Camera::from_pose(pose);
+    let (colors, mapping) = main_view
+        .read_ray_casting_results(&device, &queue)
+        .context("reading back ray casting results failed")?;
+    let gpu = GpuResults {
+        dimensions: main_view.dimensions(),
+        colors,
+        mapping,
+    };
+    if let Some([x, y]) = args.trace_pixel {
+        trace_pixel(&integrator, &camera, &gpu, x, y)?;
+    }
+    if let Some(stride) = args.validate {
+        validate(&integrator, &camera, &gpu, stride.max(1));
+    }
+
+    Ok(())
+}
+
+struct GpuResults {
+    dimensions: (u32, u32),
+    colors: Vec<[u8; 4]>,
+    mapping: Vec<[f32; 4]>,
+}
+
+impl GpuResults {
+    fn pixel(&self, x: u32, y: u32) -> (Vector3<f64>, Vec3) {
+        let index = (y * self.dimensions.0 + x) as usize;
+        let [r, g, b, _] = self.colors[index];
+        let [px, py, pz, _] = self.mapping[index];
+        (
+            Vector3::new(r, g, b).cast::<f64>().unwrap() / 255.0,
+            Vector3::new(px, py, pz).cast().unwrap(),
+        )
+    }
+}
+
+/// Prints every integration step of the CPU reference for pixel `(x, y)`.
+fn trace_pixel(
+    integrator: &Integrator,
+    camera: &Camera,
+    gpu: &GpuResults,
+    x: u32,
+    y: u32,
+) -> Result<()> {
+    let (width, height) = gpu.dimensions;
+    if x >= width || y >= height {
+        bail!(
+            "pixel {},{} is outside of the {}x{} image",
+            x,
+            y,
+            width,
+            height
+        );
+    }
+    let dir = camera.ray_direction(x, y, width, height);
+    let (result, steps) = integrator.trace(camera.origin, dir);
+    println!(
+        "Pixel {},{}: origin {}, direction {}",
+        x,
+        y,
+        fmt_vec(camera.origin),
+        fmt_vec(dir)
+    );
+    for step in &steps {
+        println!(
+            "  t = {:.4}, h = {:.4}, p = {}, v = {}",
+            step.t,
+            step.h,
+            fmt_vec(step.point),
+            fmt_vec(step.dir)
+        );
+    }
+    let (gpu_color, gpu_point) = gpu.pixel(x, y);
+    println!(
+        "CPU: color {}, mapping point {}",
+        fmt_vec(result.color.truncate()),
+        fmt_vec(result.mapping_point.truncate())
+    );
+    println!(
+        "GPU: color {}, mapping point {}",
+        fmt_vec(gpu_color),
+        fmt_vec(gpu_point)
+    );
+    Ok(())
+}
+
+fn fmt_vec(v: Vec3) -> String {
+    format!("({:.6}, {:.6}, {:.6})", v.x, v.y, v.z)
+}
+
+/// Compares every `stride`-th pixel in both directions with the CPU reference.
+fn validate(integrator: &Integrator, camera: &Camera, gpu: &GpuResults, stride: u32) {
+    // Maximum deviation of a color channel that is still caused by quantization
+    let color_tolerance = 1.5 / 255.0;
+    let (width, height) = gpu.dimensions;
+    let mut pixels = 0;
+    let mut color_mismatches = 0;
+    let mut sum_dist = 0.0;
+    let mut max_dist = (0.0, 0, 0);
+    for y in (0..height).step_by(stride as usize) {
+        for x in (0..width).step_by(stride as usize) {
+            let dir = camera.ray_direction(x, y, width, height);
+            let result = integrator.nonlinear_ray_color(camera.origin, dir);
+            let color = result.color.truncate().map(|c| c.clamp(0.0, 1.0));
+            let (gpu_color, gpu_point) = gpu.pixel(x, y);
+            let color_diff = color - gpu_color;
+            if color_diff
+                .x
+                .abs()
+                .max(color_diff.y.abs())
+                .max(color_diff.z.abs())
+                > color_tolerance
+            {
+                color_mismatches += 1;
+            }
+            let dist = (result.mapping_point.truncate() - gpu_point).magnitude();
+            sum_dist += dist;
+            if dist > max_dist.0 || dist.is_nan() {
+                max_dist = (dist, x, y);
+            }
+            pixels += 1;
+        }
+    }
+    println!(
+        "Compared {} pixels with the CPU reference integrator",
+        pixels
+    );
+    println!(
+        "  Color mismatches: {} ({:.2}%)",
+        color_mismatches,
+        100.0 * color_mismatches as f64 / pixels as f64
+    );
+    println!(
+        "  Mapping point deviation: mean {:.6}, max {:.6} at pixel {},{}",
+        sum_dist / pixels as f64,
+        max_dist.0,
+        max_dist.1,
+        max_dist.2
+    );
+}
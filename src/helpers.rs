@@ -0,0 +1,218 @@
+//! Symbols that can be used in field functions, parsed with naga from the helper section
+//! of `main_view.wgsl` for completion and signature help in the editor.
+
+use anyhow::{Context, Result};
+
+/// Comment in `main_view.wgsl` that starts the helper section, which ends at `field_function`.
+const HELPERS_MARKER: &str = "// Helpers for field functions";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    /// An argument of the function the body is inserted into
+    Argument,
+    Constant,
+    Function,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Arguments of functions as `name: type`
+    pub arguments: Vec<String>,
+    /// Type of arguments and constants, return type of functions
+    pub ty: String,
+    /// The comment above the declaration
+    pub doc: String,
+}
+
+impl Symbol {
+    /// Signature of functions, `name: type` otherwise.
+    pub fn signature(&self) -> String {
+        match self.kind {
+            SymbolKind::Function => format!(
+                "fn {}({}) -> {}",
+                self.name,
+                self.arguments.join(", "),
+                self.ty
+            ),
+            _ => format!("{}: {}", self.name, self.ty),
+        }
+    }
+}
+
+/// Parses the arguments of `function`, which the body of the field function is inserted into,
+/// and the functions and constants of the helper section.
+pub fn parse(shader_src: &str, function: &str) -> Result<Vec<Symbol>> {
+    let module = naga::front::wgsl::parse_str(shader_src)
+        .map_err(|e| anyhow::anyhow!(e.emit_to_string(shader_src)))?;
+    let ctx = module.to_ctx();
+    let type_name = |ty: naga::Handle<naga::Type>| module.types[ty].inner.to_wgsl(&ctx);
+
+    let start = shader_src
+        .find(HELPERS_MARKER)
+        .with_context(|| format!("shader has no `{}` section", HELPERS_MARKER))?;
+    let find_function = |name: &str| {
+        module
+            .functions
+            .iter()
+            .find(|(_, f)| f.name.as_deref() == Some(name))
+            .with_context(|| format!("shader has no `{}`", name))
+    };
+    let (field_function_handle, _) = find_function("field_function")?;
+    let end = span_start(module.functions.get_span(field_function_handle));
+    let in_section = |span: naga::Span| (start..end).contains(&span_start(span));
+
+    let (_, target) = find_function(function)?;
+    let mut symbols: Vec<(usize, Symbol)> = target
+        .arguments
+        .iter()
+        .filter_map(|argument| {
+            let symbol = Symbol {
+                name: argument.name.clone()?,
+                kind: SymbolKind::Argument,
+                arguments: Vec::new(),
+                ty: type_name(argument.ty),
+                doc: String::new(),
+            };
+            Some((0, symbol))
+        })
+        .collect();
+    for (handle, constant) in module.constants.iter() {
+        let span = module.constants.get_span(handle);
+        if let (Some(name), true) = (&constant.name, in_section(span)) {
+            let symbol = Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Constant,
+                arguments: Vec::new(),
+                ty: type_name(constant.ty),
+                doc: doc_comment(shader_src, span_start(span)),
+            };
+            symbols.push((span_start(span), symbol));
+        }
+    }
+    for (handle, function) in module.functions.iter() {
+        let span = module.functions.get_span(handle);
+        if let (Some(name), true) = (&function.name, in_section(span)) {
+            let arguments = function
+                .arguments
+                .iter()
+                .map(|a| format!("{}: {}", a.name.as_deref().unwrap_or("_"), type_name(a.ty)))
+                .collect();
+            let symbol = Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Function,
+                arguments,
+                ty: function
+                    .result
+                    .as_ref()
+                    .map_or_else(|| "()".to_string(), |r| type_name(r.ty)),
+                doc: doc_comment(shader_src, span_start(span)),
+            };
+            symbols.push((span_start(span), symbol));
+        }
+    }
+    // naga orders functions by their dependencies, the editor lists them as declared
+    symbols.sort_by_key(|(start, _)| *start);
+    Ok(symbols.into_iter().map(|(_, symbol)| symbol).collect())
+}
+
+fn span_start(span: naga::Span) -> usize {
+    span.to_range().map_or(0, |range| range.start)
+}
+
+/// The `//` comment lines directly above the line at `offset`.
+fn doc_comment(src: &str, offset: usize) -> String {
+    let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let mut lines: Vec<&str> = src[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .take_while(|line| line.starts_with("//") && !line.starts_with(HELPERS_MARKER))
+        .map(|line| line.trim_start_matches('/').trim())
+        .collect();
+    lines.reverse();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(symbols: &'a [Symbol], name: &str) -> &'a Symbol {
+        symbols
+            .iter()
+            .find(|s| s.name == name)
+            .unwrap_or_else(|| panic!("no symbol `{}`", name))
+    }
+
+    #[test]
+    fn main_view_helpers() {
+        let symbols = parse(include_str!("main_view.wgsl"), "field_function").unwrap();
+
+        let arguments: Vec<(&str, &str)> = symbols[..5]
+            .iter()
+            .map(|s| {
+                assert_eq!(s.kind, SymbolKind::Argument);
+                (s.name.as_str(), s.ty.as_str())
+            })
+            .collect();
+        assert_eq!(
+            arguments,
+            [
+                ("p_prev", "vec3<f32>"),
+                ("p", "vec3<f32>"),
+                ("v0", "vec3<f32>"),
+                ("v", "vec3<f32>"),
+                ("t", "f32"),
+            ]
+        );
+
+        let rotate_x = find(&symbols, "rotateX");
+        assert_eq!(rotate_x.kind, SymbolKind::Function);
+        assert_eq!(
+            rotate_x.signature(),
+            "fn rotateX(v: vec3<f32>, phi: f32) -> vec3<f32>"
+        );
+        assert_eq!(rotate_x.doc, "Rotates v around the x-axis by the angle phi");
+
+        let refraction = find(&symbols, "refraction");
+        assert_eq!(
+            refraction.signature(),
+            "fn refraction(t_in: f32, t_out: f32, v_in: vec3<f32>, n: vec3<f32>) -> vec3<f32>"
+        );
+        assert_eq!(
+            refraction.doc,
+            "Refracts v_in at a surface with normal n from temperature t_in to t_out"
+        );
+
+        let distance = find(&symbols, "point_plane_distance");
+        assert_eq!(
+            distance.signature(),
+            "fn point_plane_distance(p: vec3<f32>, n: vec3<f32>, p0: vec3<f32>) -> f32"
+        );
+        assert_eq!(
+            distance.doc,
+            "Distance between p and the plane through p0 with normal n"
+        );
+
+        let sigmoid = find(&symbols, "sigmoid");
+        assert_eq!(sigmoid.signature(), "fn sigmoid(x: f32) -> f32");
+        assert_eq!(sigmoid.doc, "The logistic function 1 / (1 + e^-x)");
+
+        let pi = find(&symbols, "PI");
+        assert_eq!(pi.kind, SymbolKind::Constant);
+        assert_eq!(pi.signature(), "PI: f32");
+
+        // Functions after the helper section are not offered
+        assert!(symbols.iter().all(|s| s.name != "nonlinear_ray_color"));
+    }
+
+    #[test]
+    fn arguments_of_mode() {
+        let symbols = parse(include_str!("main_view.wgsl"), "refractive_index").unwrap();
+        assert_eq!(symbols[0].name, "p");
+        assert_eq!(symbols[0].kind, SymbolKind::Argument);
+        assert_ne!(symbols[1].kind, SymbolKind::Argument);
+    }
+}
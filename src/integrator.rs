@@ -0,0 +1,107 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Numerical scheme used to integrate the rays in the main view and the reference view.
+/// The values must match the `INTEGRATION_SCHEME_*` constants in `main_view.wgsl`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum IntegrationScheme {
+    /// Classic Runge-Kutta with the step size heuristic of the original implementation
+    #[serde(rename = "rk4")]
+    Rk4 = 0,
+    /// Dormand-Prince 5(4) with local error control
+    #[serde(rename = "rk45")]
+    Rk45 = 1,
+    /// Explicit Euler
+    #[serde(rename = "euler")]
+    Euler = 2,
+    /// Explicit midpoint method
+    #[serde(rename = "midpoint")]
+    Midpoint = 3,
+    /// Leapfrog of second order with one evaluation per step: drift-kick-drift in the second
+    /// order modes, which preserves the phase-space structure of Hamiltonian fields, and the
+    /// velocity staggered by half a step in velocity mode
+    #[serde(rename = "leapfrog")]
+    Leapfrog = 4,
+}
+
+impl IntegrationScheme {
+    pub const ALL: [IntegrationScheme; 5] = [
+        Self::Euler,
+        Self::Midpoint,
+        Self::Rk4,
+        Self::Rk45,
+        Self::Leapfrog,
+    ];
+
+    /// Whether the scheme chooses its step size from the error tolerances.
+    pub fn is_adaptive(&self) -> bool {
+        *self == Self::Rk45
+    }
+}
+
+impl Display for IntegrationScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::Rk4 => "RK4",
+            Self::Rk45 => "RK45 (Dormand-Prince)",
+            Self::Euler => "Euler",
+            Self::Midpoint => "Midpoint",
+            Self::Leapfrog => "Leapfrog",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for IntegrationScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "euler" => Ok(Self::Euler),
+            "midpoint" => Ok(Self::Midpoint),
+            "rk4" => Ok(Self::Rk4),
+            "rk45" => Ok(Self::Rk45),
+            "leapfrog" => Ok(Self::Leapfrog),
+            _ => Err("possible values: euler, midpoint, rk4, rk45, leapfrog".to_string()),
+        }
+    }
+}
+
+/// Parameters of the ray integration that are passed to the shader as part of the settings.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntegratorSettings {
+    pub scheme: IntegrationScheme,
+    /// Integration time after which a ray is terminated
+    pub t_max: f32,
+    /// Step size a ray starts with
+    pub h_initial: f32,
+    /// Smallest step size, used by the step size heuristic of the fixed step schemes,
+    /// as lower bound of the adaptive schemes and as step size of the ray samples
+    pub h_min: f32,
+    /// Absolute tolerance of the local position error (adaptive schemes only)
+    pub abs_tol: f32,
+    /// Relative tolerance of the local position error (adaptive schemes only)
+    pub rel_tol: f32,
+    /// Largest step size an adaptive scheme may choose
+    pub h_max: f32,
+    /// Maximum number of steps per ray, including rejected steps
+    pub max_steps: u32,
+}
+
+impl Default for IntegratorSettings {
+    fn default() -> Self {
+        Self {
+            scheme: IntegrationScheme::Rk4,
+            t_max: 5.0,
+            h_initial: 0.1,
+            h_min: 0.001,
+            abs_tol: 0.0001,
+            rel_tol: 0.001,
+            h_max: 0.25,
+            max_steps: 10000,
+        }
+    }
+}
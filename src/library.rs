@@ -0,0 +1,320 @@
+//! User library of field functions, stored as one JSON file per function in the config directory
+//! so that they survive restarts and can be shared by copying the files.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::functions::FieldMode;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LibraryFunction {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Helper code inserted before the field function
+    #[serde(default)]
+    pub prelude: String,
+    pub body: String,
+    /// What the body computes, added after the first version of the library
+    #[serde(default)]
+    pub mode: FieldMode,
+    /// Parameter values by name, applied on top of the defaults of the annotations
+    #[serde(default)]
+    pub parameters: BTreeMap<String, [f32; 4]>,
+}
+
+pub struct Library {
+    dir: PathBuf,
+    /// Functions with the file they are stored in, sorted by name
+    functions: Vec<(PathBuf, LibraryFunction)>,
+}
+
+impl Library {
+    /// `functions` in the config directory.
+    pub fn default_dir() -> PathBuf {
+        config_dir().join("functions")
+    }
+
+    /// Reads all functions in `dir`, which is created when the first function is saved.
+    /// Files that cannot be read are skipped with a warning.
+    pub fn open(dir: PathBuf) -> Self {
+        let mut functions = Vec::new();
+        match std::fs::read_dir(&dir) {
+            Ok(entries) => {
+                for path in entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                {
+                    if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                        continue;
+                    }
+                    match read_function(&path) {
+                        Ok(function) => functions.push((path, function)),
+                        Err(e) => println!("Skipping library function: {:#}", e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => println!("Reading function library {} failed: {}", dir.display(), e),
+        }
+        functions.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        Self { dir, functions }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &LibraryFunction> {
+        self.functions.iter().map(|(_, f)| f)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LibraryFunction> {
+        self.functions().find(|f| f.name == name)
+    }
+
+    /// Saves `function`, replacing a function of the same name.
+    pub fn save(&mut self, function: LibraryFunction) -> Result<()> {
+        self.check_name(&function.name, Some(&function.name))?;
+        let path = match self.functions.iter().find(|(_, f)| f.name == function.name) {
+            Some((path, _)) => path.clone(),
+            None => self.new_path(&function.name),
+        };
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {} failed", self.dir.display()))?;
+        let data = serde_json::to_string_pretty(&function)?;
+        std::fs::write(&path, data)
+            .with_context(|| format!("writing {} failed", path.display()))?;
+        self.functions.retain(|(_, f)| f.name != function.name);
+        self.functions.push((path, function));
+        self.functions.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        Ok(())
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if name == new_name {
+            return Ok(());
+        }
+        self.check_name(new_name, Some(name))?;
+        let mut function = self
+            .get(name)
+            .with_context(|| format!("no library function named {:?}", name))?
+            .clone();
+        function.name = new_name.to_string();
+        // The new file is written first so that a failure cannot lose the function
+        self.save(function)?;
+        self.delete(name)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        let index = self
+            .functions
+            .iter()
+            .position(|(_, f)| f.name == name)
+            .with_context(|| format!("no library function named {:?}", name))?;
+        let path = &self.functions[index].0;
+        std::fs::remove_file(path)
+            .with_context(|| format!("removing {} failed", path.display()))?;
+        self.functions.remove(index);
+        Ok(())
+    }
+
+    /// Saves a copy of the function under an unused name and returns that name.
+    pub fn duplicate(&mut self, name: &str) -> Result<String> {
+        let mut function = self
+            .get(name)
+            .with_context(|| format!("no library function named {:?}", name))?
+            .clone();
+        function.name = (1..)
+            .map(|i| match i {
+                1 => format!("{} (copy)", name),
+                _ => format!("{} (copy {})", name, i),
+            })
+            .find(|candidate| self.check_name(candidate, None).is_ok())
+            .expect("infinite iterator");
+        let new_name = function.name.clone();
+        self.save(function)?;
+        Ok(new_name)
+    }
+
+    /// Ensures that `name` is not empty and not used by another function than `replaces`.
+    fn check_name(&self, name: &str, replaces: Option<&str>) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("the name of a library function must not be empty");
+        }
+        if Some(name) != replaces && self.get(name).is_some() {
+            bail!("a library function named {:?} already exists", name);
+        }
+        Ok(())
+    }
+
+    /// An unused file for a new function, named after it with unsuitable characters replaced.
+    fn new_path(&self, name: &str) -> PathBuf {
+        let file_name: String = name
+            .trim()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+        (1..)
+            .map(|i| match i {
+                1 => self.dir.join(format!("{}.json", file_name)),
+                _ => self.dir.join(format!("{}-{}.json", file_name, i)),
+            })
+            .find(|path| !path.exists() && self.functions.iter().all(|(p, _)| p != path))
+            .expect("infinite iterator")
+    }
+}
+
+/// `linon` in the platform's config directory, or the working directory if the config
+/// directory cannot be determined.
+pub fn config_dir() -> PathBuf {
+    let env_dir = |name: &str| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    let config_dir = if cfg!(target_os = "windows") {
+        env_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".config")))
+    };
+    match config_dir {
+        Some(dir) => dir.join("linon"),
+        None => PathBuf::from("."),
+    }
+}
+
+fn read_function(path: &Path) -> Result<LibraryFunction> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("reading {} failed", path.display()))?;
+    serde_json::from_str(&data).with_context(|| format!("parsing {} failed", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty library in its own directory, which is removed when dropped.
+    struct TempLibrary(Library);
+
+    impl TempLibrary {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("linon-library-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self(Library::open(dir))
+        }
+
+        fn files(&self) -> Vec<String> {
+            let mut files: Vec<String> = std::fs::read_dir(self.0.dir())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempLibrary {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.0.dir());
+        }
+    }
+
+    fn function(name: &str) -> LibraryFunction {
+        LibraryFunction {
+            name: name.to_string(),
+            description: "A test function".to_string(),
+            prelude: "fn helper() -> f32 { return 2.0; }".to_string(),
+            body: "// @param k: f32 = 1.0\nreturn k * v;".to_string(),
+            mode: FieldMode::Velocity,
+            parameters: BTreeMap::from([("k".to_string(), [0.5, 0.0, 0.0, 0.0])]),
+        }
+    }
+
+    fn names(library: &Library) -> Vec<&str> {
+        library.functions().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn save_and_open() {
+        let mut library = TempLibrary::new("open");
+        library.0.save(function("b")).unwrap();
+        library.0.save(function("a")).unwrap();
+        let mut changed = function("a");
+        changed.body = "return v;".to_string();
+        library.0.save(changed).unwrap();
+        assert_eq!(library.files(), ["a.json", "b.json"]);
+
+        let opened = Library::open(library.0.dir().to_path_buf());
+        assert_eq!(names(&opened), ["a", "b"]);
+        let a = opened.get("a").unwrap();
+        assert_eq!(a.body, "return v;");
+        assert_eq!(a.description, "A test function");
+        assert_eq!(a.prelude, function("a").prelude);
+        assert_eq!(a.mode, FieldMode::Velocity);
+        assert_eq!(a.parameters, function("a").parameters);
+    }
+
+    #[test]
+    fn rename() {
+        let mut library = TempLibrary::new("rename");
+        library.0.save(function("old")).unwrap();
+        library.0.rename("old", "new").unwrap();
+        assert_eq!(names(&library.0), ["new"]);
+        assert_eq!(library.files(), ["new.json"]);
+        let opened = Library::open(library.0.dir().to_path_buf());
+        assert_eq!(opened.get("new").unwrap().body, function("old").body);
+        assert!(library.0.rename("missing", "other").is_err());
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut library = TempLibrary::new("duplicate");
+        library.0.save(function("f")).unwrap();
+        assert_eq!(library.0.duplicate("f").unwrap(), "f (copy)");
+        assert_eq!(library.0.duplicate("f").unwrap(), "f (copy 2)");
+        assert_eq!(names(&library.0), ["f", "f (copy 2)", "f (copy)"]);
+        assert_eq!(
+            library.0.get("f (copy 2)").unwrap().body,
+            function("f").body
+        );
+        assert_eq!(library.files().len(), 3);
+    }
+
+    #[test]
+    fn new_path() {
+        let mut library = TempLibrary::new("path");
+        let dir = library.0.dir().to_path_buf();
+        assert_eq!(
+            library.0.new_path(" Mirage: hot/cold ö "),
+            dir.join("Mirage__hot_cold__.json")
+        );
+        // Names that map to the same file get a number
+        library.0.save(function("a b")).unwrap();
+        assert_eq!(library.0.new_path("a/b"), dir.join("a_b-2.json"));
+        library.0.save(function("a/b")).unwrap();
+        assert_eq!(library.files(), ["a_b-2.json", "a_b.json"]);
+        assert_eq!(library.0.new_path("a?b"), dir.join("a_b-3.json"));
+    }
+
+    #[test]
+    fn invalid_names() {
+        let mut library = TempLibrary::new("names");
+        assert!(library.0.save(function("")).is_err());
+        assert!(library.0.save(function("  ")).is_err());
+        library.0.save(function("a")).unwrap();
+        library.0.save(function("b")).unwrap();
+        assert!(library.0.rename("a", "b").is_err());
+        assert!(library.0.rename("a", " ").is_err());
+        assert_eq!(names(&library.0), ["a", "b"]);
+        assert_eq!(library.files(), ["a.json", "b.json"]);
+    }
+}
@@ -1,24 +1,50 @@
 mod application;
 mod arcball;
+mod bvh;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+mod clock;
 mod cornell_box;
+mod cpu_integrator;
 mod egui_wgpu;
+#[cfg(not(target_arch = "wasm32"))]
+mod export;
 mod functions;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+mod helpers;
+mod integrator;
+#[cfg(not(target_arch = "wasm32"))]
+mod library;
 mod main_view;
+mod parameters;
 mod ray_samples;
 mod reference_view;
+mod session;
 mod syntax_highlighting;
 mod texture;
+mod timeline;
+mod validation;
 mod vertices;
+mod volume;
 
 use anyhow::Result;
 use application::Application;
 #[cfg(not(target_arch = "wasm32"))]
+use clap::Parser;
+#[cfg(not(target_arch = "wasm32"))]
+use cli::Args;
+#[cfg(not(target_arch = "wasm32"))]
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use session::Model;
 use std::ffi::OsStr;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
 use std::sync::mpsc::Receiver;
 #[cfg(not(target_arch = "wasm32"))]
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
+use vertices::load_obj;
 use winit::application::ApplicationHandler;
 use winit::dpi::LogicalSize;
 use winit::event::{ElementState, KeyEvent};
@@ -27,11 +53,14 @@ use winit::window::Fullscreen;
 use winit::{event::WindowEvent, event_loop::EventLoop, keyboard::Key, window::Window};
 
 #[cfg(not(target_arch = "wasm32"))]
-fn start_watcher(tx: Sender<notify::Result<notify::Event>>) -> Result<RecommendedWatcher> {
+fn start_watcher(
+    path: &Path,
+    tx: Sender<notify::Result<notify::Event>>,
+) -> Result<RecommendedWatcher> {
     let mut watcher = notify::recommended_watcher(move |res| {
         tx.send(res).expect("sending watch event failed");
     })?;
-    watcher.watch("src/main_view.wgsl".as_ref(), RecursiveMode::NonRecursive)?;
+    watcher.watch(path, RecursiveMode::NonRecursive)?;
     Ok(watcher)
 }
 
@@ -45,6 +74,8 @@ struct ApplicationWindow {
     close_requested: bool,
     event_proxy: EventLoopProxy<UserEvent>,
     #[cfg(not(target_arch = "wasm32"))]
+    args: Args,
+    #[cfg(not(target_arch = "wasm32"))]
     shader_rx: Receiver<notify::Result<notify::Event>>,
 }
 
@@ -54,12 +85,19 @@ impl ApplicationHandler<UserEvent> for ApplicationWindow {
             return;
         }
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let inner_size = LogicalSize {
+            width: self.args.width,
+            height: self.args.height,
+        };
+        #[cfg(target_arch = "wasm32")]
+        let inner_size = LogicalSize {
+            width: 1400,
+            height: 900,
+        };
         let window_attributes = Window::default_attributes()
             .with_title("linon")
-            .with_inner_size(LogicalSize {
-                width: 1400,
-                height: 900,
-            });
+            .with_inner_size(inner_size);
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap()); // needed for resize closure on web
         self.window = Some(window.clone());
 
@@ -116,6 +154,14 @@ impl ApplicationHandler<UserEvent> for ApplicationWindow {
 
     fn user_event(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop, event: UserEvent) {
         match event {
+            #[cfg(not(target_arch = "wasm32"))]
+            UserEvent::ApplicationCreated(mut application) => {
+                if let Err(e) = application.apply_args(&self.args) {
+                    println!("Applying command line arguments failed: {:?}", e);
+                }
+                self.app = Some(application);
+            }
+            #[cfg(target_arch = "wasm32")]
             UserEvent::ApplicationCreated(application) => {
                 self.app = Some(application);
             }
@@ -140,20 +186,19 @@ impl ApplicationHandler<UserEvent> for ApplicationWindow {
                 println!("File dropped: {:?}", path);
                 if path.extension().and_then(OsStr::to_str) == Some("obj") {
                     println!("Loading object...");
-                    let (models, _) = tobj::load_obj(
-                        &path,
-                        &tobj::LoadOptions {
-                            triangulate: true,
-                            ..Default::default()
-                        },
-                    )
-                    .expect("failed to load obj file");
-                    println!("Number of models: {}", models.len());
-                    println!("Loading first model...");
-                    let mesh = &models[0].mesh;
-                    app.load_model(&mut mesh.positions.clone(), &mesh.indices);
+                    let (vertices, indices) = load_obj(&path).expect("failed to load obj file");
+                    app.load_model(Model { vertices, indices });
                     println!("Finished loading");
                 }
+                #[cfg(not(target_arch = "wasm32"))]
+                if matches!(
+                    path.extension().and_then(OsStr::to_str),
+                    Some("vtk") | Some("vol")
+                ) {
+                    if let Err(e) = app.load_volume(&path) {
+                        println!("Loading volume failed: {:?}", e);
+                    }
+                }
             }
             WindowEvent::Resized(size) => app.resize(size.width, size.height),
             WindowEvent::CloseRequested => {
@@ -213,10 +258,17 @@ impl ApplicationHandler<UserEvent> for ApplicationWindow {
             }
             if reload_compute_shader {
                 println!("Compute shader has changed");
-                let source = std::fs::read_to_string("src/main_view.wgsl")
-                    .expect("reading compute shader failed");
-                if let Err(e) = app.reload_compute_shader(&source) {
-                    println!("Shader reload failed: {:?}", e);
+                match std::fs::read_to_string(&self.args.watch) {
+                    Ok(source) => {
+                        if let Err(e) = app.reload_compute_shader(&source) {
+                            println!("Shader reload failed: {:?}", e);
+                        }
+                    }
+                    Err(e) => println!(
+                        "Reading compute shader {} failed: {}",
+                        self.args.watch.display(),
+                        e
+                    ),
                 }
             }
         }
@@ -229,12 +281,20 @@ fn main() -> Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
 
+    #[cfg(not(target_arch = "wasm32"))]
+    let args = Args::parse();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &args.headless {
+        return futures::executor::block_on(headless::render_to_png(&args, path));
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     let (shader_rx, _watcher) = {
         let (tx, rx) = channel::<notify::Result<notify::Event>>();
-        match start_watcher(tx) {
+        match start_watcher(&args.watch, tx) {
             Ok(watcher) => {
-                println!("Watching shader main_view.wgsl for changes");
+                println!("Watching shader {} for changes", args.watch.display());
                 (rx, Some(watcher))
             }
             Err(e) => {
@@ -252,6 +312,8 @@ fn main() -> Result<()> {
         close_requested: false,
         event_proxy: event_loop.create_proxy(),
         #[cfg(not(target_arch = "wasm32"))]
+        args,
+        #[cfg(not(target_arch = "wasm32"))]
         shader_rx,
     };
     event_loop.run_app(&mut app)?;
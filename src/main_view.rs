@@ -1,12 +1,25 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{application::OverlayMode, export::RayData};
 use crate::{
-    arcball::{ArcballCamera, CameraOperation},
-    functions::PredefinedFunction,
+    arcball::{ArcballCamera, CameraOperation, CameraPose},
+    functions::{default_field_function, FieldMode},
+    helpers::{self, Symbol},
+    integrator::IntegratorSettings,
+    parameters::{self, Parameter, ParametersUniform},
     texture::Texture,
+    validation::{self, Snippet, Snippets},
+    vertices::Normalization,
+    volume::{Outside, Volume, VolumeTexture},
 };
+use anyhow::{anyhow, bail, Context, Result};
 use cgmath::{Matrix4, SquareMatrix, Vector2, Vector3};
 use egui::{Image, ImageSource, Sense, Widget};
 use egui_wgpu as egui_wgpu_backend;
-use std::{borrow::Cow, sync::mpsc::channel};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::mpsc::channel,
+};
 use wgpu::util::DeviceExt;
 
 #[repr(C)]
@@ -41,25 +54,159 @@ impl CameraUniform {
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize)]
 pub struct Settings {
     pub field_weight: f32,
     pub mouse_pos: [f32; 2],
     pub overlay_mode: i32,
     pub central_difference_delta: i32,
     pub lyapunov_scaling: f32,
+    pub integration_scheme: i32,
+    pub abs_tol: f32,
+    pub rel_tol: f32,
+    pub h_min: f32,
+    pub h_max: f32,
+    pub t_max: f32,
+    pub h_initial: f32,
+    pub max_steps: u32,
+    /// Color of rays that leave the scene, see [`crate::application::Background`]
+    pub background: i32,
+    /// Global animation time, see [`crate::clock::Clock`]
+    pub time: f32,
+}
+
+impl Settings {
+    pub fn set_integrator(&mut self, integrator: &IntegratorSettings) {
+        self.integration_scheme = integrator.scheme as i32;
+        self.abs_tol = integrator.abs_tol;
+        self.rel_tol = integrator.rel_tol;
+        self.h_min = integrator.h_min;
+        self.h_max = integrator.h_max;
+        self.t_max = integrator.t_max;
+        self.h_initial = integrator.h_initial;
+        self.max_steps = integrator.max_steps;
+    }
 }
 
+impl Default for Settings {
+    fn default() -> Self {
+        let integrator = IntegratorSettings::default();
+        Self {
+            field_weight: 1.0,
+            mouse_pos: [0.5, 0.6],
+            overlay_mode: 0,
+            central_difference_delta: 1,
+            lyapunov_scaling: 50.0,
+            integration_scheme: integrator.scheme as i32,
+            abs_tol: integrator.abs_tol,
+            rel_tol: integrator.rel_tol,
+            h_min: integrator.h_min,
+            h_max: integrator.h_max,
+            t_max: integrator.t_max,
+            h_initial: integrator.h_initial,
+            max_steps: integrator.max_steps,
+            background: 0,
+            time: 0.0,
+        }
+    }
+}
+
+/// Switches of `main_view.wgsl` that are declared as `override` constants.
+/// They are passed as pipeline constants when the compute pipeline is created,
+/// so toggling them does not require patching the shader source.
+/// The field mode is passed along with them as `refractive_index_mode` and `acceleration_mode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShaderSwitches {
+    /// Refine the step size where the field changes abruptly (fixed step schemes only)
+    pub adaptive_sampling: bool,
+    /// Place the rays of the reference view on the outline of the region with high Lyapunov exponents
+    pub sample_outline_rays: bool,
+    /// Cast straight rays without integrating the field
+    pub linear_mode: bool,
+    pub use_lighting: bool,
+    pub backface_culling: bool,
+}
+
+impl Default for ShaderSwitches {
+    fn default() -> Self {
+        Self {
+            adaptive_sampling: true,
+            sample_outline_rays: false,
+            linear_mode: false,
+            use_lighting: true,
+            backface_culling: false,
+        }
+    }
+}
+
+impl ShaderSwitches {
+    /// The switches and the field mode as pipeline constants.
+    fn pipeline_constants(&self, mode: FieldMode) -> HashMap<String, f64> {
+        self.values(mode)
+            .iter()
+            .map(|&(name, value)| (name.to_string(), if value { 1.0 } else { 0.0 }))
+            .collect()
+    }
+
+    /// Checks that `shader_src` declares every switch as `override`,
+    /// since wgpu silently ignores unknown pipeline constants.
+    fn check_declared(shader_src: &str) -> Result<()> {
+        let module = naga::front::wgsl::parse_str(shader_src)
+            .map_err(|e| anyhow!(e.emit_to_string(shader_src)))?;
+        for (name, _) in Self::default().values(FieldMode::Velocity).iter() {
+            let is_declared = module
+                .overrides
+                .iter()
+                .any(|(_, o)| o.name.as_deref() == Some(*name));
+            if !is_declared {
+                bail!(
+                    "shader does not declare the switch `override {}: bool`",
+                    name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn values(&self, mode: FieldMode) -> [(&'static str, bool); 7] {
+        [
+            ("adaptive_sampling", self.adaptive_sampling),
+            ("sample_outline_rays", self.sample_outline_rays),
+            ("linear_mode", self.linear_mode),
+            ("use_lighting", self.use_lighting),
+            ("backface_culling", self.backface_culling),
+            ("refractive_index_mode", mode == FieldMode::RefractiveIndex),
+            ("acceleration_mode", mode == FieldMode::Acceleration),
+        ]
+    }
+}
+
+/// Colors and mapping points of every pixel, see [`MainView::read_ray_casting_results`].
+#[cfg(not(target_arch = "wasm32"))]
+pub type RayCastingResults = (Vec<[u8; 4]>, Vec<[f32; 4]>);
+
 pub struct MainView {
-    adaptive_sampling: bool,
+    switches: ShaderSwitches,
     downscale_factor: u32,
     texture: Texture,
-    texture_id: egui::TextureId,
+    // Registered lazily once the texture is first shown, so that the view
+    // can also be used without egui (e.g. for headless rendering)
+    texture_id: Option<egui::TextureId>,
     ray_casting_texture: Texture,
     mapping_texture: Texture,
     shader_src: String,
+    /// Helper code inserted before the field function
+    prelude: String,
     field_function: String,
-    _shader: wgpu::ShaderModule,
+    field_mode: FieldMode,
+    parameters: Vec<Parameter>,
+    parameters_buffer: wgpu::Buffer,
+    /// Symbols of the shader that can be used in field functions
+    helpers: Vec<Symbol>,
+    /// Validated shader source with the prelude, parameters and field function inserted
+    full_src: String,
+    shader: wgpu::ShaderModule,
+    backend: wgpu::Backend,
     compute_bind_group_layout: wgpu::BindGroupLayout,
     compute_bind_group: wgpu::BindGroup,
     compute_pipeline_layout: wgpu::PipelineLayout,
@@ -67,10 +214,19 @@ pub struct MainView {
     mesh_bind_group_layout: wgpu::BindGroupLayout,
     mesh_bind_group: wgpu::BindGroup,
     ray_samples_bind_group: wgpu::BindGroup,
+    /// Volumetric scalar field sampled by `sample_field`
+    scalar_field: VolumeTexture,
+    scalar_field_buffer: wgpu::Buffer,
+    /// Volumetric vector field sampled by `sample_vector_field`
+    vector_field: VolumeTexture,
+    vector_field_buffer: wgpu::Buffer,
+    field_bind_group_layout: wgpu::BindGroupLayout,
+    field_bind_group: wgpu::BindGroup,
     overlay_bind_group_layout: wgpu::BindGroupLayout,
     overlay_bind_group: wgpu::BindGroup,
     overlay_pipeline: wgpu::ComputePipeline,
     settings_buffer: wgpu::Buffer,
+    settings: Settings,
     exponents_buffer: wgpu::Buffer,
     camera_buffer: wgpu::Buffer,
     camera: ArcballCamera<f32>,
@@ -80,26 +236,36 @@ pub struct MainView {
 
 impl MainView {
     pub fn new(
-        rpass: &mut egui_wgpu_backend::Renderer,
         device: &wgpu::Device,
         vertices_buffer_binding: wgpu::BindingResource,
         faces_buffer_binding: wgpu::BindingResource,
+        bvh_buffer_binding: wgpu::BindingResource,
         center: Vector3<f32>,
         ray_samples_buffer_binding: wgpu::BindingResource,
         width: u32,
         height: u32,
-        discrete_gpu: bool,
+        downscale_factor: u32,
+        backend: wgpu::Backend,
     ) -> Self {
-        let downscale_factor = if discrete_gpu { 1 } else { 2 };
-
         let shader_src = include_str!("main_view.wgsl");
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("compute_shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(&with_field_function(
-                shader_src,
-                &PredefinedFunction::MirageSphericalSigmoid.to_code(),
-            ))),
-        });
+        let switches = ShaderSwitches::default();
+        ShaderSwitches::check_declared(shader_src)
+            .expect("main_view.wgsl does not declare all shader switches");
+        let constants = switches.pipeline_constants(FieldMode::Velocity);
+        let helpers = helpers::parse(shader_src, FieldMode::Velocity.function_name())
+            .expect("parsing helpers of main_view.wgsl failed");
+        let field_function = default_field_function();
+        let parameters = parameters::parse(&field_function)
+            .expect("invalid parameters in the initial field function");
+        let (full_src, _) = with_field_function(
+            shader_src,
+            "",
+            &parameters,
+            &field_function,
+            FieldMode::Velocity,
+        )
+        .expect("main_view.wgsl lacks the field function stub");
+        let shader = create_shader_module(device, &full_src);
 
         let width = width / downscale_factor;
         let height = height / downscale_factor;
@@ -111,8 +277,6 @@ impl MainView {
             wgpu::TextureFormat::Rgba8Unorm,
             true,
         );
-        let texture_id =
-            rpass.register_native_texture(device, &texture.view, wgpu::FilterMode::Nearest);
 
         let ray_casting_texture = Texture::new(
             device,
@@ -130,28 +294,28 @@ impl MainView {
             true,
         );
 
-        let settings = Settings {
-            field_weight: 1.0,
-            mouse_pos: [0.5, 0.6],
-            overlay_mode: 0,
-            central_difference_delta: 1,
-            lyapunov_scaling: 50.0,
-        };
+        let settings = Settings::default();
         let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("settings_buffer"),
             contents: bytemuck::cast_slice(&[settings]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let parameters_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("parameters_buffer"),
+            contents: bytemuck::cast_slice(&[ParametersUniform::new(&parameters)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let exponents_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("exponents_buffer"),
             size: 4 * (width as u64) * (height as u64),
-            usage: wgpu::BufferUsages::STORAGE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
         let mut camera = ArcballCamera::new(center, 1.0, [width as f32, height as f32]);
-        camera.zoom(-1.0, 1.0);
+        camera.set_pose(CameraPose::initial(center));
         let camera_uniform = CameraUniform::moving(&camera);
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("camera_buffer"),
@@ -212,6 +376,16 @@ impl MainView {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("compute_bind_group_layout"),
             });
@@ -238,6 +412,10 @@ impl MainView {
                     binding: 4,
                     resource: exponents_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: parameters_buffer.as_entire_binding(),
+                },
             ],
             label: Some("compute_bind_group"),
         });
@@ -264,6 +442,16 @@ impl MainView {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("mesh_bind_group_layout"),
             });
@@ -278,6 +466,10 @@ impl MainView {
                     binding: 1,
                     resource: faces_buffer_binding,
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: bvh_buffer_binding,
+                },
             ],
             label: Some("mesh_bind_group"),
         });
@@ -303,6 +495,74 @@ impl MainView {
             }],
             label: Some("ray_samples_bind_group"),
         });
+        let scalar_field = VolumeTexture::empty(device, 1);
+        let scalar_field_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scalar_field_buffer"),
+            contents: bytemuck::cast_slice(&[
+                scalar_field.uniform(Normalization::IDENTITY, Outside::Clamp)
+            ]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let vector_field = VolumeTexture::empty(device, 3);
+        let vector_field_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vector_field_buffer"),
+            contents: bytemuck::cast_slice(&[
+                vector_field.uniform(Normalization::IDENTITY, Outside::Zero)
+            ]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let field_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("field_bind_group_layout"),
+            });
+        let field_bind_group = create_field_bind_group(
+            device,
+            &field_bind_group_layout,
+            (&scalar_field, &scalar_field_buffer),
+            (&vector_field, &vector_field_buffer),
+        );
         let compute_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("compute_pipeline_layout"),
@@ -310,17 +570,12 @@ impl MainView {
                     &compute_bind_group_layout,
                     &mesh_bind_group_layout,
                     &ray_samples_bind_group_layout,
+                    &field_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("compute_pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &shader,
-            entry_point: "main_view",
-            compilation_options: Default::default(),
-            cache: None,
-        });
+        let compute_pipeline =
+            create_compute_pipeline(device, &compute_pipeline_layout, &shader, &constants);
 
         let overlay_shader_src = include_str!("overlay.wgsl");
         let overlay_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -428,15 +683,22 @@ impl MainView {
         });
 
         Self {
-            adaptive_sampling: true,
+            switches,
             downscale_factor,
             texture,
-            texture_id,
+            texture_id: None,
             ray_casting_texture,
             mapping_texture,
             shader_src: shader_src.to_string(),
-            field_function: PredefinedFunction::MirageSphericalSigmoid.to_code(),
-            _shader: shader,
+            prelude: String::new(),
+            field_function,
+            field_mode: FieldMode::Velocity,
+            parameters,
+            parameters_buffer,
+            helpers,
+            full_src,
+            shader,
+            backend,
             compute_bind_group_layout,
             compute_bind_group,
             compute_pipeline_layout,
@@ -444,10 +706,17 @@ impl MainView {
             mesh_bind_group_layout,
             mesh_bind_group,
             ray_samples_bind_group,
+            scalar_field,
+            scalar_field_buffer,
+            vector_field,
+            vector_field_buffer,
+            field_bind_group_layout,
+            field_bind_group,
             overlay_bind_group_layout,
             overlay_bind_group,
             overlay_pipeline,
             settings_buffer,
+            settings,
             exponents_buffer,
 
             camera_buffer,
@@ -463,6 +732,7 @@ impl MainView {
         queue: &wgpu::Queue,
         vertices_buffer_binding: wgpu::BindingResource,
         faces_buffer_binding: wgpu::BindingResource,
+        bvh_buffer_binding: wgpu::BindingResource,
         center: Vector3<f32>,
     ) {
         self.mesh_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -476,13 +746,17 @@ impl MainView {
                     binding: 1,
                     resource: faces_buffer_binding,
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: bvh_buffer_binding,
+                },
             ],
             label: Some("mesh_bind_group"),
         });
 
         let (width, height) = self.texture.dimensions;
         self.camera = ArcballCamera::new(center, 1.0, [width as f32, height as f32]);
-        self.camera.zoom(-1.0, 1.0);
+        self.camera.set_pose(CameraPose::initial(center));
         self.update_camera(queue);
         self.needs_redraw = true;
     }
@@ -493,11 +767,20 @@ impl MainView {
         self.needs_redraw = true;
     }
 
+    pub fn camera_pose(&self) -> CameraPose<f32> {
+        self.camera.pose()
+    }
+
+    pub fn set_camera_pose(&mut self, queue: &wgpu::Queue, pose: CameraPose<f32>) {
+        self.camera.set_pose(pose);
+        self.update_camera(queue);
+    }
+
     fn reset_camera(&mut self, queue: &wgpu::Queue) {
         let center = self.camera.center;
         let (width, height) = self.texture.dimensions;
         self.camera = ArcballCamera::new(center, 1.0, [width as f32, height as f32]);
-        self.camera.zoom(-1.0, 1.0);
+        self.camera.set_pose(CameraPose::initial(center));
         self.update_camera(queue);
     }
 
@@ -524,7 +807,7 @@ impl MainView {
         let downscale_factor = self.downscale_factor as f32;
         match camera_op {
             CameraOperation::Rotate => {
-                self.disable_adaptive_sampling(device);
+                self.set_adaptive_sampling(device, false);
                 self.camera.rotate(
                     Vector2::new(prev.0, prev.1) / downscale_factor,
                     Vector2::new(pos.0, pos.1) / downscale_factor,
@@ -532,7 +815,7 @@ impl MainView {
                 self.update_camera(queue);
             }
             CameraOperation::Pan => {
-                self.disable_adaptive_sampling(device);
+                self.set_adaptive_sampling(device, false);
                 self.camera.pan(Vector2::new(
                     (pos.0 - prev.0) / downscale_factor,
                     (pos.1 - prev.1) / downscale_factor,
@@ -546,19 +829,41 @@ impl MainView {
 
     pub fn update_settings(&mut self, queue: &wgpu::Queue, settings: Settings) {
         queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+        self.settings = settings;
         self.needs_redraw = true;
     }
 
+    /// Sets the animation time, which renders the view again.
+    pub fn set_time(&mut self, queue: &wgpu::Queue, time: f32) {
+        self.update_settings(
+            queue,
+            Settings {
+                time,
+                ..self.settings
+            },
+        );
+    }
+
     pub fn resize_texture(
         &mut self,
-        rpass: &mut egui_wgpu_backend::Renderer,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         width: u32,
         height: u32,
     ) {
-        let width = width / self.downscale_factor;
-        let height = height / self.downscale_factor;
+        self.create_textures(
+            device,
+            queue,
+            (
+                width / self.downscale_factor,
+                height / self.downscale_factor,
+            ),
+        );
+    }
+
+    /// Creates the render targets in the given size, which is the size of rendered images.
+    fn create_textures(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, size: (u32, u32)) {
+        let (width, height) = size;
         self.texture = Texture::new(
             device,
             (width, height),
@@ -566,8 +871,6 @@ impl MainView {
             wgpu::TextureFormat::Rgba8Unorm,
             true,
         );
-        self.texture_id =
-            rpass.register_native_texture(device, &self.texture.view, wgpu::FilterMode::Nearest);
         self.ray_casting_texture = Texture::new(
             device,
             (width, height),
@@ -585,7 +888,7 @@ impl MainView {
         self.exponents_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("exponents_buffer"),
             size: 4 * (width as u64) * (height as u64),
-            usage: wgpu::BufferUsages::STORAGE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
@@ -612,6 +915,10 @@ impl MainView {
                     binding: 4,
                     resource: self.exponents_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.parameters_buffer.as_entire_binding(),
+                },
             ],
             label: Some("compute_bind_group"),
         });
@@ -646,49 +953,214 @@ impl MainView {
         self.needs_redraw = true;
     }
 
+    /// Recompiles the shader with a new prelude and field function and optionally a new shader source.
+    /// `mode` tells what the field function computes.
+    /// Parameters that keep their declaration also keep their values.
     pub fn reload_shader(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         new_src: Option<&str>,
+        prelude: String,
         field_function: String,
-    ) -> Result<(), wgpu::Error> {
-        let src = with_field_function(new_src.unwrap_or(&self.shader_src), &field_function);
-
-        let (tx, rx) = channel::<wgpu::Error>();
-        device.on_uncaptured_error(Box::new(move |e: wgpu::Error| {
-            tx.send(e).expect("sending error failed");
-        }));
-
-        let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("compute_shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(&src)),
-        });
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("compute_pipeline"),
-            layout: Some(&self.compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: "main_view",
-            compilation_options: Default::default(),
-            cache: None,
-        });
-
-        device.on_uncaptured_error(Box::new(|e| panic!("{}", e)));
-
-        if let Ok(err) = rx.try_recv() {
-            return Err(err);
-        }
+        mode: FieldMode,
+    ) -> Result<()> {
+        let shader_src = new_src.unwrap_or(&self.shader_src);
+        let mut parameters = parameters::parse(&field_function)?;
+        parameters::keep_values(&mut parameters, &self.parameters);
+        let (full_src, shader, compute_pipeline, helpers) = self.create_pipeline(
+            device,
+            shader_src,
+            &prelude,
+            &field_function,
+            mode,
+            &parameters,
+        )?;
 
         if let Some(new_src) = new_src {
             self.shader_src = new_src.to_string();
         }
+        self.helpers = helpers;
+        self.prelude = prelude;
         self.field_function = field_function;
-        self._shader = compute_shader;
+        self.field_mode = mode;
+        self.parameters = parameters;
+        self.full_src = full_src;
+        self.shader = shader;
         self.compute_pipeline = compute_pipeline;
+        self.update_parameters(queue);
+
+        Ok(())
+    }
+
+    pub fn helpers(&self) -> &[Symbol] {
+        &self.helpers
+    }
+
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// The parameters for editing, call `update_parameters` after changing values.
+    pub fn parameters_mut(&mut self) -> &mut [Parameter] {
+        &mut self.parameters
+    }
+
+    /// Sets the values of the parameters by name, unknown names are ignored.
+    pub fn set_parameter_values(
+        &mut self,
+        queue: &wgpu::Queue,
+        values: &BTreeMap<String, [f32; 4]>,
+    ) {
+        parameters::set_values(&mut self.parameters, values);
+        self.update_parameters(queue);
+    }
+
+    /// Uploads the parameter values, which does not require recompiling the shader.
+    pub fn update_parameters(&mut self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.parameters_buffer,
+            0,
+            bytemuck::cast_slice(&[ParametersUniform::new(&self.parameters)]),
+        );
+        self.needs_redraw = true;
+    }
+
+    /// Uploads the volumetric scalar field sampled by `sample_field`, `None` removes it.
+    pub fn set_scalar_field(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        volume: Option<&Volume>,
+    ) -> Result<()> {
+        self.scalar_field = match volume {
+            Some(volume) if volume.components != 1 => {
+                bail!(
+                    "expected a scalar field, found {} components",
+                    volume.components
+                )
+            }
+            Some(volume) => VolumeTexture::new(device, queue, volume)?,
+            None => VolumeTexture::empty(device, 1),
+        };
+        queue.write_buffer(
+            &self.scalar_field_buffer,
+            0,
+            bytemuck::cast_slice(&[self
+                .scalar_field
+                .uniform(Normalization::IDENTITY, Outside::Clamp)]),
+        );
+        self.update_field_bind_group(device);
+        Ok(())
+    }
+
+    /// Uploads the volumetric vector field sampled by `sample_vector_field`, `None` removes it.
+    /// `mapping` is applied to the grid coordinates and the vectors.
+    pub fn set_vector_field(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        volume: Option<&Volume>,
+        mapping: Normalization,
+        outside: Outside,
+    ) -> Result<()> {
+        self.vector_field = match volume {
+            Some(volume) if volume.components != 3 => {
+                bail!(
+                    "expected a vector field, found {} components",
+                    volume.components
+                )
+            }
+            Some(volume) => VolumeTexture::new(device, queue, volume)?,
+            None => VolumeTexture::empty(device, 3),
+        };
+        self.set_vector_field_mapping(queue, mapping, outside);
+        self.update_field_bind_group(device);
+        Ok(())
+    }
+
+    /// Changes how the vector field is placed in the scene and sampled outside of its grid.
+    pub fn set_vector_field_mapping(
+        &mut self,
+        queue: &wgpu::Queue,
+        mapping: Normalization,
+        outside: Outside,
+    ) {
+        queue.write_buffer(
+            &self.vector_field_buffer,
+            0,
+            bytemuck::cast_slice(&[self.vector_field.uniform(mapping, outside)]),
+        );
         self.needs_redraw = true;
+    }
 
+    fn update_field_bind_group(&mut self, device: &wgpu::Device) {
+        self.field_bind_group = create_field_bind_group(
+            device,
+            &self.field_bind_group_layout,
+            (&self.scalar_field, &self.scalar_field_buffer),
+            (&self.vector_field, &self.vector_field_buffer),
+        );
+        self.needs_redraw = true;
+    }
+
+    pub fn switches(&self) -> ShaderSwitches {
+        self.switches
+    }
+
+    pub fn set_switches(&mut self, device: &wgpu::Device, switches: ShaderSwitches) -> Result<()> {
+        if switches == self.switches {
+            return Ok(());
+        }
+        let constants = switches.pipeline_constants(self.field_mode);
+        // The GL backend caches programs per module regardless of the constants
+        if self.backend == wgpu::Backend::Gl {
+            self.shader = capture_errors(device, || create_shader_module(device, &self.full_src))?;
+        }
+        self.compute_pipeline = capture_errors(device, || {
+            create_compute_pipeline(
+                device,
+                &self.compute_pipeline_layout,
+                &self.shader,
+                &constants,
+            )
+        })?;
+        self.switches = switches;
+        self.needs_redraw = true;
         Ok(())
     }
 
+    /// Validates and compiles the shader with the current switches.
+    /// Returns the validated source and the symbols usable in the field function along with the pipeline.
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        shader_src: &str,
+        prelude: &str,
+        field_function: &str,
+        mode: FieldMode,
+        parameters: &[Parameter],
+    ) -> Result<(
+        String,
+        wgpu::ShaderModule,
+        wgpu::ComputePipeline,
+        Vec<Symbol>,
+    )> {
+        ShaderSwitches::check_declared(shader_src)?;
+        let constants = self.switches.pipeline_constants(mode);
+        let (src, snippets) =
+            with_field_function(shader_src, prelude, parameters, field_function, mode)?;
+        validation::validate(&src, &snippets)?;
+        let helpers = helpers::parse(&src, mode.function_name())?;
+        let (shader, compute_pipeline) = capture_errors(device, || {
+            let shader = create_shader_module(device, &src);
+            let compute_pipeline =
+                create_compute_pipeline(device, &self.compute_pipeline_layout, &shader, &constants);
+            (shader, compute_pipeline)
+        })?;
+        Ok((src, shader, compute_pipeline, helpers))
+    }
+
     pub fn show(
         &mut self,
         ui: &mut egui::Ui,
@@ -703,9 +1175,16 @@ impl MainView {
                 size.y as u32 / self.downscale_factor,
             )
         {
-            self.resize_texture(rpass, device, queue, size.x as u32, size.y as u32);
+            if let Some(texture_id) = self.texture_id.take() {
+                rpass.free_texture(&texture_id);
+            }
+            self.resize_texture(device, queue, size.x as u32, size.y as u32);
         }
-        let resp = Image::new(ImageSource::Texture((self.texture_id, size).into()))
+        let texture_view = &self.texture.view;
+        let texture_id = *self.texture_id.get_or_insert_with(|| {
+            rpass.register_native_texture(device, texture_view, wgpu::FilterMode::Nearest)
+        });
+        let resp = Image::new(ImageSource::Texture((texture_id, size).into()))
             .sense(Sense::click_and_drag())
             .ui(ui);
         if resp.contains_pointer() {
@@ -718,7 +1197,6 @@ impl MainView {
             }
         }
         if resp.dragged() && resp.interact_pointer_pos().is_some() {
-            self.enable_adaptive_sampling(device);
             let pos = resp.interact_pointer_pos().unwrap();
             let camera_op = if resp.dragged_by(egui::PointerButton::Primary) {
                 CameraOperation::Rotate
@@ -735,6 +1213,7 @@ impl MainView {
             );
         } else {
             self.prev_pointer_pos = None;
+            self.set_adaptive_sampling(device, true);
         }
         // Return click position relative to render view if any
         if resp.clicked() {
@@ -749,83 +1228,93 @@ impl MainView {
         }
     }
 
-    fn enable_adaptive_sampling(&mut self, device: &wgpu::Device) {
-        if self.adaptive_sampling {
-            return;
+    fn set_adaptive_sampling(&mut self, device: &wgpu::Device, adaptive_sampling: bool) {
+        let switches = ShaderSwitches {
+            adaptive_sampling,
+            ..self.switches
+        };
+        if let Err(e) = self.set_switches(device, switches) {
+            println!("Toggling adaptive sampling failed: {:?}", e);
         }
-        self.adaptive_sampling = true;
-        let shader_src = self.shader_src.replace(
-            "let adaptive_sampling: bool = false;",
-            "let adaptive_sampling: bool = true;",
-        );
-        self.reload_shader(device, Some(&shader_src), self.field_function.clone())
-            .unwrap();
     }
 
-    fn disable_adaptive_sampling(&mut self, device: &wgpu::Device) {
-        if !self.adaptive_sampling {
-            return;
-        }
-        self.adaptive_sampling = false;
-        let shader_src = self.shader_src.replace(
-            "let adaptive_sampling: bool = true;",
-            "let adaptive_sampling: bool = false;",
-        );
-        self.reload_shader(device, Some(&shader_src), self.field_function.clone())
-            .unwrap();
+    /// Renders once with the minimum step size as initial step size.
+    pub fn render_high_accuracy(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let high_accuracy_settings = Settings {
+            h_initial: self.settings.h_min,
+            ..self.settings
+        };
+        self.render_once(device, queue, high_accuracy_settings);
     }
 
-    pub fn render_high_accuracy(
-        &mut self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        field_function: String,
-    ) {
-        let original_shader_src = self.shader_src.clone();
-        let high_accuracy_shader_src = self
-            .shader_src
-            .replace("let h_initial = 0.1;", "let h_initial = 0.001;");
-        self.reload_shader(
-            device,
-            Some(&high_accuracy_shader_src),
-            field_function.clone(),
-        )
-        .unwrap();
+    /// Renders once with `settings` instead of the current settings.
+    fn render_once(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, settings: Settings) {
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("high_accuracy_encoder"),
         });
         self.render(&mut encoder);
         queue.submit(Some(encoder.finish()));
-        self.reload_shader(device, Some(&original_shader_src), field_function.clone())
-            .unwrap();
-        self.needs_redraw = false; // prevent redraw from shader reload
+        queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.settings]),
+        );
     }
 
+    /// Renders an image of the given size like [`MainView::render_high_accuracy`], with or
+    /// without the overlay, and reads it back as opaque RGBA rows. Inside the application, the
+    /// view is resized to its widget again on the next frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_high_accuracy_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+        overlay: bool,
+    ) -> Result<image::RgbaImage, wgpu::BufferAsyncError> {
+        if self.texture.dimensions != size {
+            self.create_textures(device, queue, size);
+        }
+        let settings = Settings {
+            h_initial: self.settings.h_min,
+            overlay_mode: if overlay {
+                self.settings.overlay_mode
+            } else {
+                0
+            },
+            ..self.settings
+        };
+        self.render_once(device, queue, settings);
+        // The alpha channel holds the hit distance in some modes
+        let mut data = self.texture.read(device, queue)?;
+        data.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+        Ok(image::RgbaImage::from_raw(size.0, size.1, data).expect("texture size mismatch"))
+    }
+
+    /// Renders once with the rays of the reference view placed on the outline
+    /// of the region with high Lyapunov exponents.
     pub fn render_outline_rays(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        field_function: String,
-    ) {
-        let original_shader_src = self.shader_src.clone();
-        let high_accuracy_shader_src = self.shader_src.replace(
-            "let sample_outline_rays: bool = false;",
-            "let sample_outline_rays: bool = true;",
-        );
-        self.reload_shader(
+    ) -> Result<()> {
+        let switches = self.switches;
+        self.set_switches(
             device,
-            Some(&high_accuracy_shader_src),
-            field_function.clone(),
-        )
-        .unwrap();
+            ShaderSwitches {
+                sample_outline_rays: true,
+                ..switches
+            },
+        )?;
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("outline_rays_encoder"),
         });
         self.render(&mut encoder);
         queue.submit(Some(encoder.finish()));
-        self.reload_shader(device, Some(&original_shader_src), field_function.clone())
-            .unwrap();
-        self.needs_redraw = false; // prevent redraw from shader reload
+        self.set_switches(device, switches)?;
+        self.needs_redraw = false; // prevent redraw from pipeline recreation
+        Ok(())
     }
 
     pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder) {
@@ -839,6 +1328,7 @@ impl MainView {
         cpass.set_bind_group(0, &self.compute_bind_group, &[]);
         cpass.set_bind_group(1, &self.mesh_bind_group, &[]);
         cpass.set_bind_group(2, &self.ray_samples_bind_group, &[]);
+        cpass.set_bind_group(3, &self.field_bind_group, &[]);
         cpass.dispatch_workgroups((width + 7) / 8, (height + 7) / 8, 1);
 
         cpass.set_pipeline(&self.overlay_pipeline);
@@ -847,15 +1337,299 @@ impl MainView {
 
         self.needs_redraw = false;
     }
+
+    /// Renders the view once and reads back the resulting image
+    /// as tightly packed RGBA rows.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_to_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<image::RgbaImage, wgpu::BufferAsyncError> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("image_encoder"),
+        });
+        self.render(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        let (width, height) = self.texture.dimensions;
+        let data = self.texture.read(device, queue)?;
+        Ok(image::RgbaImage::from_raw(width, height, data).expect("texture size mismatch"))
+    }
+
+    /// Renders with the current settings and the Lyapunov exponents enabled, and reads back the
+    /// per-pixel results.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_ray_data(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+    ) -> Result<RayData> {
+        if self.switches.linear_mode {
+            bail!("straight rays have no mapping points, disable the linear mode");
+        }
+        if self.texture.dimensions != size {
+            self.create_textures(device, queue, size);
+        }
+        let settings = Settings {
+            overlay_mode: OverlayMode::LyapunovExponents as i32,
+            ..self.settings
+        };
+        self.render_once(device, queue, settings);
+        let (colors, mapping) = self
+            .read_ray_casting_results(device, queue)
+            .map_err(|e| anyhow!("reading back ray casting results failed: {}", e))?;
+        let exponents = read_buffer(device, queue, &self.exponents_buffer)
+            .map_err(|e| anyhow!("reading back Lyapunov exponents failed: {}", e))?;
+        let mut exponents = bytemuck::pod_collect_to_vec::<u8, f32>(&exponents);
+
+        // The overlay skips the border, where the central differences would leave the image
+        let (width, height) = (size.0 as usize, size.1 as usize);
+        let padding = 2 * self.settings.central_difference_delta.max(0) as usize;
+        for (index, exponent) in exponents.iter_mut().enumerate() {
+            let (x, y) = (index % width, index / width);
+            if x < padding || y < padding || x + padding >= width || y + padding >= height {
+                *exponent = f32::NAN;
+            }
+        }
+        Ok(RayData {
+            width: size.0,
+            height: size.1,
+            colors,
+            mapping,
+            exponents,
+        })
+    }
+
+    /// Reads back the ray colors and mapping points of the last render,
+    /// i.e. the results of `nonlinear_ray_color` before the overlay is applied.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_ray_casting_results(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<RayCastingResults, wgpu::BufferAsyncError> {
+        let colors = self.ray_casting_texture.read(device, queue)?;
+        let mapping = self.mapping_texture.read(device, queue)?;
+        Ok((
+            bytemuck::pod_collect_to_vec(&colors),
+            bytemuck::pod_collect_to_vec(&mapping),
+        ))
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.texture.dimensions
+    }
+
+    /// Size of the widget the view is shown in, i.e. before downscaling.
+    pub fn full_size(&self) -> (u32, u32) {
+        let (width, height) = self.texture.dimensions;
+        (
+            width * self.downscale_factor,
+            height * self.downscale_factor,
+        )
+    }
+
+    /// Describes what the view shows as key-value pairs for image metadata, without the camera.
+    pub fn image_metadata(&self) -> Vec<(&'static str, String)> {
+        let mut metadata = vec![
+            ("Software", format!("linon {}", env!("CARGO_PKG_VERSION"))),
+            (
+                "Field mode",
+                serde_json::to_value(self.field_mode)
+                    .ok()
+                    .and_then(|v| v.as_str().map(str::to_string))
+                    .unwrap_or_default(),
+            ),
+        ];
+        if !self.prelude.is_empty() {
+            metadata.push(("Prelude", self.prelude.clone()));
+        }
+        metadata.push(("Field function", self.field_function.clone()));
+        let parameters: BTreeMap<_, _> = self
+            .parameters
+            .iter()
+            .map(|p| (p.name.as_str(), p.value))
+            .collect();
+        if !parameters.is_empty() {
+            metadata.push((
+                "Parameters",
+                serde_json::to_string(&parameters).unwrap_or_default(),
+            ));
+        }
+        metadata.push((
+            "Settings",
+            serde_json::to_string(&self.settings).unwrap_or_default(),
+        ));
+        metadata
+    }
 }
 
-fn with_field_function(shader_src: &str, field_function_body: &str) -> String {
-    let field_function = format!(
-        "fn field_function(p_prev: vec3<f32>, p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32> {{\n{}\n}}",
+/// Copies the buffer contents to the CPU, blocking until the copy has finished.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("buffer_read_buffer"),
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("buffer_read_encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging_buffer.slice(..);
+    let (tx, rx) = channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        tx.send(result).expect("sending map result failed");
+    });
+    device.poll(wgpu::Maintain::Wait);
+    rx.recv().expect("receiving map result failed")?;
+    let data = slice.get_mapped_range().to_vec();
+    staging_buffer.unmap();
+    Ok(data)
+}
+
+/// Replaces the stub of the function selected by `mode` with the body and inserts the prelude
+/// before the stub field function. Returns the result together with the byte ranges of the prelude
+/// and the body in it. The parameter declarations are placed on the line of the signature to keep
+/// the line numbers of the body. Fails if the shader lacks one of the stubs.
+pub fn with_field_function(
+    shader_src: &str,
+    prelude: &str,
+    parameters: &[Parameter],
+    field_function_body: &str,
+    mode: FieldMode,
+) -> Result<(String, Snippets)> {
+    let field_function_stub = "fn field_function(p_prev: vec3<f32>, p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32> { return v; }";
+    let (stub, signature) = match mode {
+        FieldMode::Velocity => (
+            field_function_stub,
+            "fn field_function(p_prev: vec3<f32>, p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32>",
+        ),
+        FieldMode::RefractiveIndex => (
+            "fn refractive_index(p: vec3<f32>) -> f32 { return 1.0; }",
+            "fn refractive_index(p: vec3<f32>) -> f32",
+        ),
+        FieldMode::Acceleration => (
+            "fn acceleration(p: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32> { return vec3<f32>(0.0); }",
+            "fn acceleration(p: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32>",
+        ),
+    };
+    let header = format!(
+        "{} {{ {}\n",
+        signature,
+        parameters::declarations(parameters),
+    );
+    let start = shader_src
+        .find(field_function_stub)
+        .with_context(|| format!("shader lacks the stub `{}`", field_function_stub))?;
+    let stub_start = shader_src.find(stub).with_context(|| {
+        format!(
+            "shader lacks the stub `{}` of the {} mode",
+            stub,
+            mode.function_name()
+        )
+    })?;
+    if stub_start < start {
+        bail!(
+            "the stub of `{}` has to follow the stub of `field_function` in the shader",
+            mode.function_name()
+        );
+    }
+    let body_start = stub_start + prelude.len() + 1 + header.len();
+    let src = format!(
+        "{}{}\n{}{}{}\n}}{}",
+        &shader_src[..start],
+        prelude,
+        &shader_src[start..stub_start],
+        header,
         field_function_body,
+        &shader_src[stub_start + stub.len()..]
     );
-    shader_src.replace(
-        "fn field_function(p_prev: vec3<f32>, p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32> { return v; }",
-        &field_function,
-    )
+    let snippets = vec![
+        (Snippet::Prelude, start..start + prelude.len()),
+        (
+            Snippet::FieldFunction,
+            body_start..body_start + field_function_body.len(),
+        ),
+    ];
+    Ok((src, snippets))
+}
+
+/// Bind group of the scalar and vector field, each given as texture and uniform buffer.
+fn create_field_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scalar_field: (&VolumeTexture, &wgpu::Buffer),
+    vector_field: (&VolumeTexture, &wgpu::Buffer),
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&scalar_field.0.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: scalar_field.1.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&vector_field.0.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: vector_field.1.as_entire_binding(),
+            },
+        ],
+        label: Some("field_bind_group"),
+    })
+}
+
+fn create_shader_module(device: &wgpu::Device, src: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("compute_shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(src)),
+    })
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    constants: &HashMap<String, f64>,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("compute_pipeline"),
+        layout: Some(layout),
+        module,
+        entry_point: "main_view",
+        compilation_options: wgpu::PipelineCompilationOptions {
+            constants,
+            ..Default::default()
+        },
+        cache: None,
+    })
+}
+
+/// Runs `f` and returns the first validation error it caused, if any.
+fn capture_errors<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    let (tx, rx) = channel::<wgpu::Error>();
+    device.on_uncaptured_error(Box::new(move |e: wgpu::Error| {
+        tx.send(e).expect("sending error failed");
+    }));
+    let result = f();
+    device.on_uncaptured_error(Box::new(|e| panic!("{}", e)));
+    match rx.try_recv() {
+        Ok(err) => Err(err),
+        Err(_) => Ok(result),
+    }
 }
@@ -20,6 +20,18 @@ struct Settings {
     overlay_mode: i32,
     central_difference_delta: i32,
     lyapunov_scaling: f32,
+    integration_scheme: i32,
+    abs_tol: f32,
+    rel_tol: f32,
+    h_min: f32,
+    h_max: f32,
+    t_max: f32,
+    h_initial: f32,
+    max_steps: u32,
+    // One of the BACKGROUND_* constants
+    background: i32,
+    // Global animation time in seconds, see `animation_time`
+    time: f32,
 };
 @group(0) @binding(3)
 var<uniform> settings: Settings;
@@ -30,6 +42,13 @@ struct Exponents {
 @group(0) @binding(4)
 var<storage, read> exponents: Exponents;
 
+// Values of the field function parameters, see parameters.rs
+struct Parameters {
+    data: array<vec4<f32>, 16>,
+};
+@group(0) @binding(5)
+var<uniform> parameters: Parameters;
+
 struct Vertex {
     x: f32,
     y: f32,
@@ -52,6 +71,21 @@ struct Faces {
 @group(1) @binding(1)
 var<storage, read> faces: Faces;
 
+// Bounding volume hierarchy over the faces, built in bvh.rs
+struct BvhNode {
+    min: vec3<f32>,
+    // Index of the left child (the right child follows it), or of the first face for leaves
+    left_first: u32,
+    max: vec3<f32>,
+    // Number of faces for leaves, 0 for inner nodes
+    count: u32,
+};
+struct Bvh {
+    data: array<BvhNode>,
+};
+@group(1) @binding(2)
+var<storage, read> bvh: Bvh;
+
 struct RaySample {
     position: vec4<f32>,
     color: vec4<f32>,
@@ -62,19 +96,57 @@ struct RaySamples {
 @group(2) @binding(0)
 var<storage, read_write> ray_samples: RaySamples;
 
-const backface_culling: bool = false;
+// Placement of a volume loaded from a file in the scene, see volume.rs
+struct Volume {
+    origin: vec4<f32>,
+    spacing: vec4<f32>,
+    // Factor applied to the values
+    scale: f32,
+    // Behavior outside of the grid, one of the OUTSIDE_* constants
+    outside: i32,
+};
+@group(3) @binding(0)
+var scalar_field: texture_3d<f32>;
+@group(3) @binding(1)
+var<uniform> scalar_field_volume: Volume;
+@group(3) @binding(2)
+var vector_field: texture_3d<f32>;
+@group(3) @binding(3)
+var<uniform> vector_field_volume: Volume;
+
+const OUTSIDE_ZERO: i32 = 0;
+const OUTSIDE_CLAMP: i32 = 1;
+const OUTSIDE_FALLBACK: i32 = 2;
+
+override backface_culling: bool = false;
 
 const light_color: vec3<f32> = vec3<f32>(1.0, 1.0, 1.0);
 const ambient_strength: f32 = 0.01;
 const shininess: f32 = 64.0;
 const object_color: vec3<f32> = vec3<f32>(0.5, 0.5, 0.5);
 
-const linear_mode: bool = false;
-const use_lighting: bool = true;
+override linear_mode: bool = false;
+// Integrate the ray equation of `refractive_index` instead of the velocity field
+override refractive_index_mode: bool = false;
+// Integrate the velocity of rays with the result of `acceleration` as its derivative
+override acceleration_mode: bool = false;
+
+// Must match `Background` in application.rs
+const BACKGROUND_BLACK: i32 = 0;
+const BACKGROUND_CHECKERBOARD: i32 = 1;
+const BACKGROUND_STARS: i32 = 2;
+
+// Set by `absorb_ray`, reset at the start of every ray
+var<private> ray_absorbed: bool = false;
+override use_lighting: bool = true;
 const eps: f32 = 0.0000001;
 
+// Helpers for field functions, everything up to `field_function` is offered
+// for completion in the editor, see helpers.rs
+
 const PI: f32 = 3.141592653589793;
 
+// Rotates v around the x-axis by the angle phi
 fn rotateX(v: vec3<f32>, phi: f32) -> vec3<f32> {
     return mat3x3<f32>(
         vec3<f32>(1.0, 0.0, 0.0),
@@ -83,6 +155,7 @@ fn rotateX(v: vec3<f32>, phi: f32) -> vec3<f32> {
     ) * v;
 }
 
+// Rotates v around the y-axis by the angle phi
 fn rotateY(v: vec3<f32>, phi: f32) -> vec3<f32> {
     return mat3x3<f32>(
         vec3<f32>(cos(phi), 0.0, -sin(phi)),
@@ -91,6 +164,7 @@ fn rotateY(v: vec3<f32>, phi: f32) -> vec3<f32> {
     ) * v;
 }
 
+// Rotates v around the z-axis by the angle phi
 fn rotateZ(v: vec3<f32>, phi: f32) -> vec3<f32> {
     return mat3x3<f32>(
         vec3<f32>(cos(phi), sin(phi), 0.0),
@@ -99,6 +173,7 @@ fn rotateZ(v: vec3<f32>, phi: f32) -> vec3<f32> {
     ) * v;
 }
 
+// Translates v by the deltas dx, dy and dz
 fn translate(v: vec3<f32>, dx: f32, dy: f32, dz: f32) -> vec3<f32> {
     return vec3<f32>(
         v.x + dx,
@@ -107,6 +182,7 @@ fn translate(v: vec3<f32>, dx: f32, dy: f32, dz: f32) -> vec3<f32> {
     );
 }
 
+// Refraction index of air
 // t: temperature in Celsius
 fn refraction_index(t: f32) -> f32 {
     // Calculation term by Y. Zhao et al
@@ -116,6 +192,7 @@ fn refraction_index(t: f32) -> f32 {
     return c1 * air_pressure * (1.0 + air_pressure * (60.1 - 0.972 * t) * pow(10.0, -10.0)) / (1.0 + c2 * t);
 }
 
+// Refracts v_in at a surface with normal n from temperature t_in to t_out
 fn refraction(t_in: f32, t_out: f32, v_in: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
     let eta_in = refraction_index(t_in);
     let eta_out = refraction_index(t_out);
@@ -135,17 +212,103 @@ fn refraction(t_in: f32, t_out: f32, v_in: vec3<f32>, n: vec3<f32>) -> vec3<f32>
     return eta * v_in + (eta * cosi - sqrt(k)) * n_ref;
 }
 
+// Distance between p and the plane through p0 with normal n
 fn point_plane_distance(p: vec3<f32>, n: vec3<f32>, p0: vec3<f32>) -> f32 {
     let d = dot(p0, n);
     // assuming n is a unit vector
     return abs(dot(p, n) - d);
 }
 
+// The logistic function 1 / (1 + e^-x)
 fn sigmoid(x: f32) -> f32 {
     return 1.0 / (1.0 + exp(-x));
 }
 
+// Trilinear interpolation of the volumetric scalar field loaded from a file at p,
+// clamped to the grid, 0 if no field is loaded
+fn sample_field(p: vec3<f32>) -> f32 {
+    let size = vec3<i32>(textureDimensions(scalar_field));
+    let grid = clamp(
+        (p - scalar_field_volume.origin.xyz) / scalar_field_volume.spacing.xyz,
+        vec3<f32>(0.0),
+        vec3<f32>(size - 1),
+    );
+    let i0 = vec3<i32>(floor(grid));
+    let i1 = min(i0 + 1, size - 1);
+    let f = grid - vec3<f32>(i0);
+    let c00 = mix(textureLoad(scalar_field, i0, 0).r, textureLoad(scalar_field, vec3<i32>(i1.x, i0.y, i0.z), 0).r, f.x);
+    let c10 = mix(textureLoad(scalar_field, vec3<i32>(i0.x, i1.y, i0.z), 0).r, textureLoad(scalar_field, vec3<i32>(i1.x, i1.y, i0.z), 0).r, f.x);
+    let c01 = mix(textureLoad(scalar_field, vec3<i32>(i0.x, i0.y, i1.z), 0).r, textureLoad(scalar_field, vec3<i32>(i1.x, i0.y, i1.z), 0).r, f.x);
+    let c11 = mix(textureLoad(scalar_field, vec3<i32>(i0.x, i1.y, i1.z), 0).r, textureLoad(scalar_field, i1, 0).r, f.x);
+    return mix(mix(c00, c10, f.y), mix(c01, c11, f.y), f.z);
+}
+
+// Trilinear interpolation of the loaded vector field at p, outside of the grid
+// zero, clamped to the grid or the fallback (usually v) as selected
+fn sample_vector_field(p: vec3<f32>, fallback: vec3<f32>) -> vec3<f32> {
+    let size = vec3<i32>(textureDimensions(vector_field));
+    let unclamped = (p - vector_field_volume.origin.xyz) / vector_field_volume.spacing.xyz;
+    let grid = clamp(unclamped, vec3<f32>(0.0), vec3<f32>(size - 1));
+    if (any(grid != unclamped)) {
+        if (vector_field_volume.outside == OUTSIDE_ZERO) {
+            return vec3<f32>(0.0);
+        } else if (vector_field_volume.outside == OUTSIDE_FALLBACK) {
+            return fallback;
+        }
+    }
+    let i0 = vec3<i32>(floor(grid));
+    let i1 = min(i0 + 1, size - 1);
+    let f = grid - vec3<f32>(i0);
+    let c00 = mix(textureLoad(vector_field, i0, 0).xyz, textureLoad(vector_field, vec3<i32>(i1.x, i0.y, i0.z), 0).xyz, f.x);
+    let c10 = mix(textureLoad(vector_field, vec3<i32>(i0.x, i1.y, i0.z), 0).xyz, textureLoad(vector_field, vec3<i32>(i1.x, i1.y, i0.z), 0).xyz, f.x);
+    let c01 = mix(textureLoad(vector_field, vec3<i32>(i0.x, i0.y, i1.z), 0).xyz, textureLoad(vector_field, vec3<i32>(i1.x, i0.y, i1.z), 0).xyz, f.x);
+    let c11 = mix(textureLoad(vector_field, vec3<i32>(i0.x, i1.y, i1.z), 0).xyz, textureLoad(vector_field, i1, 0).xyz, f.x);
+    return vector_field_volume.scale * mix(mix(c00, c10, f.y), mix(c01, c11, f.y), f.z);
+}
+
+// Global animation time in seconds, set by the time controls of the side panel.
+// Unlike the integration time t, it changes while the field is explored.
+fn animation_time() -> f32 {
+    return settings.time;
+}
+
+// Ends the ray after the current integration step, e.g. at an event horizon.
+// Rays absorbed before hitting the scene are black.
+fn absorb_ray() {
+    ray_absorbed = true;
+}
+
+// Acceleration of a particle with velocity v and charge-to-mass ratio q_m
+// in the electric field e and the magnetic field b
+fn lorentz_force(v: vec3<f32>, e: vec3<f32>, b: vec3<f32>, q_m: f32) -> vec3<f32> {
+    return q_m * (e + cross(v, b));
+}
+
+// Magnetic field at p of a dipole with magnetic moment m at center, in units of mu0 / (4 pi).
+// The distance is limited to 0.01 to avoid the singularity at the center.
+fn dipole_field(p: vec3<f32>, center: vec3<f32>, m: vec3<f32>) -> vec3<f32> {
+    let r = p - center;
+    let d = max(length(r), 0.01);
+    let n = r / d;
+    return (3.0 * n * dot(m, n) - m) / (d * d * d);
+}
+
+// Gradient of sample_field at p by central differences over one grid cell
+fn sample_gradient(p: vec3<f32>) -> vec3<f32> {
+    let h = scalar_field_volume.spacing.xyz;
+    let dx = vec3<f32>(h.x, 0.0, 0.0);
+    let dy = vec3<f32>(0.0, h.y, 0.0);
+    let dz = vec3<f32>(0.0, 0.0, h.z);
+    return vec3<f32>(
+        sample_field(p + dx) - sample_field(p - dx),
+        sample_field(p + dy) - sample_field(p - dy),
+        sample_field(p + dz) - sample_field(p - dz),
+    ) / (2.0 * h);
+}
+
 fn field_function(p_prev: vec3<f32>, p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32> { return v; }
+fn refractive_index(p: vec3<f32>) -> f32 { return 1.0; }
+fn acceleration(p: vec3<f32>, v: vec3<f32>, t: f32) -> vec3<f32> { return vec3<f32>(0.0); }
 
 fn hit_triangle(v_in: array<vec3<f32>, 3>, origin: vec3<f32>, direction: vec3<f32>) -> f32 {
     // Moeller-Trumbore intersection algorithm
@@ -174,33 +337,70 @@ fn hit_triangle(v_in: array<vec3<f32>, 3>, origin: vec3<f32>, direction: vec3<f3
     return -1.0;
 }
 
+fn hit_aabb(box_min: vec3<f32>, box_max: vec3<f32>, origin: vec3<f32>, inv_direction: vec3<f32>, max_dist: f32) -> bool {
+    // Slab test
+    let t1 = (box_min - origin) * inv_direction;
+    let t2 = (box_max - origin) * inv_direction;
+    let t_near = max(max(min(t1.x, t2.x), min(t1.y, t2.y)), min(t1.z, t2.z));
+    let t_far = min(min(max(t1.x, t2.x), max(t1.y, t2.y)), max(t1.z, t2.z));
+    return t_near <= t_far && t_far >= 0.0 && t_near <= max_dist;
+}
+
 fn ray_color(origin: vec3<f32>, direction: vec3<f32>, max_dist: f32) -> vec4<f32> {
     var t: f32 = -1.0;
     var t_new: f32;
     var d1: vec3<f32>;
     var d2: vec3<f32>;
-    for (var i: u32 = 0u; i < arrayLength(&faces.data); i = i + 1u) {
-        let face = faces.data[i];
-        let a = vertices.data[face.a];
-        let b = vertices.data[face.b];
-        let c = vertices.data[face.c];
-        let ttriangle = array<vec3<f32>, 3>(
-            vec3<f32>(a.x, a.y, a.z),
-            vec3<f32>(b.x, b.y, b.z),
-            vec3<f32>(c.x, c.y, c.z),
-        );
-        t_new = hit_triangle(ttriangle, origin, direction);
-        if (t_new > 0.0 && t_new < max_dist && (t < 0.0 || t_new < t)) {
-            t = t_new;
-            d1 = ttriangle[1] - ttriangle[0];
-            d2 = ttriangle[2] - ttriangle[0];
+    // Avoid divisions by zero for rays parallel to an axis
+    let safe_direction = select(direction, vec3<f32>(1e-20), abs(direction) < vec3<f32>(1e-20));
+    let inv_direction = 1.0 / safe_direction;
+    // The tree depth is limited to 30 in bvh.rs, so the stack cannot overflow
+    var stack: array<u32, 32>;
+    var stack_size: u32 = 1u;
+    stack[0] = 0u;
+    while (stack_size > 0u) {
+        stack_size = stack_size - 1u;
+        let node = bvh.data[stack[stack_size]];
+        // An empty mesh is represented by a root that neither has children nor faces
+        if (node.count == 0u && node.left_first == 0u) {
+            break;
+        }
+        var closest = max_dist;
+        if (t > 0.0) {
+            closest = t;
+        }
+        if (!hit_aabb(node.min, node.max, origin, inv_direction, closest)) {
+            continue;
+        }
+        if (node.count == 0u) {
+            stack[stack_size] = node.left_first;
+            stack[stack_size + 1u] = node.left_first + 1u;
+            stack_size = stack_size + 2u;
+            continue;
+        }
+        for (var i: u32 = node.left_first; i < node.left_first + node.count; i = i + 1u) {
+            let face = faces.data[i];
+            let a = vertices.data[face.a];
+            let b = vertices.data[face.b];
+            let c = vertices.data[face.c];
+            let ttriangle = array<vec3<f32>, 3>(
+                vec3<f32>(a.x, a.y, a.z),
+                vec3<f32>(b.x, b.y, b.z),
+                vec3<f32>(c.x, c.y, c.z),
+            );
+            t_new = hit_triangle(ttriangle, origin, direction);
+            if (t_new > 0.0 && t_new < max_dist && (t < 0.0 || t_new < t)) {
+                t = t_new;
+                d1 = ttriangle[1] - ttriangle[0];
+                d2 = ttriangle[2] - ttriangle[0];
+            }
         }
     }
     if (t > 0.0 && t < max_dist) {
         let normal = normalize(cross(d1, d2));
         if (!use_lighting) {
             let color = abs(normal);
-            return vec4<f32>(color, 1.0);
+            return vec4<f32>(color, t);
         }
         let ambient = ambient_strength * light_color;
         // The camera is the light source here, which allows for
@@ -219,97 +419,402 @@ fn ray_color(origin: vec3<f32>, direction: vec3<f32>, max_dist: f32) -> vec4<f32
     return vec4<f32>(0.0, 0.0, 0.0, 0.0);
 }
 
+// Color of rays that leave the scene in direction `dir` without hitting it
+fn background_color(dir: vec3<f32>) -> vec4<f32> {
+    if (settings.background == BACKGROUND_CHECKERBOARD) {
+        // 24 x 12 fields of longitude and latitude on the celestial sphere
+        let longitude = atan2(dir.z, dir.x) / PI * 12.0;
+        let latitude = asin(clamp(dir.y, -1.0, 1.0)) / PI * 12.0;
+        let field = (i32(floor(longitude)) + i32(floor(latitude))) & 1;
+        let tint = 0.5 + 0.5 * abs(dir);
+        return vec4<f32>(select(0.2, 0.6, field == 0) * tint, 1.0);
+    } else if (settings.background == BACKGROUND_STARS) {
+        // A star with random brightness at the center of a few of the cells of a grid over the
+        // directions, fading out towards the border so that it appears as a dot
+        let cell = floor(dir * 150.0);
+        let hash = fract(sin(dot(cell, vec3<f32>(12.9898, 78.233, 37.719))) * 43758.5453);
+        let falloff = 1.0 - smoothstep(0.1, 0.4, length(fract(dir * 150.0) - 0.5));
+        let brightness = select(0.0, (hash - 0.97) / 0.03, hash > 0.97) * falloff;
+        let sky = vec3<f32>(0.0, 0.01, 0.03) * (1.0 + dir.y);
+        return vec4<f32>(sky + vec3<f32>(brightness), 1.0);
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
+
 struct NonlinearRayColorResult {
     color: vec4<f32>,
+    // End point of the ray in xyz and the length of the path to the first hit in w,
+    // which is 0 if the ray hits nothing
     mapping_point: vec4<f32>,
 };
 
-const adaptive_sampling: bool = true;
+override adaptive_sampling: bool = true;
+
+// Must match `IntegrationScheme` in integrator.rs
+const INTEGRATION_SCHEME_RK4: i32 = 0;
+const INTEGRATION_SCHEME_RK45: i32 = 1;
+const INTEGRATION_SCHEME_EULER: i32 = 2;
+const INTEGRATION_SCHEME_MIDPOINT: i32 = 3;
+const INTEGRATION_SCHEME_LEAPFROG: i32 = 4;
+
+fn euler_step(p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32, h: f32) -> vec3<f32> {
+    return field_function(p, p, v0, v, t);
+}
+
+fn midpoint_step(p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32, h: f32) -> vec3<f32> {
+    let k1 = field_function(p, p, v0, v, t);
+    return field_function(p, p + 0.5 * h * k1, v0, k1, t + 0.5 * h);
+}
+
+fn rk4_step(p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32, h: f32) -> vec3<f32> {
+    let k1 = field_function(p, p, v0, v, t);
+    let k2 = field_function(p, p + 0.5 * h * k1, v0, k1, t + 0.5 * h);
+    let k3 = field_function(p, p + 0.5 * h * k2, v0, k2, t + 0.5 * h);
+    let k4 = field_function(p, p + h * k3, v0, k3, t + h);
+    return (k1 + 2.0 * k2 + 2.0 * k3 + k4) / 6.0;
+}
+
+// Staggered leapfrog: the velocity lives at the half steps and is evaluated at the midpoint
+// predicted with the velocity of the previous half step, so that one evaluation per step gives
+// second order, the position then drifts the full step with the new velocity
+fn leapfrog_step(p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32, h: f32) -> vec3<f32> {
+    return field_function(p, p + 0.5 * h * v, v0, v, t + 0.5 * h);
+}
+
+struct Rk45Step {
+    v: vec3<f32>,
+    // Local position error relative to the tolerances, the step is accepted if <= 1
+    error: f32,
+};
+
+// Dormand-Prince 5(4) step, the error is estimated from the embedded 4th order solution
+fn rk45_step(p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32, h: f32) -> Rk45Step {
+    let k1 = field_function(p, p, v0, v, t);
+    let k2 = field_function(p, p + h * (1.0 / 5.0 * k1), v0, k1, t + 1.0 / 5.0 * h);
+    let k3 = field_function(p, p + h * (3.0 / 40.0 * k1 + 9.0 / 40.0 * k2), v0, k2, t + 3.0 / 10.0 * h);
+    let k4 = field_function(p, p + h * (44.0 / 45.0 * k1 - 56.0 / 15.0 * k2 + 32.0 / 9.0 * k3), v0, k3, t + 4.0 / 5.0 * h);
+    let k5 = field_function(p, p + h * (19372.0 / 6561.0 * k1 - 25360.0 / 2187.0 * k2 + 64448.0 / 6561.0 * k3 - 212.0 / 729.0 * k4), v0, k4, t + 8.0 / 9.0 * h);
+    let k6 = field_function(p, p + h * (9017.0 / 3168.0 * k1 - 355.0 / 33.0 * k2 + 46732.0 / 5247.0 * k3 + 49.0 / 176.0 * k4 - 5103.0 / 18656.0 * k5), v0, k5, t + h);
+    let v5 = 35.0 / 384.0 * k1 + 500.0 / 1113.0 * k3 + 125.0 / 192.0 * k4 - 2187.0 / 6784.0 * k5 + 11.0 / 84.0 * k6;
+    let k7 = field_function(p, p + h * v5, v0, k6, t + h);
+    let v4 = 5179.0 / 57600.0 * k1 + 7571.0 / 16695.0 * k3 + 393.0 / 640.0 * k4 - 92097.0 / 339200.0 * k5 + 187.0 / 2100.0 * k6 + 1.0 / 40.0 * k7;
+
+    // Only the weighted part of the field contributes to the position
+    let delta = abs(h * settings.field_weight * (v5 - v4));
+    let scale = settings.abs_tol + settings.rel_tol * max(abs(p), abs(p + h * v5));
+    let ratio = delta / scale;
+    var result: Rk45Step;
+    result.v = v5;
+    result.error = max(ratio.x, max(ratio.y, ratio.z));
+    return result;
+}
+
+// Step size for the next step of an adaptive scheme of order 5
+fn next_step_size(h: f32, error: f32) -> f32 {
+    let factor = clamp(0.9 * pow(max(error, 0.0001), -0.2), 0.2, 5.0);
+    return clamp(h * factor, settings.h_min, settings.h_max);
+}
+
+// Second order modes: the state of a ray is its position and its direction, whose derivative is
+// given by `ray_acceleration`.
+// In gradient-index mode, the ray equation d/ds (n dr/ds) = grad n is integrated with the
+// parameter d(sigma) = ds / n, which gives d^2r/d(sigma)^2 = grad(n^2) / 2 (Sharma et al. 1982).
+// The direction is then the optical direction dr/d(sigma) = n dr/ds.
+
+// Distance of the central differences for the gradient of the refractive index
+const GRIN_DELTA: f32 = 0.001;
+
+fn refractive_index_squared(p: vec3<f32>) -> f32 {
+    let n = refractive_index(p);
+    return n * n;
+}
+
+// grad(n^2) / 2 at p
+fn grin_acceleration(p: vec3<f32>) -> vec3<f32> {
+    let dx = vec3<f32>(GRIN_DELTA, 0.0, 0.0);
+    let dy = vec3<f32>(0.0, GRIN_DELTA, 0.0);
+    let dz = vec3<f32>(0.0, 0.0, GRIN_DELTA);
+    let gradient = vec3<f32>(
+        refractive_index_squared(p + dx) - refractive_index_squared(p - dx),
+        refractive_index_squared(p + dy) - refractive_index_squared(p - dy),
+        refractive_index_squared(p + dz) - refractive_index_squared(p - dz),
+    ) / (2.0 * GRIN_DELTA);
+    return 0.5 * gradient;
+}
+
+// Derivative of the direction of a ray, weighted by the field weight so that 0 gives straight rays
+fn ray_acceleration(p: vec3<f32>, dir: vec3<f32>, t: f32) -> vec3<f32> {
+    if (refractive_index_mode) {
+        return settings.field_weight * grin_acceleration(p);
+    }
+    return settings.field_weight * acceleration(p, dir, t);
+}
+
+struct SecondOrderStep {
+    // Position change and optical direction after the step
+    delta: vec3<f32>,
+    dir: vec3<f32>,
+    // Local position error relative to the tolerances, only estimated by RK45
+    error: f32,
+};
+
+fn second_order_euler_step(p: vec3<f32>, dir: vec3<f32>, t: f32, h: f32) -> SecondOrderStep {
+    var result: SecondOrderStep;
+    result.delta = h * dir;
+    result.dir = dir + h * ray_acceleration(p, dir, t);
+    return result;
+}
+
+fn second_order_midpoint_step(p: vec3<f32>, dir: vec3<f32>, t: f32, h: f32) -> SecondOrderStep {
+    let dir_mid = dir + 0.5 * h * ray_acceleration(p, dir, t);
+    var result: SecondOrderStep;
+    result.delta = h * dir_mid;
+    result.dir = dir + h * ray_acceleration(p + 0.5 * h * dir, dir_mid, t + 0.5 * h);
+    return result;
+}
+
+fn second_order_rk4_step(p: vec3<f32>, dir: vec3<f32>, t: f32, h: f32) -> SecondOrderStep {
+    let a1 = ray_acceleration(p, dir, t);
+    let d2 = dir + 0.5 * h * a1;
+    let a2 = ray_acceleration(p + 0.5 * h * dir, d2, t + 0.5 * h);
+    let d3 = dir + 0.5 * h * a2;
+    let a3 = ray_acceleration(p + 0.5 * h * d2, d3, t + 0.5 * h);
+    let d4 = dir + h * a3;
+    let a4 = ray_acceleration(p + h * d3, d4, t + h);
+    var result: SecondOrderStep;
+    result.delta = h * (dir + 2.0 * d2 + 2.0 * d3 + d4) / 6.0;
+    result.dir = dir + h * (a1 + 2.0 * a2 + 2.0 * a3 + a4) / 6.0;
+    return result;
+}
+
+// Drift-kick-drift, which is symplectic if the acceleration does not depend on the direction,
+// as in gradient-index mode. Otherwise the kick uses the direction predicted for the half step.
+fn second_order_leapfrog_step(p: vec3<f32>, dir: vec3<f32>, t: f32, h: f32) -> SecondOrderStep {
+    let p_mid = p + 0.5 * h * dir;
+    var dir_mid = dir;
+    if (!refractive_index_mode) {
+        dir_mid = dir + 0.5 * h * ray_acceleration(p_mid, dir, t + 0.5 * h);
+    }
+    var result: SecondOrderStep;
+    result.dir = dir + h * ray_acceleration(p_mid, dir_mid, t + 0.5 * h);
+    result.delta = 0.5 * h * (dir + result.dir);
+    return result;
+}
+
+// Dormand-Prince 5(4) step with the same coefficients as `rk45_step`
+fn second_order_rk45_step(p: vec3<f32>, dir: vec3<f32>, t: f32, h: f32) -> SecondOrderStep {
+    let d1 = dir;
+    let a1 = ray_acceleration(p, d1, t);
+    let d2 = dir + h * (1.0 / 5.0 * a1);
+    let a2 = ray_acceleration(p + h * (1.0 / 5.0 * d1), d2, t + 1.0 / 5.0 * h);
+    let d3 = dir + h * (3.0 / 40.0 * a1 + 9.0 / 40.0 * a2);
+    let a3 = ray_acceleration(p + h * (3.0 / 40.0 * d1 + 9.0 / 40.0 * d2), d3, t + 3.0 / 10.0 * h);
+    let d4 = dir + h * (44.0 / 45.0 * a1 - 56.0 / 15.0 * a2 + 32.0 / 9.0 * a3);
+    let a4 = ray_acceleration(p + h * (44.0 / 45.0 * d1 - 56.0 / 15.0 * d2 + 32.0 / 9.0 * d3), d4, t + 4.0 / 5.0 * h);
+    let d5 = dir + h * (19372.0 / 6561.0 * a1 - 25360.0 / 2187.0 * a2 + 64448.0 / 6561.0 * a3 - 212.0 / 729.0 * a4);
+    let a5 = ray_acceleration(p + h * (19372.0 / 6561.0 * d1 - 25360.0 / 2187.0 * d2 + 64448.0 / 6561.0 * d3 - 212.0 / 729.0 * d4), d5, t + 8.0 / 9.0 * h);
+    let d6 = dir + h * (9017.0 / 3168.0 * a1 - 355.0 / 33.0 * a2 + 46732.0 / 5247.0 * a3 + 49.0 / 176.0 * a4 - 5103.0 / 18656.0 * a5);
+    let a6 = ray_acceleration(p + h * (9017.0 / 3168.0 * d1 - 355.0 / 33.0 * d2 + 46732.0 / 5247.0 * d3 + 49.0 / 176.0 * d4 - 5103.0 / 18656.0 * d5), d6, t + h);
+    let delta5 = h * (35.0 / 384.0 * d1 + 500.0 / 1113.0 * d3 + 125.0 / 192.0 * d4 - 2187.0 / 6784.0 * d5 + 11.0 / 84.0 * d6);
+    let d7 = dir + h * (35.0 / 384.0 * a1 + 500.0 / 1113.0 * a3 + 125.0 / 192.0 * a4 - 2187.0 / 6784.0 * a5 + 11.0 / 84.0 * a6);
+    let delta4 = h * (5179.0 / 57600.0 * d1 + 7571.0 / 16695.0 * d3 + 393.0 / 640.0 * d4 - 92097.0 / 339200.0 * d5 + 187.0 / 2100.0 * d6 + 1.0 / 40.0 * d7);
+
+    let scale = settings.abs_tol + settings.rel_tol * max(abs(p), abs(p + delta5));
+    let ratio = abs(delta5 - delta4) / scale;
+    var result: SecondOrderStep;
+    result.delta = delta5;
+    result.dir = d7;
+    result.error = max(ratio.x, max(ratio.y, ratio.z));
+    return result;
+}
+
+struct IntegrationStep {
+    // If not accepted, the step has to be repeated with step size `h_next`
+    accepted: bool,
+    // Result of the integration scheme, the new direction in the second order modes
+    v: vec3<f32>,
+    // Direction and position change after applying the field weight
+    dir: vec3<f32>,
+    delta: vec3<f32>,
+    h_next: f32,
+};
+
+fn second_order_integration_step(p: vec3<f32>, dir: vec3<f32>, t: f32, h: f32) -> IntegrationStep {
+    let integration_scheme = settings.integration_scheme;
+    var result: IntegrationStep;
+    result.accepted = true;
+    result.h_next = h;
+    var step: SecondOrderStep;
+    if (integration_scheme == INTEGRATION_SCHEME_RK45) {
+        step = second_order_rk45_step(p, dir, t, h);
+        result.h_next = next_step_size(h, step.error);
+        result.accepted = step.error <= 1.0 || h <= settings.h_min;
+    } else if (integration_scheme == INTEGRATION_SCHEME_EULER) {
+        step = second_order_euler_step(p, dir, t, h);
+    } else if (integration_scheme == INTEGRATION_SCHEME_MIDPOINT) {
+        step = second_order_midpoint_step(p, dir, t, h);
+    } else if (integration_scheme == INTEGRATION_SCHEME_LEAPFROG) {
+        step = second_order_leapfrog_step(p, dir, t, h);
+    } else {
+        step = second_order_rk4_step(p, dir, t, h);
+    }
+    result.v = step.dir;
+    result.dir = step.dir;
+    result.delta = step.delta;
+    return result;
+}
+
+// Direction a ray starts with, scaled by the refractive index in gradient-index mode
+fn initial_direction(start_point: vec3<f32>, start_dir: vec3<f32>) -> vec3<f32> {
+    if (refractive_index_mode) {
+        return refractive_index(start_point) * start_dir;
+    }
+    return start_dir;
+}
+
+fn integration_step(p: vec3<f32>, v0: vec3<f32>, v: vec3<f32>, t: f32, h: f32) -> IntegrationStep {
+    if (refractive_index_mode || acceleration_mode) {
+        return second_order_integration_step(p, v, t, h);
+    }
+    let integration_scheme = settings.integration_scheme;
+    var result: IntegrationStep;
+    result.accepted = true;
+    result.h_next = h;
+    if (integration_scheme == INTEGRATION_SCHEME_RK45) {
+        let step = rk45_step(p, v0, v, t, h);
+        result.v = step.v;
+        result.h_next = next_step_size(h, step.error);
+        result.accepted = step.error <= 1.0 || h <= settings.h_min;
+    } else if (integration_scheme == INTEGRATION_SCHEME_EULER) {
+        result.v = euler_step(p, v0, v, t, h);
+    } else if (integration_scheme == INTEGRATION_SCHEME_MIDPOINT) {
+        result.v = midpoint_step(p, v0, v, t, h);
+    } else if (integration_scheme == INTEGRATION_SCHEME_LEAPFROG) {
+        result.v = leapfrog_step(p, v0, v, t, h);
+    } else {
+        result.v = rk4_step(p, v0, v, t, h);
+    }
+    result.dir = (1.0 - settings.field_weight) * v + settings.field_weight * result.v;
+    result.delta = h * result.dir;
+    return result;
+}
 
 fn nonlinear_ray_color(start_point: vec3<f32>, start_dir: vec3<f32>) -> NonlinearRayColorResult {
     var result: NonlinearRayColorResult;
     result.mapping_point = vec4<f32>(0.0, 0.0, 0.0, 0.0);
 
-    let field_weight = settings.field_weight;
+    let is_adaptive = settings.integration_scheme == INTEGRATION_SCHEME_RK45;
     var has_color: bool = false;
     var has_mapping_point: bool = false;
     var cur_point: vec3<f32> = start_point;
-    var cur_dir: vec3<f32> = start_dir;
+    var cur_dir: vec3<f32> = initial_direction(start_point, start_dir);
     var t: f32 = 0.0;
     var last_v: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
     var last_diff: f32 = -1.0;
-    let h_initial = 0.1;
+    var path_length: f32 = 0.0;
+    var hit_distance: f32 = 0.0;
+    let h_initial = settings.h_initial;
     var h: f32 = h_initial;
+    if (is_adaptive) {
+        h = clamp(h_initial, settings.h_min, settings.h_max);
+    }
 
-    for (; t <= 5.0;) {
-        // Runge-Kutta method
-        let k1 = field_function(cur_point, cur_point, start_dir, cur_dir, t);
-        let k2 = field_function(cur_point, cur_point + 0.5 * h * k1, start_dir, k1, t + 0.5 * h);
-        let k3 = field_function(cur_point, cur_point + 0.5 * h * k2, start_dir, k2, t + 0.5 * h);
-        let k4 = field_function(cur_point, cur_point + h * k3, start_dir, k3, t + h);
-        let v = (k1 + 2.0 * k2 + 2.0 * k3 + k4) / 6.0;
-        let diff = length(v - last_v);
-        if (adaptive_sampling && last_diff >= 0.0 && diff > 10.0 * last_diff && h > 0.002) {
-            h = 0.001;
+    for (var steps: u32 = 0u; t <= settings.t_max && steps < settings.max_steps; steps = steps + 1u) {
+        // Only absorptions during the step that is finally taken count
+        ray_absorbed = false;
+        let step = integration_step(cur_point, start_dir, cur_dir, t, h);
+        if (!step.accepted) {
+            h = step.h_next;
             continue;
         }
-        cur_dir = (1.0 - field_weight) * cur_dir + field_weight * v;
-
-        let step_dir = cur_dir * h;
+        let v = step.v;
+        var h_next: f32 = step.h_next;
+        if (!is_adaptive) {
+            // Heuristic step size control for the fixed step schemes
+            let diff = length(v - last_v);
+            if (adaptive_sampling && last_diff >= 0.0 && diff > 10.0 * last_diff && h > 2.0 * settings.h_min) {
+                h = settings.h_min;
+                continue;
+            }
+            if (2.0 * diff < last_diff && h < h_initial) {
+                h_next = h_initial;
+            }
+            last_diff = diff;
+        }
+        let step_dir = step.delta;
         if (!has_color) {
             result.color = ray_color(cur_point, normalize(step_dir), length(step_dir));
             has_color = result.color.a > 0.0;
+            if (has_color) {
+                hit_distance = path_length + result.color.a;
+            }
+            path_length = path_length + length(step_dir);
         }
 
         cur_point = cur_point + step_dir;
+        cur_dir = step.dir;
         t = t + h;
         last_v = v;
-        if (2.0 * diff < last_diff && h < h_initial) {
-            h = h_initial;
+        h = h_next;
+        if (ray_absorbed) {
+            break;
         }
-        last_diff = diff;
     }
 
-    result.mapping_point = vec4<f32>(cur_point, 1.0);
+    result.mapping_point = vec4<f32>(cur_point, hit_distance);
     result.color.a = 1.0;
     if (!has_color) {
-        result.color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        if (ray_absorbed) {
+            result.color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        } else {
+            result.color = background_color(normalize(cur_dir));
+        }
     }
     return result;
 }
 
 fn sample_rays(start_point: vec3<f32>, start_dir: vec3<f32>, samples_index: i32, sample_color: vec3<f32>) {
-    let field_weight = settings.field_weight;
     var cur_point: vec3<f32> = start_point;
-    var cur_dir: vec3<f32> = start_dir;
-    var color: vec4<f32>;
+    var cur_dir: vec3<f32> = initial_direction(start_point, start_dir);
     var t: f32 = 0.0;
-    let h = 0.001;
-    let steps: i32 = 5000;
+    var h: f32 = settings.h_min;
+    let t_max = settings.t_max;
 
     var ssample: RaySample;
     ssample.color = vec4<f32>(sample_color, 0.5);
     let sample_steps = 100;
-    let sample_step_size = steps / sample_steps;
-
-    for (var i: i32 = 0; i < steps; i = i + 1) {
-        // Runge-Kutta method
-        let k1 = field_function(cur_point, cur_point, start_dir, cur_dir, t);
-        let k2 = field_function(cur_point, cur_point + 0.5 * h * k1, start_dir, k1, t + 0.5 * h);
-        let k3 = field_function(cur_point, cur_point + 0.5 * h * k2, start_dir, k2, t + 0.5 * h);
-        let k4 = field_function(cur_point, cur_point + h * k3, start_dir, k3, t + h);
-        let v = (k1 + 2.0 * k2 + 2.0 * k3 + k4) / 6.0;
-        cur_dir = (1.0 - field_weight) * cur_dir + field_weight * v;
-
-        let step_dir = cur_dir * h;
-        if (i % sample_step_size == 0) {
-            ssample.position = vec4<f32>(cur_point, 1.0);
-            let index = samples_index * sample_steps + i / sample_step_size;
-            ray_samples.data[index] = ssample;
+    let sample_interval = t_max / f32(sample_steps);
+
+    var steps: u32 = 0u;
+    var i: i32 = 0;
+    for (; i < sample_steps && steps < settings.max_steps; steps = steps + 1u) {
+        ray_absorbed = false;
+        let step = integration_step(cur_point, start_dir, cur_dir, t, h);
+        if (!step.accepted) {
+            h = step.h_next;
+            continue;
+        }
+        let step_dir = step.delta;
+
+        // Store the samples within this step, adaptive steps may cover several
+        for (; i < sample_steps && f32(i) * sample_interval <= t + h; i = i + 1) {
+            let s = (f32(i) * sample_interval - t) / h;
+            ssample.position = vec4<f32>(cur_point + s * step_dir, 1.0);
+            ray_samples.data[samples_index * sample_steps + i] = ssample;
         }
 
         cur_point = cur_point + step_dir;
+        cur_dir = step.dir;
         t = t + h;
+        h = step.h_next;
+        if (ray_absorbed) {
+            break;
+        }
+    }
+    // Absorbed rays stay where they ended
+    ssample.position = vec4<f32>(cur_point, 1.0);
+    for (; i < sample_steps; i = i + 1) {
+        ray_samples.data[samples_index * sample_steps + i] = ssample;
     }
 }
 
@@ -334,7 +839,7 @@ var<private> sample_colors: array<vec3<f32>, 8> = array<vec3<f32>, 8>(
     vec3<f32>(0.5, 0.0, 0.5),                           
 );
 
-const sample_outline_rays: bool = false;
+override sample_outline_rays: bool = false;
 
 @compute @workgroup_size(8, 8)
 fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
@@ -366,7 +871,10 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
     let dir = normalize(s);
 
     if (linear_mode) {
-        let color = ray_color(origin, dir, 100.0);
+        var color = ray_color(origin, dir, 100.0);
+        if (color.a == 0.0) {
+            color = background_color(dir);
+        }
         textureStore(ttarget, coords, color);
     } else {
         let result = nonlinear_ray_color(origin, dir);
@@ -379,14 +887,15 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
     if (gid.x < 8u && gid.y == 0u) {
         var pos: vec2<f32> = vec2<f32>(0.0, 0.0);
         if (sample_outline_rays) {
-            let min_exp = 0.8;
+            // Exponents that are highlighted by the overlay, see overlay.wgsl
+            let min_exp = (log(0.8) + 5.0) / settings.lyapunov_scaling;
             var found: bool = false;
             var sum: i32 = 0;
             if (gid.x == 0u) {
                 // Bottom left
                 for (var x: i32 = 0; x < i32(size.x); x = x + 1) {
                     for (var y: i32 = 0; y < i32(size.y); y = y + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp && (i32(size.x) - x) + y >= sum) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp && (i32(size.x) - x) + y >= sum) {
                             pos = vec2<f32>(f32(x), f32(y));
                             sum = (i32(size.x) - x) + y;
                         }
@@ -396,7 +905,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Bottom middle
                 for (var y: i32 = i32(size.x) - 1; y >= 0 && !found; y = y - 1) {
                     for (var x: i32 = 0; x < i32(size.x) && !found; x = x + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp) {
                             pos = vec2<f32>(f32(x), f32(y));
                             found = true;
                         }
@@ -406,7 +915,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Bottom right
                 for (var x: i32 = 0; x < i32(size.x); x = x + 1) {
                     for (var y: i32 = 0; y < i32(size.y); y = y + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp && x + y >= sum) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp && x + y >= sum) {
                             pos = vec2<f32>(f32(x), f32(y));
                             sum = x + y;
                         }
@@ -416,7 +925,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Middle right
                 for (var x: i32 = i32(size.x) - 1; x >= 0 && !found; x = x - 1) {
                     for (var y: i32 = 0; y < i32(size.y) && !found; y = y + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp) {
                             pos = vec2<f32>(f32(x), f32(y));
                             found = true;
                         }
@@ -426,7 +935,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Top right
                 for (var x: i32 = 0; x < i32(size.x); x = x + 1) {
                     for (var y: i32 = 0; y < i32(size.y); y = y + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp && x + (i32(size.y) - y) >= sum) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp && x + (i32(size.y) - y) >= sum) {
                             pos = vec2<f32>(f32(x), f32(y));
                             sum = x + (i32(size.y) - y);
                         }
@@ -436,7 +945,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Top middle
                 for (var y: i32 = 0; y < i32(size.y) && !found; y = y + 1) {
                     for (var x: i32 = 0; x < i32(size.x) && !found; x = x + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp) {
                             pos = vec2<f32>(f32(x), f32(y));
                             found = true;
                         }
@@ -446,7 +955,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Top left
                 for (var x: i32 = 0; x < i32(size.x); x = x + 1) {
                     for (var y: i32 = 0; y < i32(size.y); y = y + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp && (i32(size.x) - x) + (i32(size.y) - y) >= sum) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp && (i32(size.x) - x) + (i32(size.y) - y) >= sum) {
                             pos = vec2<f32>(f32(x), f32(y));
                             sum = (i32(size.x) - x) + (i32(size.y) - y);
                         }
@@ -456,7 +965,7 @@ fn main_view(@builtin(global_invocation_id) gid: vec3<u32>) {
                 // Middle left
                 for (var x: i32 = 0; x < i32(size.x) && !found; x = x + 1) {
                     for (var y: i32 = 0; y < i32(size.y) && !found; y = y + 1) {
-                        if (exponents.data[y * i32(size.y) + x] >= min_exp) {
+                        if (exponents.data[y * i32(size.x) + x] >= min_exp) {
                             pos = vec2<f32>(f32(x), f32(y));
                             found = true;
                         }
@@ -86,6 +86,14 @@ struct Settings {
     overlay_mode: i32,
     central_difference_delta: i32,
     lyapunov_scaling: f32,
+    integration_scheme: i32,
+    abs_tol: f32,
+    rel_tol: f32,
+    h_min: f32,
+    h_max: f32,
+    t_max: f32,
+    h_initial: f32,
+    max_steps: u32,
 };
 @group(0) @binding(3)
 var<uniform> settings: Settings;
@@ -124,8 +132,8 @@ fn overlay(coords: vec2<i32>, size: vec2<u32>) -> vec4<f32> {
 
     if (settings.overlay_mode == 1) {
         let exponent = lyapunov_exponent(coords);
+        exponents.data[i32(size.x) * coords.y + coords.x] = exponent;
         let scaled_exponent = exp(settings.lyapunov_scaling * exponent - 5.0);
-        exponents.data[i32(size.y) * coords.y + coords.x] = scaled_exponent;
         if (scaled_exponent >= 0.8) {
             let alpha = min(1.0, scaled_exponent) * 0.5;
             return alpha * vec4<f32>(1.0, 1.0, 1.0, 1.0) + (1.0 - alpha) * color;
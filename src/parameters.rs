@@ -0,0 +1,359 @@
+//! Named parameters of a field function, declared by annotation comments such as
+//!
+//! ```text
+//! // @param t_env: f32 = 15.0 [-50.0, 100.0]
+//! // @param center: vec3<f32> = vec3<f32>(-0.5, 0.5, -0.5)
+//! ```
+//!
+//! Every parameter is stored in one `vec4<f32>` of the `parameters` uniform of
+//! `main_view.wgsl` and declared as local constant at the start of the field function,
+//! so that changing its value does not require recompiling the shader.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+
+use crate::validation::{Diagnostic, FieldFunctionError, Snippet};
+
+/// Number of parameters the uniform has room for, must match `Parameters` in `main_view.wgsl`.
+pub const MAX_PARAMETERS: usize = 16;
+
+const ANNOTATION: &str = "@param";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParameterKind {
+    Scalar,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl ParameterKind {
+    fn from_type(ty: &str) -> Option<Self> {
+        match ty {
+            "f32" => Some(Self::Scalar),
+            "vec2<f32>" => Some(Self::Vec2),
+            "vec3<f32>" => Some(Self::Vec3),
+            "vec4<f32>" => Some(Self::Vec4),
+            _ => None,
+        }
+    }
+
+    pub fn components(&self) -> usize {
+        match self {
+            Self::Scalar => 1,
+            Self::Vec2 => 2,
+            Self::Vec3 => 3,
+            Self::Vec4 => 4,
+        }
+    }
+
+    fn swizzle(&self) -> &'static str {
+        match self {
+            Self::Scalar => ".x",
+            Self::Vec2 => ".xy",
+            Self::Vec3 => ".xyz",
+            Self::Vec4 => "",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterKind,
+    pub default: [f32; 4],
+    /// Range of the slider, parameters without range are edited with drag values
+    pub range: Option<(f32, f32)>,
+    pub value: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParametersUniform {
+    data: [[f32; 4]; MAX_PARAMETERS],
+}
+
+impl ParametersUniform {
+    pub fn new(parameters: &[Parameter]) -> Self {
+        let mut data = [[0.0; 4]; MAX_PARAMETERS];
+        for (slot, parameter) in data.iter_mut().zip(parameters) {
+            *slot = parameter.value;
+        }
+        Self { data }
+    }
+}
+
+/// Parses the parameter annotations of a field function body.
+/// Errors are reported as [`FieldFunctionError`] with the line of the annotation.
+pub fn parse(body: &str) -> Result<Vec<Parameter>> {
+    let mut parameters: Vec<Parameter> = Vec::new();
+    for (i, line) in body.lines().enumerate() {
+        let annotation = match line
+            .trim()
+            .strip_prefix("//")
+            .and_then(|comment| comment.trim_start().strip_prefix(ANNOTATION))
+        {
+            Some(annotation) => annotation,
+            None => continue,
+        };
+        let parameter = parse_annotation(annotation).map_err(|e| {
+            let message = format!("invalid parameter annotation: {:#}", e);
+            FieldFunctionError::from(Diagnostic::at_line(Snippet::FieldFunction, i, message))
+        })?;
+        if parameters.iter().any(|p| p.name == parameter.name) {
+            let message = format!("parameter `{}` is declared twice", parameter.name);
+            return Err(FieldFunctionError::from(Diagnostic::at_line(
+                Snippet::FieldFunction,
+                i,
+                message,
+            ))
+            .into());
+        }
+        parameters.push(parameter);
+    }
+    if parameters.len() > MAX_PARAMETERS {
+        let message = format!(
+            "too many parameters ({}), at most {} are supported",
+            parameters.len(),
+            MAX_PARAMETERS
+        );
+        return Err(FieldFunctionError::from(Diagnostic::general(message)).into());
+    }
+    Ok(parameters)
+}
+
+/// Parses `name: type = default [min, max]`, the range is optional.
+fn parse_annotation(annotation: &str) -> Result<Parameter> {
+    let (declaration, default) = annotation
+        .split_once('=')
+        .context("expected `name: type = default`")?;
+    let (name, ty) = declaration
+        .split_once(':')
+        .context("expected `name: type`")?;
+    let name = name.trim();
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        bail!("`{}` is not a valid identifier", name);
+    }
+    let ty = ty.trim();
+    let kind = ParameterKind::from_type(ty)
+        .with_context(|| format!("unsupported type `{}`, expected f32 or vecN<f32>", ty))?;
+
+    let (default, range) = match default.split_once('[') {
+        Some((default, range)) => {
+            let range = range
+                .trim()
+                .strip_suffix(']')
+                .context("expected `]` after the range")?;
+            let bounds = parse_numbers(range)?;
+            if bounds.len() != 2 || bounds[0] >= bounds[1] {
+                bail!("expected range `[min, max]` with min < max");
+            }
+            (default, Some((bounds[0], bounds[1])))
+        }
+        None => (default, None),
+    };
+    let default = default.trim();
+    let components = match kind {
+        ParameterKind::Scalar => parse_numbers(default)?,
+        _ => {
+            let arguments = default
+                .strip_prefix(ty)
+                .and_then(|rest| rest.trim().strip_prefix('('))
+                .and_then(|rest| rest.strip_suffix(')'))
+                .with_context(|| format!("expected default value `{}(...)`", ty))?;
+            parse_numbers(arguments)?
+        }
+    };
+    if components.len() != kind.components() {
+        bail!(
+            "expected {} components for type `{}`, found {}",
+            kind.components(),
+            ty,
+            components.len()
+        );
+    }
+    let mut value = [0.0; 4];
+    value[..components.len()].copy_from_slice(&components);
+
+    Ok(Parameter {
+        name: name.to_string(),
+        kind,
+        default: value,
+        range,
+        value,
+    })
+}
+
+fn parse_numbers(list: &str) -> Result<Vec<f32>> {
+    list.split(',')
+        .map(|n| {
+            n.trim()
+                .parse()
+                .with_context(|| format!("`{}` is not a number", n.trim()))
+        })
+        .collect()
+}
+
+/// Local constants that make the parameters available in the field function.
+/// They are emitted on a single line, so that line numbers of the body are preserved.
+pub fn declarations(parameters: &[Parameter]) -> String {
+    parameters
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            format!(
+                "let {} = parameters.data[{}]{};",
+                p.name,
+                i,
+                p.kind.swizzle()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Takes over the values of `previous` for parameters with the same declaration,
+/// so that editing the function body does not reset them, but switching to another
+/// function with a parameter of the same name does not keep a value that makes no sense there.
+pub fn keep_values(parameters: &mut [Parameter], previous: &[Parameter]) {
+    for parameter in parameters {
+        if let Some(p) = previous.iter().find(|p| {
+            p.name == parameter.name && p.kind == parameter.kind && p.default == parameter.default
+        }) {
+            parameter.value = p.value;
+        }
+    }
+}
+
+/// Values by name, as stored in sessions.
+pub fn values(parameters: &[Parameter]) -> BTreeMap<String, [f32; 4]> {
+    parameters
+        .iter()
+        .map(|p| (p.name.clone(), p.value))
+        .collect()
+}
+
+pub fn set_values(parameters: &mut [Parameter], values: &BTreeMap<String, [f32; 4]>) {
+    for parameter in parameters {
+        if let Some(value) = values.get(&parameter.name) {
+            parameter.value = *value;
+        }
+    }
+}
+
+/// Shows a slider or drag values per parameter, returns whether a value changed.
+pub fn parameters_ui(ui: &mut egui::Ui, parameters: &mut [Parameter]) -> bool {
+    let mut changed = false;
+    egui::Grid::new("field_function_parameters").show(ui, |ui| {
+        for parameter in parameters.iter_mut() {
+            ui.label(format!("{}:", parameter.name));
+            ui.horizontal(|ui| {
+                let components = &mut parameter.value[..parameter.kind.components()];
+                for component in components {
+                    changed |= match parameter.range {
+                        Some((min, max)) => ui.add(egui::Slider::new(component, min..=max)),
+                        None => ui.add(egui::DragValue::new(component).speed(0.01)),
+                    }
+                    .changed();
+                }
+                if parameter.value != parameter.default && ui.small_button("Reset").clicked() {
+                    parameter.value = parameter.default;
+                    changed = true;
+                }
+            });
+            ui.end_row();
+        }
+    });
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The diagnostic of a failed `parse`.
+    fn diagnostic(body: &str) -> Diagnostic {
+        let error = parse(body).unwrap_err();
+        let error = error
+            .downcast_ref::<FieldFunctionError>()
+            .expect("expected a field function error");
+        assert_eq!(error.diagnostics.len(), 1);
+        error.diagnostics[0].clone()
+    }
+
+    #[test]
+    fn parse_scalar() {
+        let parameters = parse("// @param t_env: f32 = 15.0\nreturn v;").unwrap();
+        assert_eq!(
+            parameters,
+            vec![Parameter {
+                name: "t_env".to_string(),
+                kind: ParameterKind::Scalar,
+                default: [15.0, 0.0, 0.0, 0.0],
+                range: None,
+                value: [15.0, 0.0, 0.0, 0.0],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_vec3() {
+        let parameters = parse("//@param center: vec3<f32> = vec3<f32>(-0.5, 0.5, -0.5)").unwrap();
+        assert_eq!(parameters.len(), 1);
+        assert_eq!(parameters[0].kind, ParameterKind::Vec3);
+        assert_eq!(parameters[0].default, [-0.5, 0.5, -0.5, 0.0]);
+        assert_eq!(parameters[0].value, parameters[0].default);
+
+        let message = diagnostic("// @param center: vec3<f32> = vec3<f32>(1.0, 2.0)").message;
+        assert!(message.contains("expected 3 components"), "{}", message);
+    }
+
+    #[test]
+    fn parse_range() {
+        let parameters = parse("// @param max_dist: f32 = 0.25 [0.01, 1.0]").unwrap();
+        assert_eq!(parameters[0].range, Some((0.01, 1.0)));
+        assert_eq!(parameters[0].default[0], 0.25);
+
+        for range in ["[1.0, 0.01]", "[0.01]", "[0.01, 1.0"] {
+            let body = format!("// @param max_dist: f32 = 0.25 {}", range);
+            assert!(parse(&body).is_err(), "{}", range);
+        }
+    }
+
+    #[test]
+    fn parse_duplicate_name() {
+        let body = "// @param a: f32 = 1.0\n// @param b: f32 = 2.0\n// @param a: vec2<f32> = vec2<f32>(0.0, 0.0)";
+        let diagnostic = diagnostic(body);
+        assert_eq!(diagnostic.line, Some(2));
+        assert!(diagnostic.message.contains("`a` is declared twice"));
+    }
+
+    #[test]
+    fn parse_too_many() {
+        let declare = |n: usize| -> String {
+            (0..n)
+                .map(|i| format!("// @param p{}: f32 = {}.0\n", i, i))
+                .collect()
+        };
+        assert_eq!(
+            parse(&declare(MAX_PARAMETERS)).unwrap().len(),
+            MAX_PARAMETERS
+        );
+        let diagnostic = diagnostic(&declare(MAX_PARAMETERS + 1));
+        assert_eq!(diagnostic.line, None);
+        assert!(diagnostic.message.contains("too many parameters"));
+    }
+
+    #[test]
+    fn parse_reports_line() {
+        let body = "let x = 1.0;\n\n    // @param speed: f32 = fast\nreturn v;";
+        let diagnostic = diagnostic(body);
+        assert_eq!(diagnostic.snippet, Snippet::FieldFunction);
+        assert_eq!(diagnostic.line, Some(2));
+        assert!(diagnostic.message.contains("`fast` is not a number"));
+    }
+}
@@ -5,7 +5,7 @@ use wgpu::util::DeviceExt;
 
 use crate::{
     application::INITIAL_SIDEBAR_WIDTH,
-    arcball::{ArcballCamera, CameraOperation},
+    arcball::{ArcballCamera, CameraOperation, CameraPose},
     ray_samples::{create_indices, vertex_desc},
     texture::{DepthTexture, Texture},
 };
@@ -26,6 +26,20 @@ struct Uniforms {
     view_projection: [[f32; 4]; 4],
 }
 
+impl Uniforms {
+    fn new(camera: &ArcballCamera<f32>, aspect: f32) -> Self {
+        let eye_pos = camera.eye_pos();
+        Self {
+            camera_pos: [eye_pos.x, eye_pos.y, eye_pos.z, 0.0],
+            view_projection: {
+                let view = camera.get_mat4();
+                let proj = cgmath::perspective(cgmath::Deg(45.0), aspect, 0.1, 100.0);
+                (OPENGL_TO_WGPU_MATRIX * proj * view).into()
+            },
+        }
+    }
+}
+
 pub struct ReferenceView {
     texture: Texture,
     texture_id: egui::TextureId,
@@ -34,6 +48,7 @@ pub struct ReferenceView {
     camera: ArcballCamera<f32>,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
+    mesh_bind_group_layout: wgpu::BindGroupLayout,
     mesh_bind_group: wgpu::BindGroup,
 
     sample_index_buffer: wgpu::Buffer,
@@ -74,17 +89,9 @@ impl ReferenceView {
 
         let mut camera =
             ArcballCamera::new(center, 1.0, [INITIAL_SIDEBAR_WIDTH, INITIAL_SIDEBAR_WIDTH]);
-        camera.zoom(-1.0, 1.0);
-        let eye_pos = camera.eye_pos();
+        camera.set_pose(CameraPose::initial(center));
 
-        let uniforms = Uniforms {
-            camera_pos: [eye_pos.x, eye_pos.y, eye_pos.z, 0.0],
-            view_projection: {
-                let view = camera.get_mat4();
-                let proj = cgmath::perspective(cgmath::Deg(45.0), 1.0, 0.1, 100.0);
-                (OPENGL_TO_WGPU_MATRIX * proj * view).into()
-            },
-        };
+        let uniforms = Uniforms::new(&camera, 1.0);
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("uniform_buffer"),
             contents: bytemuck::cast_slice(&[uniforms]),
@@ -235,6 +242,7 @@ impl ReferenceView {
             camera,
             uniform_buffer,
             uniform_bind_group,
+            mesh_bind_group_layout,
             mesh_bind_group,
             prev_pointer_pos: None,
 
@@ -246,25 +254,59 @@ impl ReferenceView {
         }
     }
 
+    pub fn update_model(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices_buffer_binding: wgpu::BindingResource,
+        faces_buffer_binding: wgpu::BindingResource,
+        center: Vector3<f32>,
+    ) {
+        self.mesh_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.mesh_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: vertices_buffer_binding,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: faces_buffer_binding,
+                },
+            ],
+            label: Some("mesh_bind_group"),
+        });
+
+        let (width, height) = self.texture.dimensions;
+        self.camera = ArcballCamera::new(center, 1.0, [width as f32, height as f32]);
+        self.camera.set_pose(CameraPose::initial(center));
+        self.update_camera(queue);
+    }
+
     fn update_camera(&mut self, queue: &wgpu::Queue) {
-        let eye_pos = self.camera.eye_pos();
-        let uniforms = Uniforms {
-            camera_pos: [eye_pos.x, eye_pos.y, eye_pos.z, 0.0],
-            view_projection: {
-                let view = self.camera.get_mat4();
-                let proj = cgmath::perspective(cgmath::Deg(45.0), 1.0, 0.1, 100.0);
-                (OPENGL_TO_WGPU_MATRIX * proj * view).into()
-            },
-        };
+        let uniforms = Uniforms::new(&self.camera, 1.0);
         queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
         self.needs_redraw = true;
     }
 
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.texture.dimensions
+    }
+
+    pub fn camera_pose(&self) -> CameraPose<f32> {
+        self.camera.pose()
+    }
+
+    pub fn set_camera_pose(&mut self, queue: &wgpu::Queue, pose: CameraPose<f32>) {
+        self.camera.set_pose(pose);
+        self.update_camera(queue);
+    }
+
     fn reset_camera(&mut self, queue: &wgpu::Queue) {
         let center = self.camera.center;
         let (width, height) = self.texture.dimensions;
         self.camera = ArcballCamera::new(center, 1.0, [width as f32, height as f32]);
-        self.camera.zoom(-1.0, 1.0);
+        self.camera.set_pose(CameraPose::initial(center));
         self.update_camera(queue);
     }
 
@@ -345,11 +387,63 @@ impl ReferenceView {
         encoder: &mut wgpu::CommandEncoder,
         indices: u32,
         vertex_buffer_slice: wgpu::BufferSlice,
+    ) {
+        self.render_to(
+            encoder,
+            (&self.texture.view, &self.depth_texture.view),
+            indices,
+            vertex_buffer_slice,
+        );
+    }
+
+    /// Renders the view once in the given size, independent of the size shown in the sidebar,
+    /// and reads back the resulting image as tightly packed RGBA rows.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+        indices: u32,
+        vertex_buffer_slice: wgpu::BufferSlice,
+    ) -> Result<image::RgbaImage, wgpu::BufferAsyncError> {
+        let texture = Texture::new(
+            device,
+            size,
+            Some("reference_image_texture"),
+            self.texture.format,
+            false,
+        );
+        let depth_texture = DepthTexture::new(device, size, Some("reference_image_depth_texture"));
+        let aspect = size.0 as f32 / size.1 as f32;
+        let uniforms = Uniforms::new(&self.camera, aspect);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("reference_image_encoder"),
+        });
+        self.render_to(
+            &mut encoder,
+            (&texture.view, &depth_texture.view),
+            indices,
+            vertex_buffer_slice,
+        );
+        queue.submit(Some(encoder.finish()));
+        self.update_camera(queue);
+        let data = texture.read(device, queue)?;
+        Ok(image::RgbaImage::from_raw(size.0, size.1, data).expect("texture size mismatch"))
+    }
+
+    fn render_to(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        (view, depth_view): (&wgpu::TextureView, &wgpu::TextureView),
+        indices: u32,
+        vertex_buffer_slice: wgpu::BufferSlice,
     ) {
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("rpass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.texture.view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -362,7 +456,7 @@ impl ReferenceView {
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
+                view: depth_view,
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
                     store: wgpu::StoreOp::Store,
@@ -0,0 +1,253 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use crate::application::{Background, OverlayMode};
+use crate::arcball::CameraPose;
+use crate::clock::Clock;
+use crate::cornell_box as cbox;
+use crate::functions::FieldMode;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::functions::{default_field_function, DEFAULT_FUNCTION};
+use crate::integrator::IntegratorSettings;
+use crate::main_view::Settings;
+use crate::timeline::Timeline;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::vertices::{get_center, normalize_vertices};
+use crate::volume::VectorFieldOptions;
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Version of the session format written by this build.
+/// Increment whenever a field is added, removed or changes its meaning.
+pub const SESSION_VERSION: u32 = 10;
+
+/// A mesh as it was loaded, i.e. before normalization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+/// The complete state of an exploration, stored as JSON document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    /// Identifier of the predefined function, see `PredefinedFunction::id`
+    pub predefined_function: String,
+    /// Helper code inserted before the field function, added in version 4
+    #[serde(default)]
+    pub prelude: String,
+    pub field_function: String,
+    /// What the field function computes, added in version 7
+    #[serde(default)]
+    pub field_mode: FieldMode,
+    pub field_weight: f32,
+    pub overlay_mode: OverlayMode,
+    /// Added in version 8
+    #[serde(default)]
+    pub background: Background,
+    pub central_difference_delta: i32,
+    pub lyapunov_scaling: f32,
+    pub mouse_pos: [f32; 2],
+    /// Added in version 2
+    #[serde(default)]
+    pub integrator: IntegratorSettings,
+    /// Animation time and loop range, added in version 9
+    #[serde(default)]
+    pub clock: Clock,
+    /// Keyframes of the animation, added in version 10
+    #[serde(default)]
+    pub timeline: Timeline,
+    /// Values of the field function parameters by name, added in version 3
+    #[serde(default)]
+    pub parameters: BTreeMap<String, [f32; 4]>,
+    /// File of the volumetric scalar field, added in version 5
+    #[serde(default)]
+    pub volume: Option<PathBuf>,
+    /// File of the vector field, added in version 6
+    #[serde(default)]
+    pub vector_field: Option<PathBuf>,
+    /// Added in version 6
+    #[serde(default)]
+    pub vector_field_options: VectorFieldOptions,
+    /// The loaded model, `None` for the Cornell box
+    pub model: Option<Model>,
+    pub main_camera: CameraPose<f32>,
+    pub reference_camera: CameraPose<f32>,
+}
+
+impl Session {
+    /// The state the application starts in: the default function on the Cornell box.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn initial() -> Self {
+        let camera = CameraPose::initial(model_center(None));
+        Self {
+            version: SESSION_VERSION,
+            predefined_function: DEFAULT_FUNCTION.to_string(),
+            prelude: String::new(),
+            field_function: default_field_function(),
+            field_mode: FieldMode::Velocity,
+            field_weight: 1.0,
+            overlay_mode: OverlayMode::Disabled,
+            background: Background::Black,
+            central_difference_delta: 1,
+            lyapunov_scaling: 50.0,
+            mouse_pos: [0.5, 0.5],
+            integrator: IntegratorSettings::default(),
+            clock: Clock::default(),
+            timeline: Timeline::default(),
+            parameters: BTreeMap::new(),
+            volume: None,
+            vector_field: None,
+            vector_field_options: VectorFieldOptions::default(),
+            model: None,
+            main_camera: camera,
+            reference_camera: camera,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("reading {} failed", path.display()))?;
+        let session: Self = serde_json::from_str(&data)
+            .with_context(|| format!("parsing {} failed", path.display()))?;
+        if session.version > SESSION_VERSION {
+            bail!(
+                "session version {} is newer than the supported version {}",
+                session.version,
+                SESSION_VERSION
+            );
+        }
+        Ok(session)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, data).with_context(|| format!("writing {} failed", path.display()))
+    }
+
+    pub fn settings(&self) -> Settings {
+        let mut settings = Settings {
+            field_weight: self.field_weight,
+            mouse_pos: self.mouse_pos,
+            overlay_mode: self.overlay_mode as i32,
+            central_difference_delta: self.central_difference_delta,
+            lyapunov_scaling: self.lyapunov_scaling,
+            background: self.background as i32,
+            time: self.clock.time,
+            ..Default::default()
+        };
+        settings.set_integrator(&self.integrator);
+        settings
+    }
+}
+
+/// Vertices and indices of `model` before normalization, the Cornell box for `None`.
+pub fn model_mesh(model: Option<&Model>) -> (Vec<f32>, Vec<u32>) {
+    match model {
+        Some(model) => (model.vertices.clone(), model.indices.clone()),
+        None => (cbox::inverted_vertices().to_vec(), cbox::INDICES.to_vec()),
+    }
+}
+
+/// Center of `model` in the scene, i.e. after normalization, which the cameras start at.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn model_center(model: Option<&Model>) -> cgmath::Vector3<f32> {
+    let (mut vertices, _) = model_mesh(model);
+    normalize_vertices(&mut vertices);
+    get_center(&vertices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "linon-session-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut session = Session::initial();
+        session.prelude = "fn helper() -> f32 { return 1.0; }".to_string();
+        session.field_mode = FieldMode::Acceleration;
+        session.field_weight = 0.25;
+        session
+            .parameters
+            .insert("n".to_string(), [1.5, 0.0, 0.0, 0.0]);
+        session.volume = Some(PathBuf::from("volume.vtk"));
+        session.model = Some(Model {
+            vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+        });
+        let path = temp_path("round-trip");
+        session.save(&path).unwrap();
+        let loaded = Session::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(loaded.unwrap()).unwrap(),
+            serde_json::to_value(&session).unwrap()
+        );
+    }
+
+    #[test]
+    fn load_rejects_newer_version() {
+        let mut session = Session::initial();
+        session.version = SESSION_VERSION + 1;
+        let path = temp_path("newer");
+        session.save(&path).unwrap();
+        let result = Session::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(
+            error.contains("newer than the supported version"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn load_version_1() {
+        let path = temp_path("version-1");
+        std::fs::write(
+            &path,
+            r#"{
+                "version": 1,
+                "predefined_function": "mirage-plane",
+                "field_function": "return vec3<f32>(0.0);",
+                "field_weight": 0.5,
+                "overlay_mode": "disabled",
+                "central_difference_delta": 1,
+                "lyapunov_scaling": 50.0,
+                "mouse_pos": [0.5, 0.5],
+                "model": null,
+                "main_camera": {"center": [0.0, 0.0, 0.0], "rotation": [1.0, 0.0, 0.0, 0.0], "distance": 2.0},
+                "reference_camera": {"center": [0.0, 0.0, 0.0], "rotation": [1.0, 0.0, 0.0, 0.0], "distance": 2.0}
+            }"#,
+        )
+        .unwrap();
+        let session = Session::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let session = session.unwrap();
+        assert_eq!(session.version, 1);
+        assert_eq!(session.field_weight, 0.5);
+        assert_eq!(session.prelude, "");
+        assert_eq!(session.field_mode, FieldMode::Velocity);
+        assert!(session.parameters.is_empty());
+        assert!(session.timeline.keyframes().is_empty());
+        assert_eq!(session.volume, None);
+        assert_eq!(session.main_camera.distance, 2.0);
+    }
+}
@@ -1,58 +1,493 @@
 // Based on https://github.com/emilk/egui/blob/0.15.0/egui_demo_lib/src/syntax_highlighting.rs
 // MIT License
-use egui::{text::LayoutJob, FontId, FontFamily};
+use egui::{text::LayoutJob, FontFamily, FontId};
+
+use crate::helpers::{Symbol, SymbolKind};
+use crate::validation::Diagnostic;
+
+/// Width of the gutter left of the code, where lines with errors are marked.
+const GUTTER_WIDTH: f32 = 14.0;
+
+/// Maximum number of entries in the completion popup.
+const MAX_COMPLETIONS: usize = 8;
 
 /// View some code with syntax highlighting and selection.
-pub fn code_view_ui<S: egui::TextBuffer>(ui: &mut egui::Ui, code: &mut S) -> egui::Response {
-    let language = "rs";
+/// Lines with diagnostics are marked in the gutter and the errors are underlined.
+/// The `symbols` are offered for completion and their signatures are shown inside calls.
+pub fn code_view_ui<S: egui::TextBuffer>(
+    ui: &mut egui::Ui,
+    code: &mut S,
+    diagnostics: &[Diagnostic],
+    symbols: &[Symbol],
+) -> egui::Response {
+    let language = "wgsl";
+    let error_color = ui.visuals().error_fg_color;
+
+    let id = ui.make_persistent_id("code_view");
+    let popup_id = id.with("completion");
+    // The selected completion while the popup is open. The keys are consumed
+    // before the text edit sees them, so that they do not move the cursor.
+    let mut selected: Option<usize> = ui.data(|d| d.get_temp(popup_id));
+    let mut accept = false;
+    if let (Some(index), true) = (selected.as_mut(), ui.memory(|m| m.has_focus(id))) {
+        ui.input_mut(|i| {
+            if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown) {
+                *index += 1;
+            }
+            if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp) {
+                *index = index.saturating_sub(1);
+            }
+            // Enter keeps inserting new lines, as short names such as `p` have longer candidates
+            accept = i.consume_key(egui::Modifiers::NONE, egui::Key::Tab);
+        });
+    }
 
     let mut layouter = |ui: &egui::Ui, string: &str, _wrap_width: f32| {
-        let layout_job = highlight(ui.ctx(), string, language);
+        let mut layout_job = highlight(ui.ctx(), string, language, symbols);
         // layout_job.wrap.max_width = wrap_width; // no wrapping
+        underline_diagnostics(&mut layout_job, diagnostics, error_color);
         ui.fonts(|f| f.layout_job(layout_job))
     };
 
-    egui::ScrollArea::vertical().show(ui, |ui| {
-        ui.add(
-            egui::TextEdit::multiline(code)
+    egui::ScrollArea::vertical()
+        .show(ui, |ui| {
+            let output = egui::TextEdit::multiline(code)
+                .id(id)
                 .font(FontId::new(14.0, FontFamily::Monospace))
                 .code_editor()
                 .desired_rows(10)
                 .lock_focus(true)
-                .layouter(&mut layouter),
-        )
-    }).inner
+                .margin(egui::Margin {
+                    left: GUTTER_WIDTH,
+                    right: 4.0,
+                    top: 2.0,
+                    bottom: 2.0,
+                })
+                .layouter(&mut layouter)
+                .show(ui);
+
+            // Without wrapping, every line of the code is one row of the galley
+            for (i, diagnostic) in diagnostics.iter().enumerate() {
+                let row = match diagnostic
+                    .line
+                    .and_then(|line| output.galley.rows.get(line))
+                {
+                    Some(row) => row,
+                    None => continue,
+                };
+                let center = egui::pos2(
+                    output.response.rect.left() + GUTTER_WIDTH / 2.0,
+                    output.galley_pos.y + row.rect.center().y,
+                );
+                ui.painter().circle_filled(center, 3.5, error_color);
+                let marker = egui::Rect::from_center_size(
+                    center,
+                    egui::vec2(GUTTER_WIDTH, row.rect.height()),
+                );
+                ui.interact(
+                    marker,
+                    ui.id().with(("diagnostic", i)),
+                    egui::Sense::hover(),
+                )
+                .on_hover_text(&diagnostic.message);
+            }
+
+            let cursor = match output.cursor_range {
+                Some(range)
+                    if output.response.has_focus()
+                        && range.primary.ccursor == range.secondary.ccursor =>
+                {
+                    range.primary
+                }
+                _ => {
+                    ui.data_mut(|d| d.remove::<usize>(popup_id));
+                    return output.response;
+                }
+            };
+            let chars: Vec<char> = code.as_str().chars().collect();
+            let index = cursor.ccursor.index.min(chars.len());
+            let cursor_rect = output
+                .galley
+                .pos_from_cursor(&cursor)
+                .translate(output.galley_pos.to_vec2());
+
+            if let Some((name, argument)) = active_call(&chars, index) {
+                if let Some(symbol) = symbols
+                    .iter()
+                    .find(|s| s.kind == SymbolKind::Function && s.name == name)
+                {
+                    signature_hint(
+                        ui,
+                        id.with("signature"),
+                        cursor_rect.left_top(),
+                        symbol,
+                        argument,
+                    );
+                }
+            }
+
+            let start = completion_start(&chars, index);
+            let prefix: String = chars[start..index].iter().collect();
+            let candidates: Vec<&Symbol> = symbols
+                .iter()
+                .filter(|s| !prefix.is_empty() && s.name.starts_with(&prefix) && s.name != prefix)
+                .take(MAX_COMPLETIONS)
+                .collect();
+            if candidates.is_empty() {
+                ui.data_mut(|d| d.remove::<usize>(popup_id));
+                return output.response;
+            }
+            let mut index_selected = selected.unwrap_or(0).min(candidates.len() - 1);
+            if let Some(clicked) = completion_popup(
+                ui,
+                popup_id,
+                cursor_rect.left_bottom(),
+                &candidates,
+                index_selected,
+            ) {
+                index_selected = clicked;
+                accept = true;
+            }
+            if accept {
+                let end = complete(code, start..index, candidates[index_selected]);
+                let mut state = output.state.clone();
+                state
+                    .cursor
+                    .set_char_range(Some(egui::text::CCursorRange::one(
+                        egui::text::CCursor::new(end),
+                    )));
+                state.store(ui.ctx(), id);
+                ui.memory_mut(|m| m.request_focus(id));
+                ui.data_mut(|d| d.remove::<usize>(popup_id));
+            } else {
+                ui.data_mut(|d| d.insert_temp(popup_id, index_selected));
+            }
+
+            output.response
+        })
+        .inner
+}
+
+/// Start of the identifier that ends at `cursor`, which is the cursor itself in comments.
+fn completion_start(chars: &[char], cursor: usize) -> usize {
+    let line_start = chars[..cursor]
+        .iter()
+        .rposition(|&c| c == '\n')
+        .map_or(0, |i| i + 1);
+    let line: String = chars[line_start..cursor].iter().collect();
+    if line.contains("//") {
+        return cursor;
+    }
+    let is_identifier = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+    let start = cursor
+        - chars[..cursor]
+            .iter()
+            .rev()
+            .take_while(|c| is_identifier(c))
+            .count();
+    // Numbers such as `1e3` are not completed
+    if chars.get(start).is_none_or(|c| c.is_ascii_digit()) {
+        return cursor;
+    }
+    start
+}
+
+/// Name of the function whose argument list contains `cursor` and the index of the argument.
+fn active_call(chars: &[char], cursor: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut argument = 0;
+    for i in (0..cursor).rev() {
+        match chars[i] {
+            ')' => depth += 1,
+            '(' if depth > 0 => depth -= 1,
+            '(' => {
+                let name: String = chars[..i]
+                    .iter()
+                    .rev()
+                    .skip_while(|c| c.is_whitespace())
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .rev()
+                    .collect();
+                return Some((name, argument));
+            }
+            ',' if depth == 0 => argument += 1,
+            ';' | '{' | '}' => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Replaces the characters in `range` with `symbol` and returns the new cursor position.
+fn complete<S: egui::TextBuffer>(
+    code: &mut S,
+    range: std::ops::Range<usize>,
+    symbol: &Symbol,
+) -> usize {
+    let text = match symbol.kind {
+        SymbolKind::Function => format!("{}(", symbol.name),
+        _ => symbol.name.clone(),
+    };
+    code.delete_char_range(range.clone());
+    code.insert_text(&text, range.start);
+    range.start + text.chars().count()
+}
+
+/// Shows the candidates below the cursor and returns the clicked one.
+fn completion_popup(
+    ui: &egui::Ui,
+    id: egui::Id,
+    pos: egui::Pos2,
+    candidates: &[&Symbol],
+    selected: usize,
+) -> Option<usize> {
+    let mut clicked = None;
+    egui::Area::new(id)
+        .order(egui::Order::Foreground)
+        .fixed_pos(pos)
+        .show(ui.ctx(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                for (i, symbol) in candidates.iter().enumerate() {
+                    let text = egui::RichText::new(symbol.signature()).monospace();
+                    if ui.selectable_label(i == selected, text).clicked() {
+                        clicked = Some(i);
+                    }
+                }
+                let doc = &candidates[selected].doc;
+                if !doc.is_empty() {
+                    ui.separator();
+                    ui.label(doc);
+                }
+            });
+        });
+    clicked
 }
 
-/// Memoized Code highlighting
-pub fn highlight(ctx: &egui::Context, code: &str, language: &str) -> LayoutJob {
-    impl egui::util::cache::ComputerMut<(&str, &str), LayoutJob> for Highlighter {
-        fn compute(&mut self, (code, lang): (&str, &str)) -> LayoutJob {
+/// Shows the signature of `symbol` above the cursor with the current argument highlighted.
+fn signature_hint(ui: &egui::Ui, id: egui::Id, pos: egui::Pos2, symbol: &Symbol, argument: usize) {
+    let font_id = FontId::new(14.0, FontFamily::Monospace);
+    let color = ui.visuals().text_color();
+    let highlight = ui.visuals().strong_text_color();
+    let format = |color| egui::text::TextFormat {
+        font_id: font_id.clone(),
+        color,
+        ..Default::default()
+    };
+    let mut job = LayoutJob::default();
+    job.append(&format!("fn {}(", symbol.name), 0.0, format(color));
+    for (i, a) in symbol.arguments.iter().enumerate() {
+        if i > 0 {
+            job.append(", ", 0.0, format(color));
+        }
+        let mut text_format = format(if i == argument { highlight } else { color });
+        if i == argument {
+            text_format.underline = egui::Stroke::new(1.0, highlight);
+        }
+        job.append(a, 0.0, text_format);
+    }
+    job.append(&format!(") -> {}", symbol.ty), 0.0, format(color));
+
+    egui::Area::new(id)
+        .order(egui::Order::Foreground)
+        .pivot(egui::Align2::LEFT_BOTTOM)
+        .fixed_pos(pos)
+        .interactable(false)
+        .show(ui.ctx(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(job);
+                if !symbol.doc.is_empty() {
+                    ui.label(&symbol.doc);
+                }
+            });
+        });
+}
+
+/// Splits the sections of `job` so that the ranges of the diagnostics can be underlined.
+fn underline_diagnostics(job: &mut LayoutJob, diagnostics: &[Diagnostic], color: egui::Color32) {
+    let mut ranges = Vec::new();
+    let mut line_start = 0;
+    for (i, line) in job.text.split('\n').enumerate() {
+        for diagnostic in diagnostics.iter().filter(|d| d.line == Some(i)) {
+            // Errors without columns (or outdated ones) underline the whole line
+            let columns = if diagnostic.columns.is_empty() || diagnostic.columns.end > line.len() {
+                0..line.len()
+            } else {
+                diagnostic.columns.clone()
+            };
+            ranges.push(line_start + columns.start..line_start + columns.end);
+        }
+        line_start += line.len() + 1;
+    }
+    if ranges.is_empty() {
+        return;
+    }
+
+    let underline = egui::Stroke::new(1.5, color);
+    let mut sections = Vec::with_capacity(job.sections.len());
+    for section in job.sections.drain(..) {
+        let mut cuts = vec![section.byte_range.start, section.byte_range.end];
+        for range in &ranges {
+            cuts.extend(
+                [range.start, range.end]
+                    .iter()
+                    .copied()
+                    .filter(|&cut| section.byte_range.contains(&cut)),
+            );
+        }
+        cuts.sort_unstable();
+        cuts.dedup();
+        for piece in cuts.windows(2) {
+            let mut format = section.format.clone();
+            if ranges
+                .iter()
+                .any(|range| range.start <= piece[0] && piece[1] <= range.end)
+            {
+                format.underline = underline;
+            }
+            sections.push(egui::text::LayoutSection {
+                leading_space: if piece[0] == section.byte_range.start {
+                    section.leading_space
+                } else {
+                    0.0
+                },
+                byte_range: piece[0]..piece[1],
+                format,
+            });
+        }
+    }
+    job.sections = sections;
+}
+
+/// Memoized Code highlighting, where the `symbols` are highlighted as arguments and helpers.
+pub fn highlight(ctx: &egui::Context, code: &str, language: &str, symbols: &[Symbol]) -> LayoutJob {
+    impl egui::util::cache::ComputerMut<(&str, &str, &HelperNames), LayoutJob> for Highlighter {
+        fn compute(&mut self, (code, lang, names): (&str, &str, &HelperNames)) -> LayoutJob {
+            if *names != self.names {
+                self.names = names.clone();
+                self.wgsl = wgsl_syntax_set(names);
+            }
             self.highlight(code, lang)
         }
     }
 
     type HighlightCache = egui::util::cache::FrameCache<LayoutJob, Highlighter>;
 
+    let names = HelperNames::new(symbols);
     ctx.memory_mut(|mem| {
         mem.caches
             .cache::<HighlightCache>()
-            .get((code, language))
+            .get((code, language, &names))
     })
 }
 
 // ----------------------------------------------------------------------------
 
+const THEME: &str = "base16-mocha.dark";
+
+/// Grammar for WGSL, which is not among the syntect defaults.
+const WGSL_SYNTAX: &str = include_str!("wgsl.sublime-syntax");
+
+/// Colors for the arguments of the field function and the helpers of `main_view.wgsl`.
+const FIELD_FUNCTION_COLORS: [(&str, syntect::highlighting::Color); 2] = [
+    (
+        "variable.parameter.field-function",
+        syntect::highlighting::Color {
+            r: 0xf4,
+            g: 0xbc,
+            b: 0x87,
+            a: 0xff,
+        },
+    ),
+    (
+        "support.function.helper, support.constant.helper",
+        syntect::highlighting::Color {
+            r: 0x8a,
+            g: 0xb3,
+            b: 0xb5,
+            a: 0xff,
+        },
+    ),
+];
+
+/// The names of the symbols as alternatives of a regular expression, one for each of the
+/// variables of the WGSL grammar that are filled in from the shader.
+#[derive(Debug, Clone, PartialEq, Hash)]
+struct HelperNames {
+    arguments: String,
+    helper_constants: String,
+    helper_functions: String,
+}
+
+impl HelperNames {
+    fn new(symbols: &[Symbol]) -> Self {
+        let names = |kind| {
+            let names: Vec<&str> = symbols
+                .iter()
+                .filter(|s| s.kind == kind)
+                .map(|s| s.name.as_str())
+                .collect();
+            if names.is_empty() {
+                // Never matches
+                return "(?!)".to_string();
+            }
+            names.join("|")
+        };
+        Self {
+            arguments: names(SymbolKind::Argument),
+            helper_constants: names(SymbolKind::Constant),
+            helper_functions: names(SymbolKind::Function),
+        }
+    }
+}
+
+/// Syntax set with only the WGSL grammar, whose variables are set to `names`.
+fn wgsl_syntax_set(names: &HelperNames) -> syntect::parsing::SyntaxSet {
+    // The names are identifiers, which need no quoting in the regular expressions or YAML
+    let variables = format!(
+        "variables:\n  arguments: '{}'\n  helper_constants: '{}'\n  helper_functions: '{}'\n",
+        names.arguments, names.helper_constants, names.helper_functions
+    );
+    let src = WGSL_SYNTAX.replacen("variables:\n", &variables, 1);
+    let wgsl = syntect::parsing::SyntaxDefinition::load_from_str(&src, true, None)
+        .expect("invalid WGSL syntax definition");
+    let mut builder = syntect::parsing::SyntaxSetBuilder::new();
+    builder.add(wgsl);
+    builder.build()
+}
+
 struct Highlighter {
     ps: syntect::parsing::SyntaxSet,
+    /// The WGSL grammar for the symbols `names`
+    wgsl: syntect::parsing::SyntaxSet,
+    names: HelperNames,
     ts: syntect::highlighting::ThemeSet,
 }
 
 impl Default for Highlighter {
     fn default() -> Self {
+        let names = HelperNames::new(&[]);
+        let mut ts = syntect::highlighting::ThemeSet::load_defaults();
+        if let Some(theme) = ts.themes.get_mut(THEME) {
+            // The base16 themes do not distinguish these scopes from other variables and functions
+            for (scope, color) in FIELD_FUNCTION_COLORS.iter().copied() {
+                theme.scopes.push(syntect::highlighting::ThemeItem {
+                    scope: scope.parse().expect("invalid scope selector"),
+                    style: syntect::highlighting::StyleModifier {
+                        foreground: Some(color),
+                        background: None,
+                        font_style: None,
+                    },
+                });
+            }
+        }
+
         Self {
             ps: syntect::parsing::SyntaxSet::load_defaults_newlines(),
-            ts: syntect::highlighting::ThemeSet::load_defaults(),
+            wgsl: wgsl_syntax_set(&names),
+            names,
+            ts,
         }
     }
 }
@@ -75,13 +510,12 @@ impl Highlighter {
         use syntect::highlighting::FontStyle;
         use syntect::util::LinesWithEndings;
 
-        let syntax = self
-            .ps
-            .find_syntax_by_name(language)
-            .or_else(|| self.ps.find_syntax_by_extension(language))?;
+        let (ps, syntax) = match find_syntax(&self.wgsl, language) {
+            Some(syntax) => (&self.wgsl, syntax),
+            None => (&self.ps, find_syntax(&self.ps, language)?),
+        };
 
-        let theme = "base16-mocha.dark";
-        let mut h = HighlightLines::new(syntax, &self.ts.themes[theme]);
+        let mut h = HighlightLines::new(syntax, &self.ts.themes[THEME]);
 
         use egui::text::{LayoutSection, TextFormat};
 
@@ -91,7 +525,7 @@ impl Highlighter {
         };
 
         for line in LinesWithEndings::from(text) {
-            for (style, range) in h.highlight_line(line, &self.ps).ok()? {
+            for (style, range) in h.highlight_line(line, ps).ok()? {
                 let fg = style.foreground;
                 let text_color = egui::Color32::from_rgb(fg.r, fg.g, fg.b);
                 let italics = style.font_style.contains(FontStyle::ITALIC);
@@ -119,6 +553,14 @@ impl Highlighter {
     }
 }
 
+fn find_syntax<'a>(
+    ps: &'a syntect::parsing::SyntaxSet,
+    language: &str,
+) -> Option<&'a syntect::parsing::SyntaxReference> {
+    ps.find_syntax_by_name(language)
+        .or_else(|| ps.find_syntax_by_extension(language))
+}
+
 fn as_byte_range(whole: &str, range: &str) -> std::ops::Range<usize> {
     let whole_start = whole.as_ptr() as usize;
     let range_start = range.as_ptr() as usize;
@@ -127,3 +569,72 @@ fn as_byte_range(whole: &str, range: &str) -> std::ops::Range<usize> {
     let offset = range_start - whole_start;
     offset..(offset + range.len())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers;
+    use syntect::parsing::{ParseState, ScopeStack, ScopeStackOp};
+
+    /// Innermost scope of every token of `line`.
+    fn scopes(ps: &syntect::parsing::SyntaxSet, line: &str) -> Vec<(String, String)> {
+        let syntax = find_syntax(ps, "wgsl").unwrap();
+        let mut state = ParseState::new(syntax);
+        let ops = state.parse_line(line, ps).unwrap();
+        let mut stack = ScopeStack::new();
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        for (end, op) in ops.into_iter().chain([(line.len(), ScopeStackOp::Noop)]) {
+            if end > start {
+                let scope = stack.as_slice().last().map(|s| s.build_string());
+                tokens.push((line[start..end].to_string(), scope.unwrap_or_default()));
+                start = end;
+            }
+            stack.apply(&op).unwrap();
+        }
+        tokens
+    }
+
+    fn scope_of(tokens: &[(String, String)], token: &str) -> String {
+        tokens
+            .iter()
+            .find(|(text, _)| text == token)
+            .map(|(_, scope)| scope.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn helpers_from_shader_and_prelude() {
+        let src = include_str!("main_view.wgsl").replace(
+            "fn field_function(",
+            "fn my_helper(x: f32) -> f32 { return x; }\nfn field_function(",
+        );
+        let symbols = helpers::parse(&src, "field_function").unwrap();
+        let ps = wgsl_syntax_set(&HelperNames::new(&symbols));
+        let tokens = scopes(&ps, "return rotateX(p, PI) * my_helper(t) + other(v0);\n");
+        assert_eq!(scope_of(&tokens, "rotateX"), "support.function.helper.wgsl");
+        assert_eq!(
+            scope_of(&tokens, "my_helper"),
+            "support.function.helper.wgsl"
+        );
+        assert_eq!(scope_of(&tokens, "PI"), "support.constant.helper.wgsl");
+        assert_eq!(scope_of(&tokens, "other"), "variable.function.wgsl");
+        for argument in ["p", "t", "v0"] {
+            assert_eq!(
+                scope_of(&tokens, argument),
+                "variable.parameter.field-function.wgsl"
+            );
+        }
+    }
+
+    #[test]
+    fn without_symbols() {
+        let ps = wgsl_syntax_set(&HelperNames::new(&[]));
+        let tokens = scopes(&ps, "return rotateX(p, PI);\n");
+        assert_eq!(scope_of(&tokens, "rotateX"), "variable.function.wgsl");
+        assert_ne!(
+            scope_of(&tokens, "p"),
+            "variable.parameter.field-function.wgsl"
+        );
+    }
+}
@@ -28,6 +28,7 @@ impl Texture {
             format,
             view_formats: &[format],
             usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
                 | if storage {
                     wgpu::TextureUsages::STORAGE_BINDING
                 } else {
@@ -43,6 +44,70 @@ impl Texture {
             dimensions,
         }
     }
+
+    /// Copies the texture contents to the CPU, blocking until the copy has finished.
+    /// The returned rows are tightly packed, i.e. without the row padding required by wgpu.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+        let (width, height) = self.dimensions;
+        let bytes_per_pixel = self
+            .format
+            .block_copy_size(None)
+            .expect("texture format has no block size");
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("texture_read_buffer"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("texture_read_encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).expect("sending map result failed");
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv().expect("receiving map result failed")?;
+
+        let mut data = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+        {
+            data.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        buffer.unmap();
+        Ok(data)
+    }
 }
 
 pub struct DepthTexture {
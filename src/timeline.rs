@@ -0,0 +1,344 @@
+//! Keyframes of the camera, the field weight and the field function parameters over the animation
+//! time, which are interpolated to animate an exploration and to render it as frame sequence.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::arcball::CameraPose;
+use cgmath::{InnerSpace, Quaternion};
+use serde::{Deserialize, Serialize};
+
+/// State of the exploration at a point of the animation time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f32,
+    /// Pose of the main view camera
+    pub camera: CameraPose<f32>,
+    pub field_weight: f32,
+    /// Values of the field function parameters by name
+    #[serde(default)]
+    pub parameters: BTreeMap<String, [f32; 4]>,
+}
+
+/// How the values between two keyframes are computed, the camera rotation always uses slerp.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[serde(rename = "linear")]
+    Linear,
+    /// Cubic Hermite spline through the keyframes with Catmull-Rom tangents
+    #[default]
+    #[serde(rename = "spline")]
+    Spline,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 2] = [Self::Linear, Self::Spline];
+}
+
+impl Display for Interpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::Linear => "Linear",
+            Self::Spline => "Spline",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeline {
+    /// Keyframes sorted by time, no two at the same time
+    keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+}
+
+impl Timeline {
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Adds a keyframe, replacing a keyframe at the same time.
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .iter()
+            .position(|k| k.time >= keyframe.time - 1e-4)
+        {
+            Some(index) if (self.keyframes[index].time - keyframe.time).abs() < 1e-4 => {
+                self.keyframes[index] = keyframe;
+            }
+            Some(index) => self.keyframes.insert(index, keyframe),
+            None => self.keyframes.push(keyframe),
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.keyframes.remove(index);
+    }
+
+    /// Time of the first and the last keyframe, `None` if there are no keyframes.
+    pub fn range(&self) -> Option<(f32, f32)> {
+        Some((self.keyframes.first()?.time, self.keyframes.last()?.time))
+    }
+
+    /// The interpolated state at `time`, which is held constant before the first and after the
+    /// last keyframe. Parameters are included if any keyframe of the segment has a value for them.
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let (start, end) = self.range()?;
+        if time <= start || self.keyframes.len() == 1 {
+            return Some(Keyframe {
+                time,
+                ..self.keyframes[0].clone()
+            });
+        }
+        if time >= end {
+            return Some(Keyframe {
+                time,
+                ..self.keyframes[self.keyframes.len() - 1].clone()
+            });
+        }
+        let index = self.keyframes.iter().rposition(|k| k.time <= time)?;
+        let segment = Segment {
+            keyframes: &self.keyframes,
+            index,
+            s: (time - self.keyframes[index].time)
+                / (self.keyframes[index + 1].time - self.keyframes[index].time),
+            interpolation: self.interpolation,
+        };
+        let (k1, k2) = (&self.keyframes[index], &self.keyframes[index + 1]);
+
+        let mut center = [0.0; 3];
+        for (i, component) in center.iter_mut().enumerate() {
+            *component = segment.interpolate(|k| Some(k.camera.center[i]))?;
+        }
+        let camera = CameraPose {
+            center,
+            rotation: slerp(k1.camera.rotation, k2.camera.rotation, segment.s),
+            distance: segment.interpolate(|k| Some(k.camera.distance))?,
+        };
+        let names = k1.parameters.keys().chain(k2.parameters.keys());
+        let mut parameters = BTreeMap::new();
+        for name in names {
+            if parameters.contains_key(name) {
+                continue;
+            }
+            let mut value = [0.0; 4];
+            for (i, component) in value.iter_mut().enumerate() {
+                *component = segment
+                    .interpolate(|k| k.parameters.get(name).map(|v| v[i]))
+                    .unwrap_or_default();
+            }
+            parameters.insert(name.clone(), value);
+        }
+        Some(Keyframe {
+            time,
+            camera,
+            field_weight: segment.interpolate(|k| Some(k.field_weight))?,
+            parameters,
+        })
+    }
+}
+
+/// The part of a timeline between the keyframes `index` and `index + 1`, at the fraction `s`.
+struct Segment<'a> {
+    keyframes: &'a [Keyframe],
+    index: usize,
+    s: f32,
+    interpolation: Interpolation,
+}
+
+impl Segment<'_> {
+    /// Interpolates the value given by `value` for each keyframe. Keyframes without a value take
+    /// that of their neighbor in the segment, `None` if neither end of the segment has a value.
+    fn interpolate(&self, value: impl Fn(&Keyframe) -> Option<f32>) -> Option<f32> {
+        let i = self.index;
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        let p1 = value(k1).or_else(|| value(k2))?;
+        let p2 = value(k2).unwrap_or(p1);
+        let s = self.s;
+        if self.interpolation == Interpolation::Linear {
+            return Some(p1 + s * (p2 - p1));
+        }
+        // The outer points fall back to the ends of the segment, which gives one-sided tangents
+        let (t0, p0) = match i.checked_sub(1).map(|i| &self.keyframes[i]) {
+            Some(k0) => (k0.time, value(k0).unwrap_or(p1)),
+            None => (k1.time, p1),
+        };
+        let (t3, p3) = match self.keyframes.get(i + 2) {
+            Some(k3) => (k3.time, value(k3).unwrap_or(p2)),
+            None => (k2.time, p2),
+        };
+        let (t1, t2) = (k1.time, k2.time);
+        let m1 = (p2 - p0) / (t2 - t0) * (t2 - t1);
+        let m2 = (p3 - p1) / (t3 - t1) * (t2 - t1);
+        let s2 = s * s;
+        let s3 = s2 * s;
+        Some(
+            (2.0 * s3 - 3.0 * s2 + 1.0) * p1
+                + (s3 - 2.0 * s2 + s) * m1
+                + (-2.0 * s3 + 3.0 * s2) * p2
+                + (s3 - s2) * m2,
+        )
+    }
+}
+
+/// Spherical linear interpolation of rotations given as `[w, x, y, z]` along the shorter arc.
+fn slerp(a: [f32; 4], b: [f32; 4], s: f32) -> [f32; 4] {
+    let a = Quaternion::new(a[0], a[1], a[2], a[3]).normalize();
+    let mut b = Quaternion::new(b[0], b[1], b[2], b[3]).normalize();
+    if a.dot(b) < 0.0 {
+        b = -b;
+    }
+    let q = a.slerp(b, s).normalize();
+    [q.s, q.v.x, q.v.y, q.v.z]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: [f32; 4] = [1.0, 0.0, 0.0, 0.0];
+
+    fn keyframe(time: f32, field_weight: f32) -> Keyframe {
+        Keyframe {
+            time,
+            camera: CameraPose {
+                center: [0.0; 3],
+                rotation: IDENTITY,
+                distance: 1.0 + field_weight,
+            },
+            field_weight,
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    fn with_keyframes(interpolation: Interpolation, keyframes: &[(f32, f32)]) -> Timeline {
+        let mut timeline = Timeline {
+            interpolation,
+            ..Default::default()
+        };
+        for &(time, field_weight) in keyframes {
+            timeline.insert(keyframe(time, field_weight));
+        }
+        timeline
+    }
+
+    fn weight(timeline: &Timeline, time: f32) -> f32 {
+        timeline.sample(time).unwrap().field_weight
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn linear() {
+        let timeline = with_keyframes(Interpolation::Linear, &[(0.0, 0.0), (1.0, 1.0), (3.0, 0.0)]);
+        for &(time, expected) in &[(0.0, 0.0), (1.0, 1.0), (3.0, 0.0)] {
+            assert_near(weight(&timeline, time), expected);
+        }
+        assert_near(weight(&timeline, 0.25), 0.25);
+        assert_near(weight(&timeline, 2.0), 0.5);
+        let sample = timeline.sample(2.5).unwrap();
+        assert_eq!(sample.time, 2.5);
+        assert_near(sample.camera.distance, 1.25);
+    }
+
+    #[test]
+    fn spline() {
+        let timeline = with_keyframes(Interpolation::Spline, &[(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)]);
+        for &(time, expected) in &[(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)] {
+            assert_near(weight(&timeline, time), expected);
+        }
+        // Tangent 1 from the one-sided difference at the start, 0 at the peak
+        assert_near(weight(&timeline, 0.5), 0.625);
+        assert_near(weight(&timeline, 1.5), 0.625);
+
+        // Evenly spaced keyframes on a line are interpolated exactly
+        let timeline = with_keyframes(Interpolation::Spline, &[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)]);
+        assert_near(weight(&timeline, 0.3), 0.3);
+        assert_near(weight(&timeline, 1.7), 1.7);
+    }
+
+    #[test]
+    fn hold_outside_of_keyframes() {
+        for interpolation in Interpolation::ALL.iter().copied() {
+            let timeline = with_keyframes(interpolation, &[(1.0, 0.2), (2.0, 0.8)]);
+            let before = timeline.sample(0.0).unwrap();
+            assert_eq!(
+                before,
+                Keyframe {
+                    time: 0.0,
+                    ..keyframe(1.0, 0.2)
+                }
+            );
+            let after = timeline.sample(5.0).unwrap();
+            assert_eq!(
+                after,
+                Keyframe {
+                    time: 5.0,
+                    ..keyframe(2.0, 0.8)
+                }
+            );
+        }
+
+        let single = with_keyframes(Interpolation::Spline, &[(1.0, 0.5)]);
+        assert_eq!(weight(&single, 0.0), 0.5);
+        assert_eq!(weight(&single, 3.0), 0.5);
+        assert_eq!(Timeline::default().sample(0.0), None);
+    }
+
+    #[test]
+    fn insert_replaces_at_same_time() {
+        let mut timeline =
+            with_keyframes(Interpolation::Linear, &[(2.0, 0.0), (0.0, 0.0), (1.0, 0.0)]);
+        let times: Vec<_> = timeline.keyframes().iter().map(|k| k.time).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0]);
+
+        timeline.insert(keyframe(1.00001, 0.7));
+        assert_eq!(timeline.keyframes().len(), 3);
+        assert_eq!(timeline.keyframes()[1].field_weight, 0.7);
+        assert_eq!(timeline.range(), Some((0.0, 2.0)));
+    }
+
+    #[test]
+    fn parameters_missing_in_one_keyframe() {
+        let mut timeline = with_keyframes(Interpolation::Linear, &[(0.0, 0.0)]);
+        let mut k2 = keyframe(1.0, 0.0);
+        k2.parameters
+            .insert("speed".to_string(), [2.0, 0.0, 0.0, 0.0]);
+        timeline.insert(k2);
+        let parameters = timeline.sample(0.5).unwrap().parameters;
+        assert_eq!(parameters["speed"], [2.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn slerp_takes_shorter_arc() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        // A quarter turn around z, with the sign of the quaternion flipped
+        let flipped = [-half, 0.0, 0.0, -half];
+        let q = slerp(IDENTITY, flipped, 0.5);
+        let angle = std::f32::consts::PI / 8.0;
+        let expected = [angle.cos(), 0.0, 0.0, angle.sin()];
+        for i in 0..4 {
+            assert_near(q[i], expected[i]);
+        }
+
+        // Antipodal quaternions are the same rotation
+        let q = slerp(IDENTITY, [-1.0, 0.0, 0.0, 0.0], 0.5);
+        for i in 0..4 {
+            assert_near(q[i], IDENTITY[i]);
+        }
+
+        let mut timeline = with_keyframes(Interpolation::Spline, &[(0.0, 0.0)]);
+        let mut k2 = keyframe(1.0, 0.0);
+        k2.camera.rotation = flipped;
+        timeline.insert(k2);
+        let rotation = timeline.sample(0.5).unwrap().camera.rotation;
+        for i in 0..4 {
+            assert_near(rotation[i], expected[i]);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Validation of the shader with naga before the pipeline is created,
+//! with the diagnostics mapped back to the lines of the prelude and the field function body.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// The user code that is spliced into the shader, each shown in its own editor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Snippet {
+    /// Helper functions, constants and structs declared before the field function
+    Prelude,
+    /// The body of the field function
+    FieldFunction,
+}
+
+/// The snippets together with their byte ranges in the shader.
+pub type Snippets = Vec<(Snippet, Range<usize>)>;
+
+/// An error in the user code, as shown in the editor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub snippet: Snippet,
+    /// Zero-based line in the snippet, `None` for errors outside of it
+    pub line: Option<usize>,
+    /// Byte range of the error within the line
+    pub columns: Range<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn at_line(snippet: Snippet, line: usize, message: String) -> Self {
+        Self {
+            snippet,
+            line: Some(line),
+            columns: 0..0,
+            message,
+        }
+    }
+
+    pub fn general(message: String) -> Self {
+        Self {
+            snippet: Snippet::FieldFunction,
+            line: None,
+            columns: 0..0,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.snippet, self.line) {
+            (Snippet::Prelude, Some(line)) => {
+                write!(f, "prelude line {}: {}", line + 1, self.message)
+            }
+            (Snippet::FieldFunction, Some(line)) => {
+                write!(f, "line {}: {}", line + 1, self.message)
+            }
+            (_, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Error returned for invalid user code, so that callers can get the
+/// diagnostics back out of an `anyhow::Error` with `downcast_ref`.
+#[derive(Debug, Clone)]
+pub struct FieldFunctionError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for FieldFunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FieldFunctionError {}
+
+impl From<Diagnostic> for FieldFunctionError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self {
+            diagnostics: vec![diagnostic],
+        }
+    }
+}
+
+/// Parses and validates `src`, where `snippets` are the byte ranges of the user code in it.
+pub fn validate(src: &str, snippets: &[(Snippet, Range<usize>)]) -> Result<(), FieldFunctionError> {
+    let module = naga::front::wgsl::parse_str(src).map_err(|e| {
+        let diagnostics = e
+            .labels()
+            .map(|(span, label)| {
+                let message = if label.is_empty() || label == e.message() {
+                    e.message().to_string()
+                } else {
+                    format!("{} ({})", e.message(), label)
+                };
+                locate(src, snippets, span, message)
+            })
+            .collect();
+        with_fallback(diagnostics, e.message().to_string())
+    })?;
+
+    let mut validator = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    );
+    validator.validate(&module).map_err(|e| {
+        // The error itself only names the invalid function, the details are in its sources
+        let mut message = e.to_string();
+        let mut source = e.source();
+        while let Some(s) = source {
+            message.push_str(": ");
+            message.push_str(&s.to_string());
+            source = s.source();
+        }
+        let diagnostics = e
+            .spans()
+            .map(|(span, _)| locate(src, snippets, *span, message.clone()))
+            .collect();
+        with_fallback(diagnostics, message)
+    })?;
+    Ok(())
+}
+
+/// Maps a span of the whole shader to a line of a snippet.
+fn locate(
+    src: &str,
+    snippets: &[(Snippet, Range<usize>)],
+    span: naga::Span,
+    message: String,
+) -> Diagnostic {
+    let range = match span.to_range() {
+        Some(range) => range,
+        None => return Diagnostic::general(message),
+    };
+    let (snippet, snippet_range) = match snippets
+        .iter()
+        .find(|(_, snippet)| range.start >= snippet.start && range.start <= snippet.end)
+    {
+        Some(snippet) => snippet,
+        None => return Diagnostic::general(message),
+    };
+    let before = &src[snippet_range.start..range.start];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let start = before.len() - line_start;
+    // Spans over multiple lines are only underlined in their first line
+    let first_line = src[range.clone()].split('\n').next().unwrap_or("");
+    Diagnostic {
+        snippet: *snippet,
+        line: Some(line),
+        columns: start..start + first_line.len(),
+        message,
+    }
+}
+
+fn with_fallback(mut diagnostics: Vec<Diagnostic>, message: String) -> FieldFunctionError {
+    // Spans outside of the body (e.g. of the whole function) are only
+    // reported if there is nothing more specific
+    if diagnostics.iter().any(|d| d.line.is_some()) {
+        diagnostics.retain(|d| d.line.is_some());
+    }
+    diagnostics.dedup();
+    if diagnostics.is_empty() {
+        diagnostics.push(Diagnostic::general(message));
+    }
+    FieldFunctionError { diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::FieldMode;
+    use crate::main_view::with_field_function;
+    use crate::parameters;
+
+    /// Diagnostics of `body` with `prelude` spliced into the main view shader.
+    fn diagnostics(prelude: &str, body: &str) -> Vec<Diagnostic> {
+        let parameters = parameters::parse(body).unwrap();
+        let (src, snippets) = with_field_function(
+            include_str!("main_view.wgsl"),
+            prelude,
+            &parameters,
+            body,
+            FieldMode::Velocity,
+        )
+        .unwrap();
+        validate(&src, &snippets).unwrap_err().diagnostics
+    }
+
+    #[test]
+    fn valid_body() {
+        let (src, snippets) = with_field_function(
+            include_str!("main_view.wgsl"),
+            "",
+            &[],
+            "return v;",
+            FieldMode::Velocity,
+        )
+        .unwrap();
+        assert!(validate(&src, &snippets).is_ok());
+    }
+
+    #[test]
+    fn error_in_body() {
+        let body = "let a = 1.0;\nlet b = 2.0;\nreturn v + undefined_name;";
+        let diagnostics = diagnostics("", body);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.snippet, Snippet::FieldFunction);
+        assert_eq!(diagnostic.line, Some(2));
+        assert_eq!(diagnostic.columns, 11..25);
+        assert!(diagnostic.message.contains("undefined_name"));
+    }
+
+    #[test]
+    fn error_in_prelude() {
+        let prelude = "fn helper() -> f32 {\n    return undefined_name;\n}";
+        let diagnostics = diagnostics(prelude, "return v;");
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.snippet, Snippet::Prelude);
+        assert_eq!(diagnostic.line, Some(1));
+        assert_eq!(diagnostic.columns, 11..25);
+    }
+
+    #[test]
+    fn parameters_keep_lines() {
+        // The declarations of the parameters are inserted on the line of the signature
+        let body = "// @param k: f32 = 1.0\n// @param c: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0)\nreturn k * v + c + undefined_name;";
+        let diagnostics = diagnostics("", body);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].snippet, Snippet::FieldFunction);
+        assert_eq!(diagnostics[0].line, Some(2));
+        assert_eq!(diagnostics[0].columns, 19..33);
+    }
+
+    #[test]
+    fn fallback_without_lines() {
+        let error = with_fallback(
+            vec![Diagnostic::general("outside".to_string())],
+            "message".to_string(),
+        );
+        assert_eq!(
+            error.diagnostics,
+            [Diagnostic::general("outside".to_string())]
+        );
+        let error = with_fallback(
+            vec![
+                Diagnostic::general("outside".to_string()),
+                Diagnostic::at_line(Snippet::FieldFunction, 0, "inside".to_string()),
+                Diagnostic::at_line(Snippet::FieldFunction, 0, "inside".to_string()),
+            ],
+            "message".to_string(),
+        );
+        assert_eq!(
+            error.diagnostics,
+            [Diagnostic::at_line(
+                Snippet::FieldFunction,
+                0,
+                "inside".to_string()
+            )]
+        );
+        let error = with_fallback(Vec::new(), "message".to_string());
+        assert_eq!(
+            error.diagnostics,
+            [Diagnostic::general("message".to_string())]
+        );
+    }
+}
@@ -1,6 +1,41 @@
+use anyhow::{Context, Result};
 use cgmath::Vector3;
+use std::path::Path;
 
-pub fn normalize_vertices(vertices: &mut [f32]) {
+/// Loads the vertex positions and triangle indices of the first model in an OBJ file.
+pub fn load_obj(path: &Path) -> Result<(Vec<f32>, Vec<u32>)> {
+    let (models, _) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            triangulate: true,
+            ..Default::default()
+        },
+    )
+    .with_context(|| format!("failed to load obj file {}", path.display()))?;
+    println!("Number of models: {}", models.len());
+    let model = models
+        .into_iter()
+        .next()
+        .context("obj file contains no models")?;
+    Ok((model.mesh.positions, model.mesh.indices))
+}
+
+/// The mapping `x' = scale * x + offset` that `normalize_vertices` applies to every coordinate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalization {
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Normalization {
+    pub const IDENTITY: Normalization = Normalization {
+        scale: 1.0,
+        offset: 0.0,
+    };
+}
+
+/// Maps the model into the cube from -1 to 1, keeping its aspect ratio.
+pub fn normalize_vertices(vertices: &mut [f32]) -> Normalization {
     let mut max = f32::MIN;
     let mut min = f32::MAX;
     for x in vertices.iter() {
@@ -14,6 +49,10 @@ pub fn normalize_vertices(vertices: &mut [f32]) {
     for x in vertices.iter_mut() {
         *x = (*x - min) / (max - min) * 2.0 - 1.0;
     }
+    Normalization {
+        scale: 2.0 / (max - min),
+        offset: -min / (max - min) * 2.0 - 1.0,
+    }
 }
 
 pub fn get_center(vertices: &[f32]) -> Vector3<f32> {
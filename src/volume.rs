@@ -0,0 +1,641 @@
+//! Fields sampled on regular 3D grids, loaded from files and uploaded as 3D textures
+//! that field functions sample with the `sample_*` helpers of `main_view.wgsl`.
+//!
+//! Two file formats are supported:
+//! - raw little-endian `f32` values after a small text header, see [`Volume::parse_raw`]
+//! - legacy VTK files with `STRUCTURED_POINTS` datasets, see [`Volume::parse_vtk`]
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use crate::vertices::Normalization;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// First line of the raw format.
+const RAW_MAGIC: &str = "linon-volume";
+
+#[derive(Debug, Clone)]
+pub struct Volume {
+    /// Number of grid points along x, y and z
+    pub dimensions: [u32; 3],
+    /// World position of the first grid point
+    pub origin: [f32; 3],
+    /// World distance between neighboring grid points
+    pub spacing: [f32; 3],
+    /// Number of values per grid point, 1 for scalar and 3 for vector fields
+    pub components: u32,
+    /// Values with x varying fastest, then y, then z
+    pub data: Vec<f32>,
+}
+
+impl Volume {
+    /// Loads a volume, files ending in `.vtk` as legacy VTK and all others in the raw format.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("reading {} failed", path.display()))?;
+        let result = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("vtk") => Self::parse_vtk(&bytes),
+            _ => Self::parse_raw(&bytes),
+        };
+        result.with_context(|| format!("parsing {} failed", path.display()))
+    }
+
+    /// Parses the raw format, a text header followed by the values as little-endian `f32`:
+    ///
+    /// ```text
+    /// linon-volume
+    /// dimensions 64 64 32
+    /// origin -1 -1 -1
+    /// spacing 0.03125 0.03125 0.0625
+    /// components 1
+    /// data
+    /// ```
+    ///
+    /// `origin` (default 0), `spacing` (default 1) and `components` (default 1) are optional,
+    /// lines starting with `#` are ignored. The values start directly after the `data` line.
+    pub fn parse_raw(bytes: &[u8]) -> Result<Self> {
+        let mut lines = HeaderLines { bytes, offset: 0 };
+        if lines.next().map(str::trim) != Some(RAW_MAGIC) {
+            bail!("missing `{}` header", RAW_MAGIC);
+        }
+        let mut dimensions = None;
+        let mut origin = [0.0; 3];
+        let mut spacing = [1.0; 3];
+        let mut components = 1;
+        loop {
+            let line = lines.next().context("missing `data` line")?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => {}
+                Some(word) if word.starts_with('#') => {}
+                Some("dimensions") => dimensions = Some(parse_array(words)?),
+                Some("origin") => origin = parse_array(words)?,
+                Some("spacing") => spacing = parse_array(words)?,
+                Some("components") => components = parse_array::<u32, 1>(words)?[0],
+                Some("data") => break,
+                Some(word) => bail!("unknown header field `{}`", word),
+            }
+        }
+        let dimensions = dimensions.context("missing `dimensions` line")?;
+        let data = lines
+            .rest()
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Self {
+            dimensions,
+            origin,
+            spacing,
+            components,
+            data,
+        }
+        .checked()
+    }
+
+    /// Parses the first `SCALARS` or `VECTORS` point data of a legacy VTK file
+    /// with a `STRUCTURED_POINTS` dataset, in ASCII or (big-endian) binary encoding.
+    pub fn parse_vtk(bytes: &[u8]) -> Result<Self> {
+        let mut lines = HeaderLines { bytes, offset: 0 };
+        let version = lines.next().unwrap_or_default();
+        if !version.starts_with("# vtk DataFile") {
+            bail!("missing `# vtk DataFile` header");
+        }
+        let _title = lines.next();
+        let binary = match lines.next().map(str::trim) {
+            Some("ASCII") => false,
+            Some("BINARY") => true,
+            _ => bail!("expected `ASCII` or `BINARY` encoding"),
+        };
+        let mut dimensions = None;
+        let mut origin = [0.0; 3];
+        let mut spacing = [1.0; 3];
+        let (data_type, components) = loop {
+            let line = lines
+                .next()
+                .context("missing SCALARS or VECTORS point data")?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => {}
+                Some("DATASET") if words.next() != Some("STRUCTURED_POINTS") => {
+                    bail!("only STRUCTURED_POINTS datasets are supported");
+                }
+                Some("DATASET") => {}
+                Some("DIMENSIONS") => dimensions = Some(parse_array(words)?),
+                Some("ORIGIN") => origin = parse_array(words)?,
+                Some("SPACING") | Some("ASPECT_RATIO") => spacing = parse_array(words)?,
+                Some("POINT_DATA") => {}
+                Some("SCALARS") => {
+                    let _name = words.next();
+                    let data_type = words.next().context("SCALARS without data type")?;
+                    let components = match words.next() {
+                        Some(components) => components
+                            .parse()
+                            .with_context(|| format!("invalid component count `{}`", components))?,
+                        None => 1,
+                    };
+                    // The values follow the lookup table declaration
+                    let line = lines.next().unwrap_or_default();
+                    if !line.starts_with("LOOKUP_TABLE") {
+                        bail!("expected LOOKUP_TABLE after SCALARS");
+                    }
+                    break (data_type.to_string(), components);
+                }
+                Some("VECTORS") => {
+                    let _name = words.next();
+                    let data_type = words.next().context("VECTORS without data type")?;
+                    break (data_type.to_string(), 3);
+                }
+                Some(word) => bail!("unsupported VTK keyword `{}`", word),
+            }
+        };
+        let dimensions: [u32; 3] = dimensions.context("missing DIMENSIONS")?;
+        let count = dimensions.iter().map(|&d| d as usize).product::<usize>() * components as usize;
+        let data = if binary {
+            read_binary(lines.rest(), &data_type, count)?
+        } else {
+            std::str::from_utf8(lines.rest())
+                .context("ASCII data is not valid UTF-8")?
+                .split_whitespace()
+                .take(count)
+                .map(|value| {
+                    value
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid value `{}`", value))
+                })
+                .collect::<Result<_>>()?
+        };
+        Self {
+            dimensions,
+            origin,
+            spacing,
+            components,
+            data,
+        }
+        .checked()
+    }
+
+    /// Smallest and largest value.
+    pub fn range(&self) -> (f32, f32) {
+        self.data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            })
+    }
+
+    /// World position of the last grid point.
+    pub fn end(&self) -> [f32; 3] {
+        let mut end = self.origin;
+        for ((end, spacing), dimension) in end.iter_mut().zip(self.spacing).zip(self.dimensions) {
+            *end += spacing * (dimension - 1) as f32;
+        }
+        end
+    }
+
+    fn checked(self) -> Result<Self> {
+        if self.dimensions.contains(&0) {
+            bail!("dimensions must not be 0");
+        }
+        if self.spacing.contains(&0.0) {
+            bail!("spacing must not be 0");
+        }
+        if !(1..=4).contains(&self.components) {
+            bail!(
+                "{} components per grid point are not supported",
+                self.components
+            );
+        }
+        let count = self
+            .dimensions
+            .iter()
+            .map(|&d| d as usize)
+            .product::<usize>()
+            * self.components as usize;
+        if self.data.len() < count {
+            bail!("expected {} values, found {}", count, self.data.len());
+        }
+        Ok(self)
+    }
+}
+
+/// Splits the header of a file into lines while keeping track of where the data starts.
+struct HeaderLines<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> HeaderLines<'a> {
+    /// The next line, `None` at the end or if it is not valid UTF-8.
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = &self.bytes[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        self.offset += (end + 1).min(rest.len());
+        std::str::from_utf8(&rest[..end])
+            .ok()
+            .map(|line| line.trim_end_matches('\r'))
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+}
+
+fn parse_array<'a, T: std::str::FromStr, const N: usize>(
+    words: impl Iterator<Item = &'a str>,
+) -> Result<[T; N]> {
+    let values = words
+        .map(|word| {
+            word.parse()
+                .map_err(|_| anyhow::anyhow!("invalid number `{}`", word))
+        })
+        .collect::<Result<Vec<T>>>()?;
+    let len = values.len();
+    values
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("expected {} numbers, found {}", N, len))
+}
+
+/// Reads `count` big-endian values of a VTK data type.
+fn read_binary(bytes: &[u8], data_type: &str, count: usize) -> Result<Vec<f32>> {
+    fn read<const N: usize>(bytes: &[u8], count: usize, f: impl Fn([u8; N]) -> f32) -> Vec<f32> {
+        bytes
+            .chunks_exact(N)
+            .take(count)
+            .map(|chunk| f(chunk.try_into().unwrap()))
+            .collect()
+    }
+    Ok(match data_type {
+        "unsigned_char" => read(bytes, count, |b: [u8; 1]| b[0] as f32),
+        "char" => read(bytes, count, |b: [u8; 1]| b[0] as i8 as f32),
+        "unsigned_short" => read(bytes, count, |b| u16::from_be_bytes(b) as f32),
+        "short" => read(bytes, count, |b| i16::from_be_bytes(b) as f32),
+        "unsigned_int" => read(bytes, count, |b| u32::from_be_bytes(b) as f32),
+        "int" => read(bytes, count, |b| i32::from_be_bytes(b) as f32),
+        "unsigned_long" => read(bytes, count, |b| u64::from_be_bytes(b) as f32),
+        "long" => read(bytes, count, |b| i64::from_be_bytes(b) as f32),
+        "float" => read(bytes, count, f32::from_be_bytes),
+        "double" => read(bytes, count, |b| f64::from_be_bytes(b) as f32),
+        _ => bail!("unsupported data type `{}`", data_type),
+    })
+}
+
+/// Behavior of `sample_vector_field` outside of the grid.
+/// The values must match the `OUTSIDE_*` constants in `main_view.wgsl`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Outside {
+    #[serde(rename = "zero")]
+    Zero = 0,
+    /// The value at the closest point of the grid
+    #[serde(rename = "clamp")]
+    Clamp = 1,
+    /// The fallback passed to `sample_vector_field`, usually `v`
+    #[serde(rename = "fallback")]
+    Fallback = 2,
+}
+
+impl Outside {
+    pub const ALL: [Outside; 3] = [Self::Zero, Self::Clamp, Self::Fallback];
+}
+
+impl Display for Outside {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::Zero => "Zero",
+            Self::Clamp => "Clamp to grid",
+            Self::Fallback => "Fall back to v",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for Outside {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Self::Zero),
+            "clamp" => Ok(Self::Clamp),
+            "fallback" => Ok(Self::Fallback),
+            _ => Err("possible values: zero, clamp, fallback".to_string()),
+        }
+    }
+}
+
+/// Coordinate system of the origin and spacing of a vector field.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum VolumeSpace {
+    /// The normalized scene the rays are cast in
+    #[serde(rename = "scene")]
+    Scene,
+    /// The loaded model before `normalize_vertices`, vectors are scaled along with the grid
+    #[serde(rename = "model")]
+    Model,
+}
+
+impl VolumeSpace {
+    pub const ALL: [VolumeSpace; 2] = [Self::Scene, Self::Model];
+}
+
+impl Display for VolumeSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::Scene => "Scene",
+            Self::Model => "Model",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for VolumeSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scene" => Ok(Self::Scene),
+            "model" => Ok(Self::Model),
+            _ => Err("possible values: scene, model".to_string()),
+        }
+    }
+}
+
+/// How a vector field is placed in the scene and sampled outside of its grid.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct VectorFieldOptions {
+    pub space: VolumeSpace,
+    pub outside: Outside,
+}
+
+impl VectorFieldOptions {
+    /// The mapping into the scene, given the normalization of the loaded model.
+    pub fn mapping(&self, model: Normalization) -> Normalization {
+        match self.space {
+            VolumeSpace::Scene => Normalization::IDENTITY,
+            VolumeSpace::Model => model,
+        }
+    }
+}
+
+impl Default for VectorFieldOptions {
+    fn default() -> Self {
+        Self {
+            space: VolumeSpace::Scene,
+            outside: Outside::Zero,
+        }
+    }
+}
+
+/// Placement of a volume in the scene as passed to the shader, see `Volume` in `main_view.wgsl`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VolumeUniform {
+    origin: [f32; 4],
+    spacing: [f32; 4],
+    scale: f32,
+    outside: i32,
+    _padding: [f32; 2],
+}
+
+/// A volume uploaded as 3D texture. The shader interpolates with `textureLoad`,
+/// as 32-bit float textures are not filterable on all devices.
+pub struct VolumeTexture {
+    _texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    origin: [f32; 3],
+    spacing: [f32; 3],
+}
+
+impl VolumeTexture {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, volume: &Volume) -> Result<Self> {
+        let max_dimension = device.limits().max_texture_dimension_3d;
+        if volume.dimensions.iter().any(|&d| d > max_dimension) {
+            bail!(
+                "the grid exceeds the maximum size of {} points per axis",
+                max_dimension
+            );
+        }
+        let [width, height, depth] = volume.dimensions;
+        let count = width as usize * height as usize * depth as usize;
+        let (texture, components) = create_texture(device, volume.dimensions, volume.components);
+        let mut data = Vec::with_capacity(count * components);
+        for point in volume
+            .data
+            .chunks_exact(volume.components as usize)
+            .take(count)
+        {
+            data.extend_from_slice(point);
+            data.extend(std::iter::repeat_n(0.0, components - point.len()));
+        }
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&data),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4 * components as u32),
+                rows_per_image: Some(height),
+            },
+            texture.size(),
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(Self {
+            _texture: texture,
+            view,
+            origin: volume.origin,
+            spacing: volume.spacing,
+        })
+    }
+
+    /// A single zero value, bound while no volume is loaded.
+    pub fn empty(device: &wgpu::Device, components: u32) -> Self {
+        // Textures are initialized with zeros
+        let (texture, _) = create_texture(device, [1, 1, 1], components);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            _texture: texture,
+            view,
+            origin: [0.0; 3],
+            spacing: [1.0; 3],
+        }
+    }
+
+    /// The placement of the grid after mapping its coordinates and values with `mapping`.
+    pub fn uniform(&self, mapping: Normalization, outside: Outside) -> VolumeUniform {
+        let [x, y, z] = self.origin.map(|x| mapping.scale * x + mapping.offset);
+        let [dx, dy, dz] = self.spacing.map(|dx| mapping.scale * dx);
+        VolumeUniform {
+            origin: [x, y, z, 0.0],
+            spacing: [dx, dy, dz, 0.0],
+            scale: mapping.scale,
+            outside: outside as i32,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Creates a float texture for the given number of components,
+/// also returns the number of components per texel.
+fn create_texture(
+    device: &wgpu::Device,
+    dimensions: [u32; 3],
+    components: u32,
+) -> (wgpu::Texture, usize) {
+    // Three-component textures do not exist, vectors are padded to four components
+    let (format, texel_components) = match components {
+        1 => (wgpu::TextureFormat::R32Float, 1),
+        2 => (wgpu::TextureFormat::Rg32Float, 2),
+        _ => (wgpu::TextureFormat::Rgba32Float, 4),
+    };
+    let [width, height, depth] = dimensions;
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("volume_texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: depth,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format,
+        view_formats: &[format],
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    (texture, texel_components)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A raw file with a 2 × 1 × 2 grid and the given header lines.
+    fn raw(header: &str, values: &[f32]) -> Vec<u8> {
+        let mut bytes = format!("{}\n{}\ndata\n", RAW_MAGIC, header).into_bytes();
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// A VTK file with a 2 × 1 × 2 grid, `data` is appended after the header.
+    fn vtk(encoding: &str, dimensions: &str, point_data: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = format!(
+            "# vtk DataFile Version 3.0\ntest\n{}\nDATASET STRUCTURED_POINTS\n{}\nORIGIN 1 2 3\nSPACING 0.5 0.5 0.25\nPOINT_DATA 4\n{}\n",
+            encoding, dimensions, point_data
+        )
+        .into_bytes();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn parse_raw() {
+        let bytes = raw(
+            "# comment\ndimensions 2 1 2\norigin -1 -1 -1\nspacing 0.5 1 2",
+            &[1.0, 2.0, 3.0, 4.0],
+        );
+        let volume = Volume::parse_raw(&bytes).unwrap();
+        assert_eq!(volume.dimensions, [2, 1, 2]);
+        assert_eq!(volume.origin, [-1.0; 3]);
+        assert_eq!(volume.spacing, [0.5, 1.0, 2.0]);
+        assert_eq!(volume.components, 1);
+        assert_eq!(volume.data, [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(volume.end(), [-0.5, -1.0, 1.0]);
+        assert_eq!(volume.range(), (1.0, 4.0));
+
+        let bytes = raw(
+            "dimensions 1 1 2\ncomponents 3",
+            &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0],
+        );
+        let volume = Volume::parse_raw(&bytes).unwrap();
+        assert_eq!(volume.origin, [0.0; 3]);
+        assert_eq!(volume.spacing, [1.0; 3]);
+        assert_eq!(volume.components, 3);
+    }
+
+    #[test]
+    fn parse_raw_errors() {
+        let message = |bytes: &[u8]| format!("{:#}", Volume::parse_raw(bytes).unwrap_err());
+        assert!(message(b"volume\ndimensions 1 1 1\ndata\n").contains("header"));
+        let missing_dimensions = raw("origin 0 0 0", &[1.0]);
+        assert!(message(&missing_dimensions).contains("missing `dimensions` line"));
+        assert!(message(b"linon-volume\ndimensions 1 1 1\n").contains("missing `data` line"));
+        let truncated = raw("dimensions 2 1 2", &[1.0, 2.0, 3.0]);
+        assert!(message(&truncated).contains("expected 4 values, found 3"));
+        let mut partial_value = raw("dimensions 2 1 2", &[1.0, 2.0, 3.0]);
+        partial_value.extend_from_slice(&[0, 0]);
+        assert!(message(&partial_value).contains("expected 4 values, found 3"));
+        assert!(message(&raw("dimensions 2 1", &[])).contains("expected 3 numbers, found 2"));
+        assert!(message(&raw("dimensions 0 1 1", &[])).contains("must not be 0"));
+    }
+
+    #[test]
+    fn parse_vtk_ascii() {
+        let bytes = vtk(
+            "ASCII",
+            "DIMENSIONS 2 1 2",
+            "SCALARS density float\nLOOKUP_TABLE default",
+            b"1 2\n3 4.5\n",
+        );
+        let volume = Volume::parse_vtk(&bytes).unwrap();
+        assert_eq!(volume.dimensions, [2, 1, 2]);
+        assert_eq!(volume.origin, [1.0, 2.0, 3.0]);
+        assert_eq!(volume.spacing, [0.5, 0.5, 0.25]);
+        assert_eq!(volume.components, 1);
+        assert_eq!(volume.data, [1.0, 2.0, 3.0, 4.5]);
+
+        let values = "0 0 1  0 1 0\n1 0 0  0 0 -1\n".repeat(2);
+        let bytes = vtk(
+            "ASCII",
+            "DIMENSIONS 2 1 2",
+            "VECTORS v float",
+            values.as_bytes(),
+        );
+        let volume = Volume::parse_vtk(&bytes).unwrap();
+        assert_eq!(volume.components, 3);
+        assert_eq!(volume.data.len(), 12);
+        assert_eq!(volume.data[9..], [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn parse_vtk_binary() {
+        let data: Vec<u8> = [1.0f32, -2.0, 3.0, 4.0]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let point_data = "SCALARS density float 1\nLOOKUP_TABLE default";
+        let volume = Volume::parse_vtk(&vtk("BINARY", "DIMENSIONS 2 1 2", point_data, &data));
+        assert_eq!(volume.unwrap().data, [1.0, -2.0, 3.0, 4.0]);
+
+        let data: Vec<u8> = [1i16, -2, 300, 4]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let point_data = "SCALARS density short\nLOOKUP_TABLE default";
+        let volume = Volume::parse_vtk(&vtk("BINARY", "DIMENSIONS 2 1 2", point_data, &data));
+        assert_eq!(volume.unwrap().data, [1.0, -2.0, 300.0, 4.0]);
+    }
+
+    #[test]
+    fn parse_vtk_errors() {
+        let message = |bytes: &[u8]| format!("{:#}", Volume::parse_vtk(bytes).unwrap_err());
+        let point_data = "SCALARS density float\nLOOKUP_TABLE default";
+        let missing_dimensions = vtk("ASCII", "", point_data, b"1 2 3 4");
+        assert!(message(&missing_dimensions).contains("missing DIMENSIONS"));
+        let truncated = vtk("ASCII", "DIMENSIONS 2 1 2", point_data, b"1 2 3");
+        assert!(message(&truncated).contains("expected 4 values, found 3"));
+        let truncated = vtk("BINARY", "DIMENSIONS 2 1 2", point_data, &[0; 15]);
+        assert!(message(&truncated).contains("expected 4 values, found 3"));
+        let no_lookup_table = vtk("ASCII", "DIMENSIONS 2 1 2", "SCALARS density float", b"");
+        assert!(message(&no_lookup_table).contains("LOOKUP_TABLE"));
+        let invalid = vtk("ASCII", "DIMENSIONS 2 1 2", point_data, b"1 2 x 4");
+        assert!(message(&invalid).contains("invalid value `x`"));
+        assert!(message(b"# vtk DataFile Version 3.0\ntest\nXML\n").contains("encoding"));
+    }
+}